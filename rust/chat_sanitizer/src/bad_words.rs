@@ -1,35 +1,139 @@
 use crate::{normalized_message, MessageChecker, Severity};
 
-use std::marker::PhantomData;
+use itertools::Itertools;
+use std::{fs, io, marker::PhantomData, path::Path};
 
-struct BadWordsChecker<T> {
-    blacklist: Vec<String>,
-    whitelist: Vec<String>,
+/// Folded words of a list entry
+type Phrase = Vec<String>;
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '@' || c == '$'
+}
+
+fn fold_leetspeak(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        '8' => 'b',
+        c => c,
+    }
+}
+
+/// Brings the look-alike spellings of a word to the same form
+fn fold_word(word: &str) -> String {
+    let unleeted: String = word.chars().map(fold_leetspeak).collect();
+    let mut folded = String::with_capacity(word.len());
+    // "poooop" is the same word as "poop", double letters are kept to tell "poop" from "pop"
+    for (_, run) in &normalized_message(&unleeted)
+        .to_lowercase()
+        .chars()
+        .group_by(|c| *c)
+    {
+        folded.extend(run.take(2));
+    }
+    folded
+}
+
+fn phrase(text: &str) -> Phrase {
+    text.split(|c| !is_word_char(c))
+        .filter(|word| !word.is_empty())
+        .map(fold_word)
+        .collect()
+}
+
+fn phrases<S: AsRef<str>>(list: &[S]) -> Vec<Phrase> {
+    list.iter()
+        .map(|s| phrase(s.as_ref()))
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn phrase_positions<'a>(
+    words: &'a [String],
+    phrase: &'a [String],
+) -> impl Iterator<Item = usize> + 'a {
+    (0..words.len().saturating_sub(phrase.len() - 1))
+        .filter(move |i| &words[*i..*i + phrase.len()] == phrase)
+}
+
+/// Reads a word list, one entry per line.
+/// Entries starting with `+` are allowed phrases, lines starting with `#` are comments
+pub fn parse_word_list(text: &str) -> (Vec<String>, Vec<String>) {
+    let mut blacklist = vec![];
+    let mut whitelist = vec![];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else if let Some(entry) = line.strip_prefix('+') {
+            whitelist.push(entry.trim().to_string());
+        } else {
+            blacklist.push(line.to_string());
+        }
+    }
+    (blacklist, whitelist)
+}
+
+pub struct BadWordsChecker<T> {
+    blacklist: Vec<Phrase>,
+    whitelist: Vec<Phrase>,
     player_id_type: PhantomData<T>,
 }
 
 impl<T> BadWordsChecker<T> {
     pub fn new(blacklist: &[&str], whitelist: &[&str]) -> Self {
         Self {
-            blacklist: blacklist.iter().map(|s| normalized_message(*s)).collect(),
-            whitelist: whitelist.iter().map(|s| normalized_message(*s)).collect(),
+            blacklist: phrases(blacklist),
+            whitelist: phrases(whitelist),
             player_id_type: PhantomData,
         }
     }
+
+    /// Adds more entries to the lists
+    pub fn extend<S: AsRef<str>>(&mut self, blacklist: &[S], whitelist: &[S]) {
+        self.blacklist.extend(phrases(blacklist));
+        self.whitelist.extend(phrases(whitelist));
+    }
+
+    /// Loads the word lists of the languages from the `<language>.txt` files in the directory
+    pub fn load(directory: &Path, languages: &[&str]) -> io::Result<Self> {
+        let mut blacklist = vec![];
+        let mut whitelist = vec![];
+        for language in languages {
+            let text = fs::read_to_string(directory.join(format!("{}.txt", language)))?;
+            let (bad_words, good_words) = parse_word_list(&text);
+            blacklist.extend(bad_words);
+            whitelist.extend(good_words);
+        }
+
+        Ok(Self {
+            blacklist: phrases(&blacklist),
+            whitelist: phrases(&whitelist),
+            player_id_type: PhantomData,
+        })
+    }
 }
 
 impl<T> MessageChecker<T> for BadWordsChecker<T> {
-    fn check(&self, _player_id: T, message: &str) -> Severity {
-        let msg = normalized_message(message);
-
-        // silly implementation, allows bad messages with a single good word
-        for bad_word in &self.blacklist {
-            if msg.contains(bad_word) {
-                if !self
-                    .whitelist
-                    .iter()
-                    .any(|good_word| msg.contains(good_word))
-                {
+    fn check(&mut self, _player_id: T, message: &str) -> Severity {
+        let words = phrase(message);
+
+        // only the words covered by the allowed phrases are excused
+        let mut is_allowed = vec![false; words.len()];
+        for good_phrase in &self.whitelist {
+            for i in phrase_positions(&words, good_phrase) {
+                for allowed in &mut is_allowed[i..i + good_phrase.len()] {
+                    *allowed = true;
+                }
+            }
+        }
+
+        for bad_phrase in &self.blacklist {
+            for i in phrase_positions(&words, bad_phrase) {
+                if !is_allowed[i..i + bad_phrase.len()].iter().all(|a| *a) {
                     return Severity::Warn;
                 }
             }
@@ -44,15 +148,36 @@ mod tests {
     use super::*;
     #[test]
     fn it_works() {
-        let checker = BadWordsChecker::new(&["fsck", "poop"], &["fsck -y"]);
+        let mut checker = BadWordsChecker::new(&["fsck", "poop"], &["fsck -y"]);
         assert_eq!(checker.check(0, "group hug"), Severity::Pass);
-        assert_eq!(checker.check(0, "fpoopf"), Severity::Warn);
+        assert_eq!(checker.check(0, "fpoopf"), Severity::Pass);
         assert_eq!(checker.check(0, "PooP"), Severity::Warn);
+        assert_eq!(checker.check(0, "run fsck -y now"), Severity::Pass);
+        assert_eq!(checker.check(0, "poop 'fsck -y' poop"), Severity::Warn);
+        assert_eq!(checker.check(0, "P00P"), Severity::Warn);
+        assert_eq!(checker.check(0, "p0op!"), Severity::Warn);
+        assert_eq!(checker.check(0, "pooooop"), Severity::Warn);
+        assert_eq!(checker.check(0, "pop music"), Severity::Pass);
+        // Cyrillic look-alike letters
+        assert_eq!(checker.check(0, "рoор"), Severity::Warn);
+    }
+
+    #[test]
+    fn word_lists() {
+        let (blacklist, whitelist) = parse_word_list("# test\npoop\n\n+ poop deck \n");
+        assert_eq!(blacklist, vec!["poop"]);
+        assert_eq!(whitelist, vec!["poop deck"]);
 
-        // this one fails
-        //assert_eq!(checker.check(0, "poop 'fsck -y' poop"), Severity::Warn);
+        let directory = std::env::temp_dir().join(format!("bad_words_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("en.txt"), "poop\n+poop deck\n").unwrap();
+        fs::write(directory.join("de.txt"), "kacke\n").unwrap();
 
-        // ideally this one shouldn't fail, need a better confusables check
-        // assert_eq!(checker.check(0, "P00P"), Severity::Warn);
+        let result = BadWordsChecker::load(&directory, &["en", "de"]);
+        fs::remove_dir_all(&directory).unwrap();
+        let mut checker = result.unwrap();
+        assert_eq!(checker.check(0, "KACKE"), Severity::Warn);
+        assert_eq!(checker.check(0, "clean the poop deck"), Severity::Pass);
+        assert_eq!(checker.check(0, "poop on the deck"), Severity::Warn);
     }
 }
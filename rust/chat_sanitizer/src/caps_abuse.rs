@@ -1,9 +1,128 @@
 use crate::{MessageChecker, Severity};
 
-struct CapsAbuseChecker {}
+use itertools::Itertools;
+use std::marker::PhantomData;
 
-impl<T> MessageChecker<T> for CapsAbuseChecker {
-    fn check(&self, player_id: T, message: &str) -> Severity {
-        Severity::Pass
+pub struct CapsAbuseChecker<T> {
+    /// Maximum allowed ratio of the uppercase letters
+    threshold: f32,
+    /// Messages with fewer cased letters are never checked
+    min_letters: usize,
+    acronyms: Vec<String>,
+    player_id_type: PhantomData<T>,
+}
+
+impl<T> CapsAbuseChecker<T> {
+    pub fn new(threshold: f32, min_letters: usize, acronyms: &[&str]) -> Self {
+        Self {
+            threshold,
+            min_letters,
+            acronyms: acronyms.iter().map(|s| s.to_uppercase()).collect(),
+            player_id_type: PhantomData,
+        }
+    }
+
+    fn is_acronym(&self, word: &str) -> bool {
+        self.acronyms.iter().any(|acronym| acronym == word)
+    }
+
+    fn is_abusive(&self, message: &str) -> bool {
+        let mut letters = 0;
+        let mut uppercase = 0;
+
+        for (is_word, group) in &message.chars().group_by(|c| c.is_alphanumeric()) {
+            let word: String = group.collect();
+            if !is_word || self.is_acronym(&word) {
+                continue;
+            }
+            // letters of the scripts without case don't count
+            for c in word
+                .chars()
+                .filter(|c| c.is_uppercase() || c.is_lowercase())
+            {
+                letters += 1;
+                if c.is_uppercase() {
+                    uppercase += 1;
+                }
+            }
+        }
+
+        letters >= self.min_letters && uppercase as f32 / letters as f32 > self.threshold
+    }
+}
+
+impl<T> MessageChecker<T> for CapsAbuseChecker<T> {
+    fn check(&mut self, _player_id: T, message: &str) -> Severity {
+        if self.is_abusive(message) {
+            Severity::Warn
+        } else {
+            Severity::Pass
+        }
+    }
+
+    /// Converts the message to the sentence case keeping the acronyms
+    fn fix(&self, _player_id: T, message: &str) -> Option<String> {
+        if !self.is_abusive(message) {
+            return None;
+        }
+
+        let mut result = String::with_capacity(message.len());
+        let mut is_sentence_start = true;
+
+        for (is_word, group) in &message.chars().group_by(|c| c.is_alphanumeric()) {
+            let part: String = group.collect();
+            if !is_word {
+                is_sentence_start |= part.contains(&['.', '!', '?'][..]);
+                result.push_str(&part);
+            } else {
+                if self.is_acronym(&part) {
+                    result.push_str(&part);
+                } else {
+                    let mut chars = part.chars();
+                    if let Some(first) = chars.next() {
+                        if is_sentence_start {
+                            result.extend(first.to_uppercase());
+                        } else {
+                            result.extend(first.to_lowercase());
+                        }
+                        result.extend(chars.flat_map(|c| c.to_lowercase()));
+                    }
+                }
+                is_sentence_start = false;
+            }
+        }
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let mut checker = CapsAbuseChecker::new(0.7, 6, &["gg", "afk"]);
+        assert_eq!(checker.check(0, "Hello world!"), Severity::Pass);
+        assert_eq!(checker.check(0, "HELLO WORLD!"), Severity::Warn);
+        assert_eq!(checker.check(0, "WHAT?"), Severity::Pass);
+        assert_eq!(checker.check(0, "GG AFK GG nice game"), Severity::Pass);
+        assert_eq!(checker.check(0, "ПРИВЕТ ВСЕМ"), Severity::Warn);
+        assert_eq!(checker.check(0, "Привет всем"), Severity::Pass);
+        assert_eq!(checker.check(0, "大家好 HI 大家好"), Severity::Pass);
+    }
+
+    #[test]
+    fn fix() {
+        let checker = CapsAbuseChecker::new(0.7, 6, &["gg"]);
+        assert_eq!(checker.fix(0, "Hello world!"), None);
+        assert_eq!(
+            checker.fix(0, "HELLO EVERYONE. HOW ARE YOU? GG"),
+            Some("Hello everyone. How are you? GG".to_string())
+        );
+        assert_eq!(
+            checker.fix(0, "ЭТО БЫЛО ВЕЛИКОЛЕПНО!"),
+            Some("Это было великолепно!".to_string())
+        );
     }
 }
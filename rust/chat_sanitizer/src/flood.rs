@@ -1,9 +1,182 @@
-use crate::{MessageChecker, Severity};
+use crate::{Clock, MessageChecker, Severity, SystemClock};
 
-struct FloodChecker {}
+use serde_derive::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant},
+};
 
-impl<T> MessageChecker<T> for FloodChecker {
-    fn check(&self, player_id: T, message: &str) -> Severity {
-        Severity::Pass
+/// Amount of chat within the window that triggers a severity level
+#[derive(Deserialize)]
+pub struct FloodThreshold {
+    pub messages: usize,
+    pub characters: usize,
+}
+
+impl FloodThreshold {
+    fn is_reached(&self, messages: usize, characters: usize) -> bool {
+        messages >= self.messages || characters >= self.characters
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FloodConfig {
+    /// Length of the window in seconds in the config files
+    #[serde(deserialize_with = "crate::seconds")]
+    pub window: Duration,
+    pub warn: FloodThreshold,
+    pub silence: FloodThreshold,
+    pub ban: FloodThreshold,
+}
+
+impl Default for FloodConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            warn: FloodThreshold {
+                messages: 5,
+                characters: 400,
+            },
+            silence: FloodThreshold {
+                messages: 8,
+                characters: 600,
+            },
+            ban: FloodThreshold {
+                messages: 12,
+                characters: 1000,
+            },
+        }
+    }
+}
+
+pub struct FloodChecker<T, C = SystemClock> {
+    config: FloodConfig,
+    clock: C,
+    /// Times and lengths of the recent messages of every player
+    history: HashMap<T, VecDeque<(Instant, usize)>>,
+}
+
+impl<T: Hash + Eq> FloodChecker<T> {
+    pub fn new(config: FloodConfig) -> Self {
+        Self::with_clock(config, SystemClock)
+    }
+}
+
+impl<T: Hash + Eq, C: Clock> FloodChecker<T, C> {
+    pub fn with_clock(config: FloodConfig, clock: C) -> Self {
+        Self {
+            config,
+            clock,
+            history: HashMap::new(),
+        }
+    }
+
+    /// Drops the history of the players who haven't written anything within the window
+    pub fn expire(&mut self) {
+        let now = self.clock.now();
+        let window = self.config.window;
+        self.history.retain(|_, messages| match messages.back() {
+            Some((time, _)) => now.duration_since(*time) < window,
+            None => false,
+        });
+    }
+}
+
+impl<T: Hash + Eq, C: Clock> MessageChecker<T> for FloodChecker<T, C> {
+    fn check(&mut self, player_id: T, message: &str) -> Severity {
+        let now = self.clock.now();
+        let window = self.config.window;
+        let messages = self.history.entry(player_id).or_default();
+
+        while let Some((time, _)) = messages.front() {
+            if now.duration_since(*time) >= window {
+                messages.pop_front();
+            } else {
+                break;
+            }
+        }
+        messages.push_back((now, message.chars().count()));
+
+        let count = messages.len();
+        let characters = messages.iter().map(|(_, length)| length).sum();
+
+        if self.config.ban.is_reached(count, characters) {
+            Severity::Ban
+        } else if self.config.silence.is_reached(count, characters) {
+            Severity::Silence
+        } else if self.config.warn.is_reached(count, characters) {
+            Severity::Warn
+        } else {
+            Severity::Pass
+        }
+    }
+
+    fn expire(&mut self) {
+        FloodChecker::expire(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    fn config() -> FloodConfig {
+        FloodConfig {
+            window: Duration::from_secs(10),
+            warn: FloodThreshold {
+                messages: 3,
+                characters: 100,
+            },
+            silence: FloodThreshold {
+                messages: 4,
+                characters: 200,
+            },
+            ban: FloodThreshold {
+                messages: 5,
+                characters: 300,
+            },
+        }
+    }
+
+    #[test]
+    fn it_works() {
+        let clock = TestClock::new();
+        let mut checker = FloodChecker::with_clock(config(), clock.clone());
+        let second = Duration::from_secs(1);
+
+        assert_eq!(checker.check(0, "hi"), Severity::Pass);
+        clock.advance(second);
+        assert_eq!(checker.check(0, "hi"), Severity::Pass);
+        assert_eq!(checker.check(1, "hi"), Severity::Pass);
+        clock.advance(second);
+        assert_eq!(checker.check(0, "hi"), Severity::Warn);
+        assert_eq!(checker.check(0, "hi"), Severity::Silence);
+        assert_eq!(checker.check(0, "hi"), Severity::Ban);
+
+        // the first messages leave the window
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(checker.check(0, "hi"), Severity::Silence);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(checker.check(0, "hi"), Severity::Pass);
+
+        let long_message = "a".repeat(150);
+        assert_eq!(checker.check(1, &long_message), Severity::Warn);
+        assert_eq!(checker.check(1, &long_message), Severity::Ban);
+    }
+
+    #[test]
+    fn expire() {
+        let clock = TestClock::new();
+        let mut checker = FloodChecker::with_clock(config(), clock.clone());
+        checker.check(0, "hi");
+        clock.advance(Duration::from_secs(5));
+        checker.check(1, "hi");
+        clock.advance(Duration::from_secs(5));
+        checker.expire();
+        assert_eq!(checker.history.len(), 1);
+        assert!(checker.history.contains_key(&1));
     }
 }
@@ -3,7 +3,7 @@ use crate::{MessageChecker, Severity};
 use itertools::Itertools;
 use std::marker::PhantomData;
 
-struct LetterRepeatChecker<T> {
+pub struct LetterRepeatChecker<T> {
     threshold: usize,
     player_id_type: PhantomData<T>,
 }
@@ -18,7 +18,7 @@ impl<T> LetterRepeatChecker<T> {
 }
 
 impl<T> MessageChecker<T> for LetterRepeatChecker<T> {
-    fn check(&self, _player_id: T, message: &str) -> Severity {
+    fn check(&mut self, _player_id: T, message: &str) -> Severity {
         for (_key, group) in &message.chars().into_iter().group_by(|c| *c) {
             if group.count() >= self.threshold {
                 return Severity::Warn;
@@ -34,7 +34,7 @@ mod tests {
     use super::*;
     #[test]
     fn it_works() {
-        let checker = LetterRepeatChecker::new(3);
+        let mut checker = LetterRepeatChecker::new(3);
         assert_eq!(checker.check(0, "Hello world!"), Severity::Pass);
         assert_eq!(checker.check(0, "ooops"), Severity::Warn);
         assert_eq!(
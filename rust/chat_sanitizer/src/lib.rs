@@ -1,21 +1,74 @@
 pub mod bad_words;
+pub mod caps_abuse;
+pub mod flood;
 pub mod letter_repeat;
+pub mod part_repeat;
+pub mod sanitizer;
+pub mod url;
 
+use serde::{Deserialize, Deserializer};
+use std::time::{Duration, Instant};
 use unicode_skeleton::UnicodeSkeleton;
 
-#[derive(PartialEq, Debug)]
-enum Severity {
+pub use sanitizer::ChatSanitizer;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Severity {
     Pass,
     Warn,
     Silence,
     Ban,
 }
 
-trait MessageChecker<T> {
-    fn check(&self, player_id: T, message: &str) -> Severity;
+pub trait MessageChecker<T> {
+    fn check(&mut self, player_id: T, message: &str) -> Severity;
     fn fix(&self, player_id: T, message: &str) -> Option<String> {
         None
     }
+    /// Drops the state kept for the players who haven't written anything recently
+    fn expire(&mut self) {}
+}
+
+/// Source of the current time for the checkers that track the message history
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock shared by a test and the checker under test, moved forward by the test
+#[cfg(test)]
+#[derive(Clone)]
+struct TestClock(std::rc::Rc<std::cell::Cell<Instant>>);
+
+#[cfg(test)]
+impl TestClock {
+    fn new() -> Self {
+        TestClock(std::rc::Rc::new(std::cell::Cell::new(Instant::now())))
+    }
+
+    fn advance(&self, duration: Duration) {
+        self.0.set(self.0.get() + duration);
+    }
+}
+
+#[cfg(test)]
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.0.get()
+    }
+}
+
+/// Reads a duration given in seconds in the config files
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
 }
 
 fn normalized_message(s: &str) -> String {
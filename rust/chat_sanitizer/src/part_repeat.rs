@@ -1,9 +1,223 @@
-use crate::{MessageChecker, Severity};
+use crate::{normalized_message, Clock, MessageChecker, Severity, SystemClock};
 
-struct PartRepeatChecker {}
+use serde_derive::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant},
+};
 
-impl<T> MessageChecker<T> for PartRepeatChecker {
-    fn check(&self, player_id: T, message: &str) -> Severity {
-        Severity::Pass
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PartRepeatConfig {
+    /// Shortest repeated part, single letters are left to `LetterRepeatChecker`
+    pub min_part_length: usize,
+    /// Number of consecutive copies of a part that makes a message spam
+    pub max_part_repeats: usize,
+    /// Messages are considered the same when their similarity is at least this high
+    pub similarity: f32,
+    /// Number of similar recent messages allowed before the next one is flagged
+    pub max_duplicates: usize,
+    /// Messages shorter than this are not compared with the history
+    pub min_message_length: usize,
+    /// Length of the window in seconds in the config files
+    #[serde(deserialize_with = "crate::seconds")]
+    pub window: Duration,
+    pub history_size: usize,
+}
+
+impl Default for PartRepeatConfig {
+    fn default() -> Self {
+        Self {
+            min_part_length: 2,
+            max_part_repeats: 3,
+            similarity: 0.85,
+            max_duplicates: 2,
+            min_message_length: 5,
+            window: Duration::from_secs(60),
+            history_size: 10,
+        }
+    }
+}
+
+fn levenshtein_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Similarity of two messages from 0 for completely different ones to 1 for equal ones
+fn similarity(a: &[char], b: &[char]) -> f32 {
+    let length = a.len().max(b.len());
+    if length == 0 {
+        1.0
+    } else {
+        1.0 - levenshtein_distance(a, b) as f32 / length as f32
+    }
+}
+
+/// Largest number of consecutive copies of a part at least `min_length` long.
+/// Parts made of a single repeated character are ignored, as in "nooooooo"
+fn max_part_repeats(chars: &[char], min_length: usize) -> usize {
+    let mut max_repeats = 1;
+    for period in min_length.max(1)..=chars.len() / 2 {
+        let mut run = 0;
+        for i in period..chars.len() {
+            if chars[i] == chars[i - period] {
+                run += 1;
+                let start = i + 1 - run - period;
+                let part = &chars[start..start + period];
+                if part.iter().any(|c| *c != part[0]) {
+                    max_repeats = max_repeats.max((run + period) / period);
+                }
+            } else {
+                run = 0;
+            }
+        }
+    }
+    max_repeats
+}
+
+pub struct PartRepeatChecker<T, C = SystemClock> {
+    config: PartRepeatConfig,
+    clock: C,
+    /// Recent normalized messages of every player
+    history: HashMap<T, VecDeque<(Instant, Vec<char>)>>,
+}
+
+impl<T: Hash + Eq> PartRepeatChecker<T> {
+    pub fn new(config: PartRepeatConfig) -> Self {
+        Self::with_clock(config, SystemClock)
+    }
+}
+
+impl<T: Hash + Eq, C: Clock> PartRepeatChecker<T, C> {
+    pub fn with_clock(config: PartRepeatConfig, clock: C) -> Self {
+        Self {
+            config,
+            clock,
+            history: HashMap::new(),
+        }
+    }
+
+    /// Drops the history of the players who haven't written anything within the window
+    pub fn expire(&mut self) {
+        let now = self.clock.now();
+        let window = self.config.window;
+        self.history.retain(|_, messages| match messages.back() {
+            Some((time, _)) => now.duration_since(*time) < window,
+            None => false,
+        });
+    }
+}
+
+impl<T: Hash + Eq, C: Clock> MessageChecker<T> for PartRepeatChecker<T, C> {
+    fn check(&mut self, player_id: T, message: &str) -> Severity {
+        // parts are searched in the lowercased letters because the skeleton turns "mmm"
+        // into "rnrnrn", the trailing space lets the last word count as a full copy
+        let mut words = String::new();
+        for word in message.split_whitespace() {
+            words.extend(word.chars().flat_map(|c| c.to_lowercase()));
+            words.push(' ');
+        }
+        let letters: Vec<char> = words.chars().collect();
+        if max_part_repeats(&letters, self.config.min_part_length) >= self.config.max_part_repeats {
+            return Severity::Warn;
+        }
+
+        let normalized = normalized_message(message);
+        let chars: Vec<char> = normalized
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .collect();
+        if chars.len() < self.config.min_message_length {
+            return Severity::Pass;
+        }
+
+        let now = self.clock.now();
+        let config = &self.config;
+        let messages = self.history.entry(player_id).or_default();
+        messages.retain(|(time, _)| now.duration_since(*time) < config.window);
+
+        let duplicates = messages
+            .iter()
+            .filter(|(_, old)| similarity(old, &chars) >= config.similarity)
+            .count();
+
+        if messages.len() == config.history_size {
+            messages.pop_front();
+        }
+        messages.push_back((now, chars));
+
+        if duplicates >= config.max_duplicates {
+            Severity::Warn
+        } else {
+            Severity::Pass
+        }
+    }
+
+    fn expire(&mut self) {
+        PartRepeatChecker::expire(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+
+    #[test]
+    fn repeated_parts() {
+        let mut checker = PartRepeatChecker::new(PartRepeatConfig::default());
+        assert_eq!(checker.check(0, "Hello world!"), Severity::Pass);
+        assert_eq!(checker.check(0, "lol lol lol lol"), Severity::Warn);
+        assert_eq!(checker.check(0, "hahahahaha"), Severity::Warn);
+        assert_eq!(checker.check(0, "LOL   lol Lol"), Severity::Warn);
+        assert_eq!(checker.check(0, "banana"), Severity::Pass);
+        assert_eq!(checker.check(0, "hmmm"), Severity::Pass);
+        assert_eq!(checker.check(0, "1000000"), Severity::Pass);
+        assert_eq!(checker.check(0, "nooooooo"), Severity::Pass);
+        assert_eq!(checker.check(0, "zzzzzz"), Severity::Pass);
+        assert_eq!(checker.check(0, "nonononono"), Severity::Warn);
+        assert_eq!(
+            checker.check(0, "жираф - длинношеее животное"),
+            Severity::Pass
+        );
+    }
+
+    #[test]
+    fn repeated_messages() {
+        let clock = TestClock::new();
+        let mut checker = PartRepeatChecker::with_clock(PartRepeatConfig::default(), clock.clone());
+
+        assert_eq!(checker.check(0, "join my room please"), Severity::Pass);
+        assert_eq!(checker.check(1, "join my room please"), Severity::Pass);
+        assert_eq!(checker.check(0, "Join my room please!"), Severity::Pass);
+        assert_eq!(checker.check(0, "something else"), Severity::Pass);
+        assert_eq!(checker.check(0, "JOIN MY ROOM, PLEASE"), Severity::Warn);
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(checker.check(0, "join my room please"), Severity::Pass);
+    }
+
+    #[test]
+    fn message_similarity() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(levenshtein_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(similarity(&chars("abcd"), &chars("abcd")), 1.0);
+        assert_eq!(similarity(&chars("abcd"), &chars("wxyz")), 0.0);
+        assert_eq!(max_part_repeats(&chars("abcabcab"), 2), 2);
+        assert_eq!(max_part_repeats(&chars("aaaa"), 2), 1);
+        assert_eq!(max_part_repeats(&chars("xaaaaaaxaaaaaa"), 2), 2);
     }
 }
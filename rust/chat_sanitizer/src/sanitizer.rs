@@ -0,0 +1,537 @@
+use crate::{
+    bad_words::BadWordsChecker, caps_abuse::CapsAbuseChecker, flood::FloodChecker,
+    flood::FloodConfig, letter_repeat::LetterRepeatChecker, part_repeat::PartRepeatChecker,
+    part_repeat::PartRepeatConfig, url::URLChecker, Clock, MessageChecker, Severity, SystemClock,
+};
+
+use serde_derive::Deserialize;
+use serde_yaml;
+use std::{
+    collections::HashMap,
+    fs,
+    hash::Hash,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// Offence scores decayed below this are forgotten
+const FORGOTTEN_SCORE: f32 = 0.01;
+
+fn decayed(score: f32, elapsed: Duration, half_life: Duration) -> f32 {
+    score * 0.5f32.powf(elapsed.as_secs_f32() / half_life.as_secs_f32())
+}
+
+impl Severity {
+    fn score(self) -> f32 {
+        self as u8 as f32
+    }
+}
+
+/// How the verdicts of the checkers are combined into one
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// The most severe verdict wins
+    #[default]
+    Max,
+    /// Verdicts are scored from 0 for `Pass` to 3 for `Ban`, multiplied by the checker weights
+    /// and summed, the sum is compared with the thresholds of each severity level
+    Weighted { warn: f32, silence: f32, ban: f32 },
+}
+
+/// Every offence adds its score to the player's offence score, which halves each `half_life`.
+/// Reaching a threshold raises the verdict to the corresponding severity
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct EscalationConfig {
+    #[serde(deserialize_with = "crate::seconds")]
+    pub half_life: Duration,
+    pub silence: f32,
+    pub ban: f32,
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        Self {
+            half_life: Duration::from_secs(300),
+            silence: 3.0,
+            ban: 6.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CheckerKind {
+    BadWords {
+        #[serde(default)]
+        words: Vec<String>,
+        #[serde(default)]
+        allowed: Vec<String>,
+        /// Directory of the `<language>.txt` word lists
+        directory: Option<String>,
+        #[serde(default)]
+        languages: Vec<String>,
+    },
+    CapsAbuse {
+        threshold: f32,
+        min_letters: usize,
+        #[serde(default)]
+        acronyms: Vec<String>,
+    },
+    Flood(FloodConfig),
+    LetterRepeat {
+        threshold: usize,
+    },
+    PartRepeat(PartRepeatConfig),
+    Url {
+        allowed_domains: Vec<String>,
+    },
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+pub struct CheckerConfig {
+    /// Name reported in the decisions, the type of the checker by default
+    pub name: Option<String>,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    #[serde(flatten)]
+    pub kind: CheckerKind,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct SanitizerConfig {
+    pub policy: Policy,
+    pub escalation: EscalationConfig,
+    /// Checkers in the order they are run
+    pub checkers: Vec<CheckerConfig>,
+}
+
+impl SanitizerConfig {
+    pub fn from_file(filename: &str) -> Result<Self> {
+        let contents = fs::read_to_string(filename)?;
+        serde_yaml::from_str(&contents).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+fn as_strs(strings: &[String]) -> Vec<&str> {
+    strings.iter().map(String::as_str).collect()
+}
+
+impl CheckerKind {
+    fn name(&self) -> &'static str {
+        match self {
+            CheckerKind::BadWords { .. } => "bad_words",
+            CheckerKind::CapsAbuse { .. } => "caps_abuse",
+            CheckerKind::Flood(_) => "flood",
+            CheckerKind::LetterRepeat { .. } => "letter_repeat",
+            CheckerKind::PartRepeat(_) => "part_repeat",
+            CheckerKind::Url { .. } => "url",
+        }
+    }
+
+    fn build<T: Hash + Eq + 'static>(
+        self,
+        is_registered: Rc<dyn Fn(&T) -> bool>,
+    ) -> Result<Box<dyn MessageChecker<T>>> {
+        let checker: Box<dyn MessageChecker<T>> = match self {
+            CheckerKind::BadWords {
+                words,
+                allowed,
+                directory,
+                languages,
+            } => {
+                let mut checker = match directory {
+                    Some(directory) => {
+                        BadWordsChecker::load(Path::new(&directory), &as_strs(&languages))?
+                    }
+                    None => BadWordsChecker::new(&[], &[]),
+                };
+                checker.extend(&words, &allowed);
+                Box::new(checker)
+            }
+            CheckerKind::CapsAbuse {
+                threshold,
+                min_letters,
+                acronyms,
+            } => Box::new(CapsAbuseChecker::new(
+                threshold,
+                min_letters,
+                &as_strs(&acronyms),
+            )),
+            CheckerKind::Flood(config) => Box::new(FloodChecker::new(config)),
+            CheckerKind::LetterRepeat { threshold } => {
+                Box::new(LetterRepeatChecker::new(threshold))
+            }
+            CheckerKind::PartRepeat(config) => Box::new(PartRepeatChecker::new(config)),
+            CheckerKind::Url { allowed_domains } => Box::new(URLChecker::new(
+                &as_strs(&allowed_domains),
+                move |player_id: &T| is_registered(player_id),
+            )),
+        };
+        Ok(checker)
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub enum Reason {
+    /// None of the checkers objected
+    Clean,
+    /// Verdict of a single checker under the `Max` policy
+    Verdict(Severity),
+    /// Sum of the weighted verdicts under the `Weighted` policy
+    Score(f32),
+    /// Offence score of the player after the repeated offences
+    Escalation(f32),
+}
+
+#[derive(PartialEq, Debug)]
+pub struct Decision {
+    pub severity: Severity,
+    pub reason: Reason,
+    /// Name of the checker most responsible for the decision
+    pub checker: Option<String>,
+    /// Replacement for the message offered by the first checker able to fix it
+    pub fixed_message: Option<String>,
+}
+
+struct Stage<T> {
+    name: String,
+    weight: f32,
+    checker: Box<dyn MessageChecker<T>>,
+}
+
+pub struct ChatSanitizer<T, C = SystemClock> {
+    stages: Vec<Stage<T>>,
+    policy: Policy,
+    escalation: EscalationConfig,
+    clock: C,
+    /// Offence score of every player and the time it was last updated
+    offences: HashMap<T, (Instant, f32)>,
+}
+
+impl<T: Clone + Hash + Eq> ChatSanitizer<T> {
+    pub fn new(policy: Policy, escalation: EscalationConfig) -> Self {
+        Self::with_clock(policy, escalation, SystemClock)
+    }
+}
+
+impl<T: Clone + Hash + Eq + 'static> ChatSanitizer<T> {
+    /// Builds the pipeline described by the config,
+    /// links of the players passing `is_registered` are never checked
+    pub fn from_config<F>(config: SanitizerConfig, is_registered: F) -> Result<Self>
+    where
+        F: Fn(&T) -> bool + 'static,
+    {
+        let is_registered: Rc<dyn Fn(&T) -> bool> = Rc::new(is_registered);
+        let mut sanitizer = Self::new(config.policy, config.escalation);
+        for checker in config.checkers {
+            let name = match checker.name {
+                Some(name) => name,
+                None => checker.kind.name().to_string(),
+            };
+            sanitizer.add_checker(
+                &name,
+                checker.weight,
+                checker.kind.build(is_registered.clone())?,
+            );
+        }
+        Ok(sanitizer)
+    }
+
+    pub fn from_file<F>(filename: &str, is_registered: F) -> Result<Self>
+    where
+        F: Fn(&T) -> bool + 'static,
+    {
+        Self::from_config(SanitizerConfig::from_file(filename)?, is_registered)
+    }
+}
+
+impl<T: Clone + Hash + Eq, C: Clock> ChatSanitizer<T, C> {
+    pub fn with_clock(policy: Policy, escalation: EscalationConfig, clock: C) -> Self {
+        Self {
+            stages: vec![],
+            policy,
+            escalation,
+            clock,
+            offences: HashMap::new(),
+        }
+    }
+
+    /// Appends a checker to the end of the pipeline
+    pub fn add_checker(&mut self, name: &str, weight: f32, checker: Box<dyn MessageChecker<T>>) {
+        self.stages.push(Stage {
+            name: name.to_string(),
+            weight,
+            checker,
+        });
+    }
+
+    pub fn offence_score(&self, player_id: &T) -> f32 {
+        match self.offences.get(player_id) {
+            Some((time, score)) => decayed(
+                *score,
+                self.clock.now().duration_since(*time),
+                self.escalation.half_life,
+            ),
+            None => 0.0,
+        }
+    }
+
+    /// Forgets the players whose offences have decayed and expires the state of the checkers
+    pub fn expire(&mut self) {
+        for stage in &mut self.stages {
+            stage.checker.expire();
+        }
+
+        let now = self.clock.now();
+        let half_life = self.escalation.half_life;
+        self.offences.retain(|_, (time, score)| {
+            decayed(*score, now.duration_since(*time), half_life) >= FORGOTTEN_SCORE
+        });
+    }
+
+    /// Combines the verdicts into the severity, the reason and the index of the responsible stage
+    fn combine(&self, verdicts: &[Severity]) -> (Severity, Reason, Option<usize>) {
+        match self.policy {
+            Policy::Max => match verdicts
+                .iter()
+                .enumerate()
+                .max_by_key(|(i, v)| (**v, -(*i as isize)))
+            {
+                Some((i, verdict)) if *verdict > Severity::Pass => {
+                    (*verdict, Reason::Verdict(*verdict), Some(i))
+                }
+                _ => (Severity::Pass, Reason::Clean, None),
+            },
+            Policy::Weighted { warn, silence, ban } => {
+                let scores: Vec<_> = verdicts
+                    .iter()
+                    .zip(&self.stages)
+                    .map(|(verdict, stage)| verdict.score() * stage.weight)
+                    .collect();
+                let total = scores.iter().sum();
+                let severity = if total >= ban {
+                    Severity::Ban
+                } else if total >= silence {
+                    Severity::Silence
+                } else if total >= warn {
+                    Severity::Warn
+                } else {
+                    return (Severity::Pass, Reason::Clean, None);
+                };
+                let mut responsible = 0;
+                for (i, score) in scores.iter().enumerate() {
+                    if *score > scores[responsible] {
+                        responsible = i;
+                    }
+                }
+                (severity, Reason::Score(total), Some(responsible))
+            }
+        }
+    }
+
+    pub fn check(&mut self, player_id: T, message: &str) -> Decision {
+        let verdicts: Vec<_> = self
+            .stages
+            .iter_mut()
+            .map(|stage| stage.checker.check(player_id.clone(), message))
+            .collect();
+
+        let (mut severity, mut reason, responsible) = self.combine(&verdicts);
+        if severity == Severity::Pass {
+            return Decision {
+                severity,
+                reason,
+                checker: None,
+                fixed_message: None,
+            };
+        }
+
+        let fixed_message = self
+            .stages
+            .iter()
+            .zip(&verdicts)
+            .filter(|(_, verdict)| **verdict > Severity::Pass)
+            .find_map(|(stage, _)| stage.checker.fix(player_id.clone(), message));
+
+        let score = self.offence_score(&player_id) + severity.score();
+        self.offences.insert(player_id, (self.clock.now(), score));
+
+        let escalated = if score >= self.escalation.ban {
+            Severity::Ban
+        } else if score >= self.escalation.silence {
+            Severity::Silence
+        } else {
+            Severity::Pass
+        };
+        if escalated > severity {
+            severity = escalated;
+            reason = Reason::Escalation(score);
+        }
+
+        Decision {
+            severity,
+            reason,
+            checker: responsible.map(|i| self.stages[i].name.clone()),
+            fixed_message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestClock;
+    use std::cell::Cell;
+
+    fn test_sanitizer(policy: Policy, clock: TestClock) -> ChatSanitizer<u32, TestClock> {
+        let mut sanitizer = ChatSanitizer::with_clock(policy, EscalationConfig::default(), clock);
+        sanitizer.add_checker("letter_repeat", 1.0, Box::new(LetterRepeatChecker::new(5)));
+        sanitizer.add_checker(
+            "caps_abuse",
+            2.0,
+            Box::new(CapsAbuseChecker::new(0.7, 6, &[])),
+        );
+        sanitizer
+    }
+
+    #[test]
+    fn policies() {
+        let clock = TestClock::new();
+        let mut sanitizer = test_sanitizer(Policy::Max, clock.clone());
+        assert_eq!(
+            sanitizer.check(0, "Hello world!"),
+            Decision {
+                severity: Severity::Pass,
+                reason: Reason::Clean,
+                checker: None,
+                fixed_message: None
+            }
+        );
+        assert_eq!(
+            sanitizer.check(1, "HELLO WORLD!!!!!"),
+            Decision {
+                severity: Severity::Warn,
+                reason: Reason::Verdict(Severity::Warn),
+                checker: Some("letter_repeat".to_string()),
+                fixed_message: Some("Hello world!!!!!".to_string())
+            }
+        );
+
+        let mut sanitizer = test_sanitizer(
+            Policy::Weighted {
+                warn: 1.5,
+                silence: 3.0,
+                ban: 10.0,
+            },
+            clock,
+        );
+        let decision = sanitizer.check(0, "Hello world!!!!!");
+        assert_eq!(decision.severity, Severity::Pass);
+        let decision = sanitizer.check(1, "HELLO WORLD");
+        assert_eq!(decision.severity, Severity::Warn);
+        assert_eq!(decision.reason, Reason::Score(2.0));
+        assert_eq!(decision.checker, Some("caps_abuse".to_string()));
+        let decision = sanitizer.check(2, "HELLO WORLD!!!!!");
+        assert_eq!(decision.severity, Severity::Silence);
+        assert_eq!(decision.reason, Reason::Score(3.0));
+    }
+
+    #[test]
+    fn escalation() {
+        let clock = TestClock::new();
+        let mut sanitizer = test_sanitizer(Policy::Max, clock.clone());
+
+        assert_eq!(sanitizer.check(0, "HELLO WORLD").severity, Severity::Warn);
+        assert_eq!(sanitizer.check(0, "HELLO WORLD").severity, Severity::Warn);
+        assert_eq!(sanitizer.check(1, "HELLO WORLD").severity, Severity::Warn);
+        let decision = sanitizer.check(0, "HELLO WORLD");
+        assert_eq!(decision.severity, Severity::Silence);
+        assert_eq!(decision.reason, Reason::Escalation(3.0));
+        assert_eq!(decision.checker, Some("caps_abuse".to_string()));
+
+        // the score halves each 5 minutes
+        clock.advance(Duration::from_secs(600));
+        assert_eq!(sanitizer.offence_score(&0), 0.75);
+        assert_eq!(sanitizer.check(0, "HELLO WORLD").severity, Severity::Warn);
+
+        clock.advance(Duration::from_secs(3600));
+        sanitizer.expire();
+        assert!(sanitizer.offences.is_empty());
+    }
+
+    struct ExpiryCounter(Rc<Cell<usize>>);
+
+    impl MessageChecker<u32> for ExpiryCounter {
+        fn check(&mut self, _player_id: u32, _message: &str) -> Severity {
+            Severity::Pass
+        }
+
+        fn expire(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn checker_expiry() {
+        let clock = TestClock::new();
+        let mut sanitizer = test_sanitizer(Policy::Max, clock);
+        let expirations = Rc::new(Cell::new(0));
+        sanitizer.add_checker("counter", 1.0, Box::new(ExpiryCounter(expirations.clone())));
+
+        sanitizer.expire();
+        sanitizer.expire();
+        assert_eq!(expirations.get(), 2);
+    }
+
+    #[test]
+    fn config() {
+        let config: SanitizerConfig = serde_yaml::from_str(
+            r#"
+policy:
+  weighted: {warn: 1, silence: 2, ban: 3}
+escalation:
+  half_life: 60
+checkers:
+  - type: bad_words
+    words: [poop]
+    weight: 2
+  - type: url
+    name: links
+    allowed_domains: [hedgewars.org]
+  - type: flood
+    window: 5
+    warn: {messages: 3, characters: 100}
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.escalation.half_life, Duration::from_secs(60));
+        assert_eq!(config.escalation.ban, 6.0);
+
+        let mut sanitizer =
+            ChatSanitizer::from_config(config, |player_id| *player_id == 1).unwrap();
+        assert_eq!(sanitizer.check(0, "Hello world!").severity, Severity::Pass);
+
+        let decision = sanitizer.check(0, "poop");
+        assert_eq!(decision.severity, Severity::Silence);
+        assert_eq!(decision.checker, Some("bad_words".to_string()));
+
+        let decision = sanitizer.check(2, "join evil.com now");
+        assert_eq!(decision.severity, Severity::Warn);
+        assert_eq!(decision.checker, Some("links".to_string()));
+        assert_eq!(decision.fixed_message, Some("join now".to_string()));
+        assert_eq!(sanitizer.check(1, "join evil.com").severity, Severity::Pass);
+        assert_eq!(sanitizer.check(1, "join evil.com").severity, Severity::Pass);
+        let decision = sanitizer.check(1, "join evil.com");
+        assert_eq!(decision.severity, Severity::Warn);
+        assert_eq!(decision.checker, Some("flood".to_string()));
+    }
+}
@@ -1,9 +1,217 @@
-use crate::{MessageChecker, Severity};
+use crate::{normalized_message, MessageChecker, Severity};
 
-struct URLChecker {}
+use std::marker::PhantomData;
 
-impl<T> MessageChecker<T> for URLChecker {
-    fn check(&self, player_id: T, message: &str) -> Severity {
-        Severity::Pass
+const SCHEMES: &[&str] = &["http://", "https://", "ftp://", "www."];
+const TOP_LEVEL_DOMAINS: &[&str] = &[
+    "com", "net", "org", "info", "biz", "io", "co", "me", "tv", "gg", "cc", "to", "ly", "xyz",
+    "app", "dev", "site", "online", "link", "eu", "uk", "us", "de", "fr", "pl", "ru", "ua", "br",
+];
+
+/// Byte ranges of the whitespace separated words
+fn words(message: &str) -> Vec<(usize, usize)> {
+    let mut words = vec![];
+    let mut start = None;
+    for (i, c) in message.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, message.len()));
+    }
+    words
+}
+
+/// Undoes the usual ways of disguising links, expects a normalized word
+fn deobfuscate(word: &str) -> String {
+    let mut word = word
+        .replace("[.]", ".")
+        .replace("(.)", ".")
+        .replace("(dot)", ".")
+        .replace("[dot]", ".");
+    if word.starts_with("hxxp") {
+        word.replace_range(..4, "http");
+    }
+    word
+}
+
+/// Digits are confusable with the uppercase letters, so the skeleton is lowercased again
+fn normalized(s: &str) -> String {
+    normalized_message(s).to_lowercase()
+}
+
+fn is_dot_word(word: &str) -> bool {
+    word == "dot" || word == "."
+}
+
+pub struct URLChecker<T, F> {
+    allowed_domains: Vec<String>,
+    top_level_domains: Vec<String>,
+    /// Links from the registered players are never checked
+    is_registered: F,
+    player_id_type: PhantomData<T>,
+}
+
+impl<T, F: Fn(&T) -> bool> URLChecker<T, F> {
+    pub fn new(allowed_domains: &[&str], is_registered: F) -> Self {
+        Self {
+            allowed_domains: allowed_domains.iter().map(|s| normalized(s)).collect(),
+            top_level_domains: TOP_LEVEL_DOMAINS.iter().map(|s| normalized(s)).collect(),
+            is_registered,
+            player_id_type: PhantomData,
+        }
+    }
+
+    /// Returns the domain if the normalized text is a link
+    fn link_domain(&self, text: &str) -> Option<String> {
+        let text = text.trim_matches(|c: char| !c.is_alphanumeric());
+        let scheme = SCHEMES.iter().find(|scheme| text.starts_with(*scheme));
+        let rest = &text[scheme.map_or(0, |s| s.len())..];
+
+        let host = rest.split(&['/', '?', '#'][..]).next()?;
+        let host = host.rsplit('@').next()?.split(':').next()?;
+        let labels: Vec<_> = host.split('.').collect();
+
+        let is_valid_host = labels.iter().all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+        let has_known_domain = labels.len() > 1
+            && self
+                .top_level_domains
+                .iter()
+                .any(|tld| tld == labels[labels.len() - 1]);
+
+        if is_valid_host && (scheme.is_some() || has_known_domain) {
+            Some(host.to_string())
+        } else {
+            None
+        }
+    }
+
+    fn is_allowed(&self, domain: &str) -> bool {
+        let domain = domain.trim_start_matches("www.");
+        self.allowed_domains.iter().any(|allowed| {
+            domain == allowed
+                || (domain.ends_with(allowed)
+                    && domain[..domain.len() - allowed.len()].ends_with('.'))
+        })
+    }
+
+    /// Byte ranges of the links to the domains outside of the allow-list
+    fn find_links(&self, message: &str) -> Vec<(usize, usize)> {
+        let words = words(message);
+        let normalized: Vec<_> = words
+            .iter()
+            .map(|(start, end)| deobfuscate(&normalized(&message[*start..*end])))
+            .collect();
+
+        let mut links = vec![];
+        let mut i = 0;
+        while i < words.len() {
+            // "example dot com" is joined into a single candidate
+            let mut candidate = normalized[i].clone();
+            let mut last = i;
+            while last + 2 < words.len() && is_dot_word(&normalized[last + 1]) {
+                candidate.push('.');
+                candidate.push_str(&normalized[last + 2]);
+                last += 2;
+            }
+
+            match self.link_domain(&candidate) {
+                Some(domain) => {
+                    if !self.is_allowed(&domain) {
+                        links.push((words[i].0, words[last].1));
+                    }
+                    i = last + 1;
+                }
+                None => i += 1,
+            }
+        }
+        links
+    }
+}
+
+impl<T, F: Fn(&T) -> bool> MessageChecker<T> for URLChecker<T, F> {
+    fn check(&mut self, player_id: T, message: &str) -> Severity {
+        if !(self.is_registered)(&player_id) && !self.find_links(message).is_empty() {
+            Severity::Warn
+        } else {
+            Severity::Pass
+        }
+    }
+
+    /// Removes the disallowed links from the message
+    fn fix(&self, player_id: T, message: &str) -> Option<String> {
+        if (self.is_registered)(&player_id) {
+            return None;
+        }
+        let links = self.find_links(message);
+        if links.is_empty() {
+            return None;
+        }
+
+        let mut result = String::with_capacity(message.len());
+        let mut position = 0;
+        for (start, end) in links {
+            result.push_str(&message[position..start]);
+            position = end;
+        }
+        result.push_str(&message[position..]);
+
+        Some(result.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker() -> URLChecker<u32, impl Fn(&u32) -> bool> {
+        URLChecker::new(&["hedgewars.org"], |player_id| *player_id == 1)
+    }
+
+    #[test]
+    fn it_works() {
+        let mut checker = checker();
+        assert_eq!(checker.check(0, "Hello world!"), Severity::Pass);
+        assert_eq!(checker.check(0, "i.e. it works.Really"), Severity::Pass);
+        assert_eq!(
+            checker.check(0, "see https://www.hedgewars.org/wiki"),
+            Severity::Pass
+        );
+        assert_eq!(checker.check(0, "wiki.hedgewars.org"), Severity::Pass);
+        assert_eq!(checker.check(0, "nothedgewars.org"), Severity::Warn);
+        assert_eq!(checker.check(0, "check example.com!"), Severity::Warn);
+        assert_eq!(checker.check(0, "http://localhost:8080"), Severity::Warn);
+        assert_eq!(checker.check(1, "check example.com!"), Severity::Pass);
+    }
+
+    #[test]
+    fn obfuscated_links() {
+        let mut checker = checker();
+        assert_eq!(checker.check(0, "hxxp://evil[.]net"), Severity::Warn);
+        assert_eq!(checker.check(0, "evil dot com"), Severity::Warn);
+        assert_eq!(checker.check(0, "evil (dot) com"), Severity::Warn);
+        assert_eq!(checker.check(0, "EVIL.C0M"), Severity::Warn);
+        // Cyrillic look-alike letters
+        assert_eq!(checker.check(0, "hеdgеwars.оrg"), Severity::Pass);
+        assert_eq!(checker.check(0, "еvil.соm"), Severity::Warn);
+    }
+
+    #[test]
+    fn fix() {
+        let checker = checker();
+        assert_eq!(checker.fix(0, "join hedgewars.org"), None);
+        assert_eq!(
+            checker.fix(0, "join evil dot com now, not (www.evil.net)"),
+            Some("join now, not".to_string())
+        );
+        assert_eq!(checker.fix(1, "join evil.com"), None);
     }
 }
@@ -0,0 +1,374 @@
+use crate::stats::{ErrorKind, Operation, Stats};
+use log::*;
+use netbuf::Buf;
+use rand::{thread_rng, Rng};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    io::Write,
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const JOIN_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+const START_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const ENGINE_COMMANDS: &[u8] = b"+++++LlRrUuj";
+
+pub struct ClientConfig {
+    pub address: String,
+    pub protocol_number: u16,
+    pub rooms_count: usize,
+    pub teams_per_room: usize,
+    pub ping_interval: Duration,
+    pub chat_interval: Duration,
+    pub engine_interval: Duration,
+    pub warmup: Duration,
+    pub duration: Duration,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum ClientState {
+    Connecting,
+    LoggingIn,
+    Lobby,
+    Room,
+}
+
+fn extract_packet(buf: &mut Buf) -> Option<Buf> {
+    let packet_end = buf[..].windows(2).position(|window| window == b"\n\n")?;
+
+    let mut tail = buf.split_off(packet_end);
+
+    std::mem::swap(&mut tail, buf);
+
+    buf.consume(2);
+
+    Some(tail)
+}
+
+fn jitter(interval: Duration) -> Duration {
+    let millis = interval.as_millis() as u64;
+    Duration::from_millis(thread_rng().gen_range(millis / 2, millis + millis / 2 + 1))
+}
+
+fn engine_message() -> String {
+    let mut rng = thread_rng();
+    let commands = rng.gen_range(1, 4);
+    let mut bytes = Vec::with_capacity(commands * 2);
+    for _ in 0..commands {
+        bytes.push(1);
+        bytes.push(ENGINE_COMMANDS[rng.gen_range(0, ENGINE_COMMANDS.len())]);
+    }
+    base64::encode(&bytes)
+}
+
+struct SimulatedClient<'a> {
+    index: usize,
+    nick: String,
+    config: &'a ClientConfig,
+    stream: TcpStream,
+    buf: Buf,
+    state: ClientState,
+    stats: Stats,
+    started: Instant,
+    login_started: Instant,
+    pending_pings: VecDeque<Instant>,
+    pending_chats: HashMap<u32, Instant>,
+    chat_counter: u32,
+    is_in_game: bool,
+    game_requested: bool,
+    next_ping: Instant,
+    next_chat: Instant,
+    next_engine_message: Instant,
+    next_join: Option<Instant>,
+    next_start: Instant,
+}
+
+impl<'a> SimulatedClient<'a> {
+    fn new(index: usize, config: &'a ClientConfig, stream: TcpStream) -> Self {
+        let now = Instant::now();
+        Self {
+            index,
+            nick: format!("loadtest{}", index),
+            config,
+            stream,
+            buf: Buf::new(),
+            state: ClientState::Connecting,
+            stats: Stats::new(),
+            started: now,
+            login_started: now,
+            pending_pings: VecDeque::new(),
+            pending_chats: HashMap::new(),
+            chat_counter: 0,
+            is_in_game: false,
+            game_requested: false,
+            next_ping: now + jitter(config.ping_interval),
+            next_chat: now + jitter(config.chat_interval),
+            next_engine_message: now,
+            next_join: None,
+            next_start: now + config.warmup,
+        }
+    }
+
+    fn room_index(&self) -> usize {
+        self.index % self.config.rooms_count
+    }
+
+    fn room_name(&self) -> String {
+        format!("loadtest room {}", self.room_index())
+    }
+
+    fn is_room_master(&self) -> bool {
+        self.index < self.config.rooms_count
+    }
+
+    fn has_team(&self) -> bool {
+        self.index / self.config.rooms_count < self.config.teams_per_room
+    }
+
+    fn send(&mut self, parts: &[&str]) -> io::Result<()> {
+        let mut msg = parts.join("\n");
+        msg.push_str("\n\n");
+        self.stats.messages_sent += 1;
+        self.stream.write_all(msg.as_bytes())
+    }
+
+    fn add_team(&mut self) -> io::Result<()> {
+        let name = format!("team{}", self.index);
+        let mut parts = vec![
+            "ADD_TEAM".to_string(),
+            name,
+            "0".to_string(),
+            "Statue".to_string(),
+            "Plane".to_string(),
+            "Default".to_string(),
+            "hedgewars".to_string(),
+            "0".to_string(),
+        ];
+        for hog in 1..=8 {
+            parts.push(format!("hog{}", hog));
+            parts.push("NoHat".to_string());
+        }
+        let parts: Vec<_> = parts.iter().map(|s| &s[..]).collect();
+        self.send(&parts)
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) -> io::Result<bool> {
+        self.stats.messages_received += 1;
+        let text = String::from_utf8_lossy(packet);
+        let mut lines = text.split('\n');
+        let command = lines.next().unwrap_or_default();
+
+        match command {
+            "CONNECTED" => {
+                self.state = ClientState::LoggingIn;
+                self.login_started = Instant::now();
+                let nick = self.nick.clone();
+                let protocol = self.config.protocol_number.to_string();
+                self.send(&["NICK", &nick])?;
+                self.send(&["PROTO", &protocol])?;
+            }
+            "PING" => self.send(&["PONG"])?,
+            "PONG" => {
+                if let Some(sent) = self.pending_pings.pop_front() {
+                    self.stats.record_latency(Operation::Ping, sent.elapsed());
+                }
+            }
+            "ASKPASSWORD" => {
+                warn!(
+                    "{}: the server asks for a password, use a non-official server",
+                    self.nick
+                );
+                self.stats.record_error(ErrorKind::ServerError);
+                return Ok(false);
+            }
+            "LOBBY:JOINED"
+                if self.state == ClientState::LoggingIn && lines.any(|nick| nick == self.nick) =>
+            {
+                self.stats
+                    .record_latency(Operation::Login, self.login_started.elapsed());
+                self.state = ClientState::Lobby;
+                if self.is_room_master() {
+                    let name = self.room_name();
+                    self.send(&["CREATE_ROOM", &name])?;
+                } else {
+                    self.next_join = Some(Instant::now() + JOIN_RETRY_INTERVAL);
+                }
+            }
+            "JOINED" if self.state == ClientState::Lobby && lines.any(|nick| nick == self.nick) => {
+                self.state = ClientState::Room;
+                self.next_join = None;
+                if self.has_team() {
+                    self.add_team()?;
+                }
+            }
+            "CHAT" => {
+                if let (Some(nick), Some(msg)) = (lines.next(), lines.next()) {
+                    if nick == self.nick {
+                        let seq = msg.split_once(' ').and_then(|(_, s)| s.parse::<u32>().ok());
+                        if let Some(sent) = seq.and_then(|seq| self.pending_chats.remove(&seq)) {
+                            self.stats.record_latency(Operation::Chat, sent.elapsed());
+                        }
+                    }
+                }
+            }
+            "RUN_GAME" => self.is_in_game = true,
+            "ROUND_FINISHED" => {
+                self.is_in_game = false;
+                self.game_requested = false;
+            }
+            "WARNING" => {
+                let msg = lines.next().unwrap_or_default();
+                if msg == "No such room." && self.state == ClientState::Lobby {
+                    self.next_join = Some(Instant::now() + JOIN_RETRY_INTERVAL);
+                } else {
+                    debug!("{}: warning {}", self.nick, msg);
+                    self.stats.record_error(ErrorKind::ServerWarning);
+                    self.game_requested = false;
+                }
+            }
+            "ERROR" => {
+                debug!("{}: error {}", self.nick, lines.next().unwrap_or_default());
+                self.stats.record_error(ErrorKind::ServerError);
+            }
+            "BYE" => {
+                warn!(
+                    "{}: disconnected: {}",
+                    self.nick,
+                    lines.next().unwrap_or_default()
+                );
+                self.stats.record_error(ErrorKind::Disconnected);
+                return Ok(false);
+            }
+            _ => (),
+        }
+
+        Ok(true)
+    }
+
+    fn act(&mut self, now: Instant) -> io::Result<()> {
+        if self.state == ClientState::Connecting || self.state == ClientState::LoggingIn {
+            return Ok(());
+        }
+
+        if now >= self.next_ping {
+            self.next_ping = now + jitter(self.config.ping_interval);
+            self.pending_pings.push_back(now);
+            self.send(&["PING"])?;
+        }
+
+        if let Some(next_join) = self.next_join {
+            if now >= next_join {
+                self.next_join = None;
+                let name = self.room_name();
+                self.send(&["JOIN_ROOM", &name])?;
+            }
+        }
+
+        if self.state == ClientState::Room {
+            if now >= self.next_chat {
+                self.next_chat = now + jitter(self.config.chat_interval);
+                self.chat_counter += 1;
+                self.pending_chats.insert(self.chat_counter, now);
+                let msg = format!("loadtest {}", self.chat_counter);
+                self.send(&["CHAT", &msg])?;
+            }
+
+            if self.is_room_master()
+                && !self.is_in_game
+                && !self.game_requested
+                && now >= self.next_start
+            {
+                self.game_requested = true;
+                self.next_start = now + START_RETRY_INTERVAL;
+                self.send(&["START_GAME"])?;
+            }
+
+            if self.is_in_game && self.has_team() && now >= self.next_engine_message {
+                self.next_engine_message = now + jitter(self.config.engine_interval);
+                self.stats.engine_messages_sent += 1;
+                let msg = engine_message();
+                self.send(&["EM", &msg])?;
+            }
+        }
+
+        self.expire_requests(now);
+        Ok(())
+    }
+
+    fn expire_requests(&mut self, now: Instant) {
+        while let Some(sent) = self.pending_pings.front().cloned() {
+            if now.duration_since(sent) < RESPONSE_TIMEOUT {
+                break;
+            }
+            self.pending_pings.pop_front();
+            self.stats.record_error(ErrorKind::Timeout);
+        }
+
+        let expired: Vec<_> = self
+            .pending_chats
+            .iter()
+            .filter(|(_, sent)| now.duration_since(**sent) >= RESPONSE_TIMEOUT)
+            .map(|(seq, _)| *seq)
+            .collect();
+        for seq in expired {
+            self.pending_chats.remove(&seq);
+            self.stats.record_error(ErrorKind::Timeout);
+        }
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        self.stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let deadline = self.started + self.config.duration;
+
+        loop {
+            match self.buf.read_from(&mut self.stream) {
+                Ok(0) => {
+                    self.stats.record_error(ErrorKind::Disconnected);
+                    return Ok(());
+                }
+                Ok(_) => {
+                    while let Some(packet) = extract_packet(&mut self.buf) {
+                        if !self.handle_packet(&packet[..])? {
+                            return Ok(());
+                        }
+                    }
+                }
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                if self.state == ClientState::Connecting || self.state == ClientState::LoggingIn {
+                    self.stats.record_error(ErrorKind::Timeout);
+                }
+                return self.send(&["QUIT", "load test finished"]);
+            }
+            self.act(now)?;
+        }
+    }
+}
+
+pub fn run_client(index: usize, config: &ClientConfig) -> Stats {
+    match TcpStream::connect(&config.address) {
+        Ok(stream) => {
+            let mut client = SimulatedClient::new(index, config, stream);
+            if let Err(e) = client.run() {
+                debug!("{}: {}", client.nick, e);
+                client.stats.record_error(ErrorKind::Connection);
+            }
+            client.stats
+        }
+        Err(e) => {
+            debug!("Client {} could not connect: {}", index, e);
+            let mut stats = Stats::new();
+            stats.record_error(ErrorKind::Connection);
+            stats
+        }
+    }
+}
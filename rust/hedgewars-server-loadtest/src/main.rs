@@ -0,0 +1,136 @@
+use getopts::Options;
+use log::*;
+use std::{
+    env,
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod client;
+mod stats;
+
+use crate::{
+    client::{run_client, ClientConfig},
+    stats::Stats,
+};
+
+const PROGRAM_NAME: &'_ str = "Hedgewars Game Server Load Test";
+
+fn parse_opt<T: FromStr>(matches: &getopts::Matches, name: &str, default: T) -> T {
+    matches
+        .opt_str(name)
+        .and_then(|s| T::from_str(&s).ok())
+        .unwrap_or(default)
+}
+
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::new();
+
+    opts.optopt(
+        "a",
+        "address",
+        "server address - defaults to 127.0.0.1",
+        "HOST",
+    );
+    opts.optopt("p", "port", "port - defaults to 46631", "PORT");
+    opts.optopt("c", "clients", "number of clients - defaults to 100", "N");
+    opts.optopt("r", "rooms", "number of rooms - defaults to 10", "N");
+    opts.optopt("t", "teams", "teams per room - defaults to 2", "N");
+    opts.optopt(
+        "d",
+        "duration",
+        "test duration in seconds - defaults to 60",
+        "SECS",
+    );
+    opts.optopt("", "proto", "protocol number - defaults to 58", "N");
+    opts.optopt(
+        "",
+        "connect-rate",
+        "new connections per second - defaults to 50",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "warmup",
+        "seconds before games are started - defaults to 10",
+        "SECS",
+    );
+    opts.optopt(
+        "",
+        "ping-interval",
+        "milliseconds between pings - defaults to 1000",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "chat-interval",
+        "milliseconds between chat messages - defaults to 5000",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "engine-interval",
+        "milliseconds between engine messages - defaults to 250",
+        "MS",
+    );
+    opts.optflag("h", "help", "help");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}\n{}", e, opts.short_usage(""));
+            return;
+        }
+    };
+    if matches.opt_present("h") {
+        println!("{}", opts.usage(PROGRAM_NAME));
+        return;
+    }
+
+    let host = matches
+        .opt_str("a")
+        .unwrap_or_else(|| "127.0.0.1".to_string());
+    let port: u16 = parse_opt(&matches, "p", 46631);
+    let clients_count: usize = parse_opt(&matches, "c", 100);
+    let connect_rate: u64 = parse_opt(&matches, "connect-rate", 50);
+
+    let config = Arc::new(ClientConfig {
+        address: format!("{}:{}", host, port),
+        protocol_number: parse_opt(&matches, "proto", 58),
+        rooms_count: parse_opt(&matches, "r", 10usize).max(1),
+        teams_per_room: parse_opt(&matches, "t", 2),
+        ping_interval: Duration::from_millis(parse_opt(&matches, "ping-interval", 1000)),
+        chat_interval: Duration::from_millis(parse_opt(&matches, "chat-interval", 5000)),
+        engine_interval: Duration::from_millis(parse_opt(&matches, "engine-interval", 250)),
+        warmup: Duration::from_secs(parse_opt(&matches, "warmup", 10)),
+        duration: Duration::from_secs(parse_opt(&matches, "d", 60)),
+    });
+
+    info!(
+        "Starting {} clients against {} in {} rooms",
+        clients_count, config.address, config.rooms_count
+    );
+
+    let start = Instant::now();
+    let connect_delay = Duration::from_millis(1000 / connect_rate.max(1));
+    let mut threads = Vec::with_capacity(clients_count);
+    for index in 0..clients_count {
+        let config = config.clone();
+        threads.push(thread::spawn(move || run_client(index, &config)));
+        thread::sleep(connect_delay);
+    }
+
+    let mut stats = Stats::new();
+    for thread in threads {
+        match thread.join() {
+            Ok(client_stats) => stats.merge(client_stats),
+            Err(_) => error!("Client thread panicked"),
+        }
+    }
+
+    println!("{}", stats.report(start.elapsed()));
+}
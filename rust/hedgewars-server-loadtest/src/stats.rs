@@ -0,0 +1,147 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Operation {
+    Login,
+    Ping,
+    Chat,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::Login => "login",
+            Operation::Ping => "ping",
+            Operation::Chat => "chat echo",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+pub enum ErrorKind {
+    Connection,
+    Timeout,
+    ServerError,
+    ServerWarning,
+    Disconnected,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Connection => "connection errors",
+            ErrorKind::Timeout => "timeouts",
+            ErrorKind::ServerError => "ERROR messages",
+            ErrorKind::ServerWarning => "WARNING messages",
+            ErrorKind::Disconnected => "unexpected disconnects",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Default)]
+pub struct Stats {
+    latencies: HashMap<Operation, Vec<Duration>>,
+    errors: HashMap<ErrorKind, u32>,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    pub engine_messages_sent: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_latency(&mut self, operation: Operation, latency: Duration) {
+        self.latencies.entry(operation).or_default().push(latency);
+    }
+
+    pub fn record_error(&mut self, kind: ErrorKind) {
+        *self.errors.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (operation, mut latencies) in other.latencies {
+            self.latencies
+                .entry(operation)
+                .or_default()
+                .append(&mut latencies);
+        }
+        for (kind, count) in other.errors {
+            *self.errors.entry(kind).or_insert(0) += count;
+        }
+        self.messages_sent += other.messages_sent;
+        self.messages_received += other.messages_received;
+        self.engine_messages_sent += other.engine_messages_sent;
+    }
+
+    pub fn report(&mut self, elapsed: Duration) -> String {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        let mut lines = vec![
+            format!("Duration: {:.1}s", seconds),
+            format!(
+                "Messages sent: {} ({:.1}/s), of them engine messages: {}",
+                self.messages_sent,
+                self.messages_sent as f64 / seconds,
+                self.engine_messages_sent
+            ),
+            format!(
+                "Messages received: {} ({:.1}/s)",
+                self.messages_received,
+                self.messages_received as f64 / seconds
+            ),
+        ];
+
+        for operation in &[Operation::Login, Operation::Ping, Operation::Chat] {
+            if let Some(latencies) = self.latencies.get_mut(operation) {
+                latencies.sort();
+                lines.push(format!(
+                    "{:>10}: n = {}, p50 = {:?}, p90 = {:?}, p99 = {:?}, max = {:?}",
+                    operation.to_string(),
+                    latencies.len(),
+                    percentile(latencies, 50.0),
+                    percentile(latencies, 90.0),
+                    percentile(latencies, 99.0),
+                    latencies.last().cloned().unwrap_or_default()
+                ));
+            }
+        }
+
+        if self.errors.is_empty() {
+            lines.push("No errors".to_string());
+        } else {
+            for (kind, count) in &self.errors {
+                lines.push(format!("{}: {}", kind, count));
+            }
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Nearest-rank percentile of an already sorted slice
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        Duration::default()
+    } else {
+        let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+        sorted[rank.max(1).min(sorted.len()) - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<_> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&values, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&values, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&values, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&values[..1], 90.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 90.0), Duration::default());
+    }
+}
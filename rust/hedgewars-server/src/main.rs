@@ -10,7 +10,11 @@ mod protocol;
 mod server;
 mod utils;
 
-use crate::server::network::{NetworkLayer, NetworkLayerBuilder};
+use crate::server::{
+    assets::AssetIndex,
+    config::ServerConfig,
+    network::{NetworkLayer, NetworkLayerBuilder},
+};
 
 const PROGRAM_NAME: &'_ str = "Hedgewars Game Server";
 
@@ -23,6 +27,20 @@ fn main() {
     let mut opts = Options::new();
 
     opts.optopt("p", "port", "port - defaults to 46631", "PORT");
+    opts.optopt("c", "config", "server config file in YAML format", "FILE");
+    opts.optopt(
+        "w",
+        "websocket-port",
+        "port for the WebSocket clients",
+        "PORT",
+    );
+    #[cfg(feature = "tls-connections")]
+    opts.optopt(
+        "s",
+        "secure-websocket-port",
+        "port for the WebSocket clients connecting over TLS",
+        "PORT",
+    );
     opts.optflag("h", "help", "help");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -41,11 +59,40 @@ fn main() {
         .and_then(|s| u16::from_str(&s).ok())
         .unwrap_or(46631);
     let address = format!("0.0.0.0:{}", port).parse().unwrap();
+    let websocket_port = matches.opt_str("w").and_then(|s| u16::from_str(&s).ok());
+
+    let config = match matches.opt_str("c") {
+        Some(filename) => match ServerConfig::from_file(&filename) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Unable to load the config file {}: {}", filename, e);
+                return;
+            }
+        },
+        None => ServerConfig::default(),
+    };
+
+    let assets = match config.data_dir {
+        Some(ref data_dir) => match AssetIndex::load(data_dir) {
+            Ok(assets) => Some(assets),
+            Err(e) => {
+                println!("Unable to index the data directory {}: {}", data_dir, e);
+                return;
+            }
+        },
+        None => None,
+    };
 
     let listener = TcpListener::bind(&address).unwrap();
 
     let poll = Poll::new().unwrap();
-    let mut hw_builder = NetworkLayerBuilder::default().with_listener(listener);
+    let mut hw_builder = NetworkLayerBuilder::default()
+        .with_listener(listener)
+        .with_config(config);
+
+    if let Some(assets) = assets {
+        hw_builder = hw_builder.with_assets(assets);
+    }
 
     #[cfg(feature = "tls-connections")]
     {
@@ -53,6 +100,20 @@ fn main() {
         hw_builder = hw_builder.with_secure_listener(TcpListener::bind(&address).unwrap());
     }
 
+    if let Some(websocket_port) = websocket_port {
+        let address = format!("0.0.0.0:{}", websocket_port).parse().unwrap();
+        hw_builder = hw_builder.with_websocket_listener(TcpListener::bind(&address).unwrap());
+    }
+
+    #[cfg(feature = "tls-connections")]
+    {
+        if let Some(port) = matches.opt_str("s").and_then(|s| u16::from_str(&s).ok()) {
+            let address = format!("0.0.0.0:{}", port).parse().unwrap();
+            hw_builder =
+                hw_builder.with_secure_websocket_listener(TcpListener::bind(&address).unwrap());
+        }
+    }
+
     let mut hw_network = hw_builder.build();
     hw_network.register(&poll).unwrap();
 
@@ -69,7 +130,10 @@ fn main() {
         for event in events.iter() {
             if event.readiness() & Ready::readable() == Ready::readable() {
                 match event.token() {
-                    token @ utils::SERVER_TOKEN | token @ utils::SECURE_SERVER_TOKEN => {
+                    token @ utils::SERVER_TOKEN
+                    | token @ utils::SECURE_SERVER_TOKEN
+                    | token @ utils::WEBSOCKET_SERVER_TOKEN
+                    | token @ utils::SECURE_WEBSOCKET_SERVER_TOKEN => {
                         match hw_network.accept_client(&poll, token) {
                             Ok(()) => (),
                             Err(e) => debug!("Error accepting client: {}", e),
@@ -80,10 +144,15 @@ fn main() {
                         Err(e) => debug!("Error in timer event: {}", e),
                     },
                     #[cfg(feature = "official-server")]
-                    utils::IO_TOKEN => match hw_network.handle_io_result() {
+                    utils::IO_TOKEN => match hw_network.handle_io_result(&poll) {
                         Ok(()) => (),
                         Err(e) => debug!("Error in IO task: {}", e),
                     },
+                    #[cfg(feature = "federation")]
+                    utils::FEDERATION_TOKEN => match hw_network.handle_peer_events(&poll) {
+                        Ok(()) => (),
+                        Err(e) => debug!("Error in federation event: {}", e),
+                    },
                     Token(token) => match hw_network.client_readable(&poll, token) {
                         Ok(()) => (),
                         Err(e) => debug!("Error reading from client socket {}: {}", token, e),
@@ -94,8 +163,11 @@ fn main() {
                 match event.token() {
                     utils::SERVER_TOKEN
                     | utils::SECURE_SERVER_TOKEN
+                    | utils::WEBSOCKET_SERVER_TOKEN
+                    | utils::SECURE_WEBSOCKET_SERVER_TOKEN
                     | utils::TIMER_TOKEN
-                    | utils::IO_TOKEN => unreachable!(),
+                    | utils::IO_TOKEN
+                    | utils::FEDERATION_TOKEN => unreachable!(),
                     Token(token) => match hw_network.client_writable(&poll, token) {
                         Ok(()) => (),
                         Err(e) => debug!("Error writing to client socket {}: {}", token, e),
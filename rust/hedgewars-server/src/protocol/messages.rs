@@ -1,4 +1,6 @@
-use crate::server::coretypes::{GameCfg, HedgehogInfo, ServerVar, TeamInfo, VoteType};
+use crate::server::coretypes::{
+    AnnouncementTarget, GameCfg, HedgehogInfo, ServerVar, TeamInfo, VoteType,
+};
 use std::{convert::From, iter::once, ops};
 
 #[derive(PartialEq, Eq, Clone, Debug)]
@@ -17,12 +19,18 @@ pub enum HWProtocolMessage {
     Proto(u16),
     Password(String, String),
     Checker(u16, String, String),
+    // checker messages
+    CheckerReady,
+    CheckedOk(Vec<String>),
+    CheckedFail(String),
     // lobby messages
     List,
     Chat(String),
     CreateRoom(String, Option<String>),
+    CreateRankedRoom(String),
     JoinRoom(String, Option<String>),
     Follow(String),
+    PrivateMessage(String, String),
     Rnd(Vec<String>),
     Kick(String),
     Ban(String, String, u32),
@@ -30,6 +38,13 @@ pub enum HWProtocolMessage {
     BanNick(String, String, u32),
     BanList,
     Unban(String),
+    Mute(String, u32, Option<String>),
+    Unmute(String),
+    Announce(AnnouncementTarget, Option<u32>, Option<u32>, String),
+    ListAnnouncements,
+    CancelAnnouncement(u32),
+    Rating(Option<String>),
+    Leaderboard,
     SetServerVar(ServerVar),
     GetServerVar,
     RestartServer,
@@ -52,6 +67,8 @@ pub enum HWProtocolMessage {
     Delegate(String),
     TeamChat(String),
     MaxTeams(u8),
+    Balance(Option<u8>),
+    AutoBalance(bool),
     Fix,
     Unfix,
     Greeting(String),
@@ -62,6 +79,13 @@ pub enum HWProtocolMessage {
     Delete(String),
     SaveRoom(String),
     LoadRoom(String),
+    ListPresets,
+    LoadPreset(String),
+    SavePreset(String),
+    DeletePreset(String),
+    PublishPreset(String),
+    UnpublishPreset(String),
+    CustomCommand(String, Option<String>),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -110,7 +134,8 @@ pub fn remove_flags(flags: &[ProtocolFlags]) -> String {
 #[derive(Debug)]
 pub enum HWServerMessage {
     Connected(u32),
-    Redirect(u16),
+    /// Port and host of the server the client should reconnect to, the same host by default
+    Redirect(u16, Option<String>),
 
     Ping,
     Pong,
@@ -120,6 +145,7 @@ pub enum HWServerMessage {
     Proto(u16),
     AskPassword(String),
     ServerAuth(String),
+    LogonPassed,
 
     LobbyLeft(String, String),
     LobbyJoined(Vec<String>),
@@ -143,6 +169,8 @@ pub enum HWServerMessage {
     ForwardEngineMessage(Vec<String>),
     RoundFinished,
     ReplayStart,
+    /// Engine messages of a game demo for the checker to replay
+    Replay(Vec<String>),
 
     Info(Vec<String>),
     ServerMessage(String),
@@ -273,13 +301,18 @@ impl HWProtocolMessage {
             Proto(version) => msg!["PROTO", version],
             Password(p, s) => msg!["PASSWORD", p, s],
             Checker(i, n, p) => msg!["CHECKER", i, n, p],
+            CheckerReady => msg!["READY"],
+            CheckedOk(info) => construct_message(&["CHECKED", "OK"], &info),
+            CheckedFail(msg) => msg!["CHECKED", "FAIL", msg],
             List => msg!["LIST"],
             Chat(msg) => msg!["CHAT", msg],
             CreateRoom(name, None) => msg!["CREATE_ROOM", name],
             CreateRoom(name, Some(password)) => msg!["CREATE_ROOM", name, password],
+            CreateRankedRoom(name) => msg!["CMD", format!("RANKED {}", name)],
             JoinRoom(name, None) => msg!["JOIN_ROOM", name],
             JoinRoom(name, Some(password)) => msg!["JOIN_ROOM", name, password],
             Follow(name) => msg!["FOLLOW", name],
+            PrivateMessage(nick, msg) => msg!["CMD", format!("MSG {} {}", nick, msg)],
             Rnd(args) => {
                 if args.is_empty() {
                     msg!["CMD", "RND"]
@@ -293,6 +326,26 @@ impl HWProtocolMessage {
             BanNick(nick, reason, time) => msg!("BAN_NICK", nick, reason, time),
             BanList => msg!["BANLIST"],
             Unban(name) => msg!["UNBAN", name],
+            Mute(nick, duration, None) => msg!["CMD", format!("MUTE {} {}", nick, duration)],
+            Mute(nick, duration, Some(reason)) => {
+                msg!["CMD", format!("MUTE {} {} {}", nick, duration, reason)]
+            }
+            Unmute(nick) => msg!["CMD", format!("UNMUTE {}", nick)],
+            Announce(target, delay, interval, msg) => {
+                let mut command = format!("ANNOUNCE {}", target.to_protocol());
+                if let Some(delay) = delay {
+                    command.push_str(&format!(" IN {}", delay));
+                }
+                if let Some(interval) = interval {
+                    command.push_str(&format!(" EVERY {}", interval));
+                }
+                msg!["CMD", format!("{} {}", command, msg)]
+            }
+            ListAnnouncements => msg!["CMD", "ANNOUNCEMENTS"],
+            CancelAnnouncement(id) => msg!["CMD", format!("CANCELANNOUNCEMENT {}", id)],
+            Rating(None) => msg!["CMD", "RATING"],
+            Rating(Some(nick)) => msg!["CMD", format!("RATING {}", nick)],
+            Leaderboard => msg!["CMD", "LEADERBOARD"],
             SetServerVar(var) => construct_message(&["SET_SERVER_VAR"], &var.to_protocol()),
             GetServerVar => msg!["GET_SERVER_VAR"],
             RestartServer => msg!["CMD", "RESTART_SERVER YES"],
@@ -324,6 +377,8 @@ impl HWProtocolMessage {
             ToggleReady => msg!["TOGGLE_READY"],
             StartGame => msg!["START_GAME"],
             EngineMessage(msg) => msg!["EM", msg],
+            CustomCommand(name, None) => msg!["CMD", name],
+            CustomCommand(name, Some(args)) => msg!["CMD", format!("{} {}", name, args)],
             RoundFinished => msg!["ROUNDFINISHED"],
             ReplayStart => msg!["REPLAY_START"],
             ToggleRestrictJoin => msg!["TOGGLE_RESTRICT_JOINS"],
@@ -333,6 +388,12 @@ impl HWProtocolMessage {
             Delegate(name) => msg!["CMD", format!("DELEGATE {}", name)],
             TeamChat(msg) => msg!["TEAMCHAT", msg],
             MaxTeams(count) => msg!["CMD", format!("MAXTEAMS {}", count)],
+            Balance(None) => msg!["CMD", "BALANCE"],
+            Balance(Some(count)) => msg!["CMD", format!("BALANCE {}", count)],
+            AutoBalance(value) => msg![
+                "CMD",
+                format!("AUTOBALANCE {}", if *value { "YES" } else { "NO" })
+            ],
             Fix => msg!["CMD", "FIX"],
             Unfix => msg!["CMD", "UNFIX"],
             Greeting(msg) => msg!["CMD", format!("GREETING {}", msg)],
@@ -343,6 +404,12 @@ impl HWProtocolMessage {
             Delete(name) => msg!["CMD", format!("DELETE {}", name)],
             SaveRoom(name) => msg!["CMD", format!("SAVEROOM {}", name)],
             LoadRoom(name) => msg!["CMD", format!("LOADROOM {}", name)],
+            ListPresets => msg!["CMD", "PRESETS"],
+            LoadPreset(name) => msg!["CMD", format!("LOADPRESET {}", name)],
+            SavePreset(name) => msg!["CMD", format!("SAVEPRESET {}", name)],
+            DeletePreset(name) => msg!["CMD", format!("DELETEPRESET {}", name)],
+            PublishPreset(name) => msg!["CMD", format!("PUBLISHPRESET {}", name)],
+            UnpublishPreset(name) => msg!["CMD", format!("UNPUBLISHPRESET {}", name)],
             _ => panic!("Protocol message not yet implemented"),
         }
     }
@@ -366,12 +433,14 @@ impl HWServerMessage {
                 "Hedgewars server https://www.hedgewars.org/",
                 protocol_version
             ],
-            Redirect(port) => msg!["REDIRECT", port],
+            Redirect(port, None) => msg!["REDIRECT", port],
+            Redirect(port, Some(host)) => msg!["REDIRECT", port, host],
             Bye(msg) => msg!["BYE", msg],
             Nick(nick) => msg!["NICK", nick],
             Proto(proto) => msg!["PROTO", proto],
             AskPassword(salt) => msg!["ASKPASSWORD", salt],
             ServerAuth(hash) => msg!["SERVER_AUTH", hash],
+            LogonPassed => msg!["LOGONPASSED"],
             LobbyLeft(nick, msg) => msg!["LOBBY:LEFT", nick, msg],
             LobbyJoined(nicks) => construct_message(&["LOBBY:JOINED"], &nicks),
             ClientFlags(flags, nicks) => construct_message(&["CLIENT_FLAGS", flags], &nicks),
@@ -392,6 +461,7 @@ impl HWServerMessage {
             RunGame => msg!["RUN_GAME"],
             ForwardEngineMessage(em) => construct_message(&["EM"], &em),
             RoundFinished => msg!["ROUND_FINISHED"],
+            Replay(demo) => construct_message(&["REPLAY"], &demo),
             ChatMsg { nick, msg } => msg!["CHAT", nick, msg],
             Info(info) => construct_message(&["INFO"], &info),
             ServerMessage(msg) => msg!["SERVER_MESSAGE", msg],
@@ -16,7 +16,8 @@ use std::{
 
 use super::messages::{HWProtocolMessage, HWProtocolMessage::*};
 use crate::server::coretypes::{
-    GameCfg, HedgehogInfo, ServerVar, TeamInfo, VoteType, MAX_HEDGEHOGS_PER_TEAM,
+    AnnouncementTarget, GameCfg, HedgehogInfo, ServerVar, TeamInfo, VoteType,
+    MAX_HEDGEHOGS_PER_TEAM,
 };
 
 #[derive(Debug, PartialEq)]
@@ -112,6 +113,58 @@ fn u32_line(input: &[u8]) -> HWResult<u32> {
     Ok((i, convert_from_str(str)?.1))
 }
 
+/// A duration in seconds written as numbers followed by `d`, `h`, `m` or `s` units, e.g. `1h30m`,
+/// numbers without a unit are seconds
+fn duration_arg(input: &[u8]) -> HWResult<u32> {
+    let (i, text) = take_while(|c: u8| c.is_ascii_alphanumeric())(input)?;
+    let mut total = 0u32;
+    let mut value = None;
+    for c in text.iter().map(|c| c.to_ascii_lowercase()) {
+        let multiplier = match c {
+            b'0'..=b'9' => {
+                let digit = u32::from(c - b'0');
+                value = Some(
+                    value
+                        .unwrap_or(0u32)
+                        .saturating_mul(10)
+                        .saturating_add(digit),
+                );
+                continue;
+            }
+            b's' => 1,
+            b'm' => 60,
+            b'h' => 60 * 60,
+            b'd' => 24 * 60 * 60,
+            _ => return Err(Err::Error(HWProtocolError::new())),
+        };
+        let value = value.take().ok_or(Err::Error(HWProtocolError::new()))?;
+        total = total.saturating_add(value.saturating_mul(multiplier));
+    }
+
+    match value {
+        Some(seconds) => Ok((i, total.saturating_add(seconds))),
+        None if !text.is_empty() => Ok((i, total)),
+        None => Err(Err::Error(HWProtocolError::new())),
+    }
+}
+
+fn u16_arg(input: &[u8]) -> HWResult<u16> {
+    let (i, digits) = take_while(|c: u8| c.is_ascii_digit())(input)?;
+    Ok((i, convert_from_str(convert_utf8(digits)?.1)?.1))
+}
+
+fn announcement_target(input: &[u8]) -> HWResult<AnnouncementTarget> {
+    alt((
+        |i| tag_no_case("ALL")(i).map(|(i, _)| (i, AnnouncementTarget::All)),
+        |i| tag_no_case("LOBBY")(i).map(|(i, _)| (i, AnnouncementTarget::Lobby)),
+        |i| {
+            let (i, min) = u16_arg(i)?;
+            let (i, max) = opt!(i, |i| precededc(i, hw_tag("-"), u16_arg))?;
+            Ok((i, AnnouncementTarget::Protocols(min, max.unwrap_or(min))))
+        },
+    ))(input)
+}
+
 fn yes_no_line(input: &[u8]) -> HWResult<bool> {
     alt((
         |i| tag_no_case(b"YES")(i).map(|(i, _)| (i, true)),
@@ -196,6 +249,7 @@ fn no_arg_message(input: &[u8]) -> HWResult<HWProtocolMessage> {
         |i| messagec(i, "TOGGLE_RESTRICT_JOINS", ToggleRestrictJoin),
         |i| messagec(i, "TOGGLE_RESTRICT_TEAMS", ToggleRestrictTeams),
         |i| messagec(i, "TOGGLE_REGISTERED_ONLY", ToggleRegisteredOnly),
+        |i| messagec(i, "READY", CheckerReady),
     ))(input)
 }
 
@@ -232,12 +286,23 @@ fn single_arg_message(input: &[u8]) -> HWResult<HWProtocolMessage> {
 }
 
 fn cmd_message<'a>(input: &'a [u8]) -> HWResult<'a, HWProtocolMessage> {
+    /// Once the name of a built-in command matches, its arguments must parse,
+    /// so a malformed built-in command is not taken for a custom one
+    fn cmdc<'a, T, F>(input: &'a [u8], name: &'a str, parser: F) -> HWResult<'a, T>
+    where
+        F: Fn(&'a [u8]) -> HWResult<'a, T>,
+    {
+        let (i, _) = hw_tag_no_case(name)(input)?;
+        let (i, _) = peek!(i, alt((hw_tag(" "), hw_tag("\n\n"))))?;
+        cutc(i, parser)
+    }
+
     fn cmdc_no_arg<'a>(
         input: &'a [u8],
         name: &'a str,
         msg: HWProtocolMessage,
     ) -> HWResult<'a, HWProtocolMessage> {
-        tag_no_case(name)(input).map(|(i, _)| (i, msg.clone()))
+        cmdc(input, name, |i| peek!(i, end_of_message)).map(|(i, _)| (i, msg.clone()))
     }
 
     fn cmdc_single_arg<'a, T, F, G>(
@@ -250,8 +315,7 @@ fn cmd_message<'a>(input: &'a [u8]) -> HWResult<'a, HWProtocolMessage> {
         F: Fn(&'a [u8]) -> HWResult<'a, T>,
         G: Fn(T) -> HWProtocolMessage,
     {
-        precededc(input, |i| pairc(i, hw_tag_no_case(name), spaces), parser)
-            .map(|(i, v)| (i, constructor(v)))
+        cmdc(input, name, |i| precededc(i, spaces, &parser)).map(|(i, v)| (i, constructor(v)))
     }
 
     fn cmd_no_arg_message(input: &[u8]) -> HWResult<HWProtocolMessage> {
@@ -261,6 +325,9 @@ fn cmd_message<'a>(input: &'a [u8]) -> HWResult<'a, HWProtocolMessage> {
             |i| cmdc_no_arg(i, "UNFIX", Unfix),
             |i| cmdc_no_arg(i, "REGISTERED_ONLY", ToggleServerRegisteredOnly),
             |i| cmdc_no_arg(i, "SUPER_POWER", SuperPower),
+            |i| cmdc_no_arg(i, "PRESETS", ListPresets),
+            |i| cmdc_no_arg(i, "ANNOUNCEMENTS", ListAnnouncements),
+            |i| cmdc_no_arg(i, "LEADERBOARD", Leaderboard),
         ))(input)
     }
 
@@ -271,13 +338,20 @@ fn cmd_message<'a>(input: &'a [u8]) -> HWResult<'a, HWProtocolMessage> {
             |i| cmdc_single_arg(i, "DELETE", a_line, Delete),
             |i| cmdc_single_arg(i, "SAVEROOM", a_line, SaveRoom),
             |i| cmdc_single_arg(i, "LOADROOM", a_line, LoadRoom),
+            |i| cmdc_single_arg(i, "LOADPRESET", a_line, LoadPreset),
+            |i| cmdc_single_arg(i, "SAVEPRESET", a_line, SavePreset),
+            |i| cmdc_single_arg(i, "DELETEPRESET", a_line, DeletePreset),
+            |i| cmdc_single_arg(i, "PUBLISHPRESET", a_line, PublishPreset),
+            |i| cmdc_single_arg(i, "UNPUBLISHPRESET", a_line, UnpublishPreset),
             |i| cmdc_single_arg(i, "GLOBAL", a_line, Global),
+            |i| cmdc_single_arg(i, "UNMUTE", a_line, Unmute),
             |i| cmdc_single_arg(i, "WATCH", u32_line, Watch),
             |i| cmdc_single_arg(i, "GREETING", a_line, Greeting),
             |i| cmdc_single_arg(i, "VOTE", yes_no_line, Vote),
             |i| cmdc_single_arg(i, "FORCE", yes_no_line, ForceVote),
             |i| cmdc_single_arg(i, "INFO", a_line, Info),
             |i| cmdc_single_arg(i, "MAXTEAMS", u8_line, MaxTeams),
+            |i| cmdc_single_arg(i, "AUTOBALANCE", yes_no_line, AutoBalance),
             |i| cmdc_single_arg(i, "CALLVOTE", |i| opt!(i, voting), CallVote),
         ))(input)
     }
@@ -288,10 +362,19 @@ fn cmd_message<'a>(input: &'a [u8]) -> HWResult<'a, HWProtocolMessage> {
         alt((
             cmd_no_arg_message,
             cmd_single_arg_message,
-            |i| precededc(i, hw_tag_no_case("PART"), opt_space_arg).map(|(i, s)| (i, Part(s))),
-            |i| precededc(i, hw_tag_no_case("QUIT"), opt_space_arg).map(|(i, s)| (i, Quit(s))),
+            |i| cmdc(i, "PART", opt_space_arg).map(|(i, s)| (i, Part(s))),
+            |i| cmdc(i, "QUIT", opt_space_arg).map(|(i, s)| (i, Quit(s))),
+            |i| {
+                cmdc(i, "BALANCE", |i| {
+                    alt((
+                        |i: &'a [u8]| peek!(i, end_of_message).map(|(i, _)| (i, None)),
+                        |i| precededc(i, spaces, u8_line).map(|(i, n)| (i, Some(n))),
+                    ))(i)
+                })
+                .map(|(i, n)| (i, Balance(n)))
+            },
             |i| {
-                precededc(i, hw_tag_no_case("SAVE"), |i| {
+                cmdc(i, "SAVE", |i| {
                     pairc(
                         i,
                         |i| precededc(i, spaces, cmd_arg),
@@ -301,10 +384,54 @@ fn cmd_message<'a>(input: &'a [u8]) -> HWResult<'a, HWProtocolMessage> {
                 .map(|(i, (n, l))| (i, Save(n, l)))
             },
             |i| {
-                let (i, _) = tag_no_case("RND")(i)?;
-                let (i, _) = alt((spaces, |i: &'a [u8]| peek!(i, end_of_message)))(i)?;
-                let (i, v) = str_line(i)?;
-                Ok((i, Rnd(v.split_whitespace().map(String::from).collect())))
+                cmdc(i, "MUTE", |i| {
+                    let (i, nick) = precededc(i, spaces, cmd_arg)?;
+                    let (i, duration) = precededc(i, spaces, duration_arg)?;
+                    let (i, reason) = opt_space_arg(i)?;
+                    Ok((i, Mute(nick, duration, reason)))
+                })
+            },
+            |i| cmdc_single_arg(i, "CANCELANNOUNCEMENT", u32_line, CancelAnnouncement),
+            |i| {
+                cmdc(i, "MSG", |i| {
+                    let (i, nick) = precededc(i, spaces, cmd_arg)?;
+                    let (i, msg) = precededc(i, spaces, a_line)?;
+                    Ok((i, PrivateMessage(nick, msg)))
+                })
+            },
+            |i| cmdc_single_arg(i, "RANKED", a_line, CreateRankedRoom),
+            |i| cmdc(i, "RATING", opt_space_arg).map(|(i, s)| (i, Rating(s))),
+            |i| {
+                cmdc(i, "ANNOUNCE", |i| {
+                    let (i, target) = precededc(i, spaces, announcement_target)?;
+                    let (i, delay) = opt!(i, |i| precededc(
+                        i,
+                        |i| pairc(i, spaces, hw_tag_no_case("IN")),
+                        |i| precededc(i, spaces, duration_arg)
+                    ))?;
+                    let (i, interval) = opt!(i, |i| precededc(
+                        i,
+                        |i| pairc(i, spaces, hw_tag_no_case("EVERY")),
+                        |i| precededc(i, spaces, duration_arg)
+                    ))?;
+                    let (i, msg) = precededc(i, spaces, a_line)?;
+                    Ok((i, Announce(target, delay, interval, msg)))
+                })
+            },
+            |i| {
+                cmdc(i, "RND", |i| {
+                    let (i, _) = alt((spaces, |i: &'a [u8]| peek!(i, end_of_message)))(i)?;
+                    let (i, v) = str_line(i)?;
+                    Ok((i, Rnd(v.split_whitespace().map(String::from).collect())))
+                })
+            },
+            |i| {
+                let (i, name) = cmd_arg(i)?;
+                if name.is_empty() {
+                    return Err(Err::Error(HWProtocolError::new()));
+                }
+                let (i, args) = opt_space_arg(i)?;
+                Ok((i, CustomCommand(name, args)))
             },
         )),
     )
@@ -528,6 +655,30 @@ fn complex_message(input: &[u8]) -> HWResult<HWProtocolMessage> {
                 },
             )
         },
+        |i| {
+            precededc(
+                i,
+                |i| terminatedc(i, hw_tag("CHECKED"), eol),
+                |i| {
+                    alt((
+                        |i| {
+                            precededc(i, |i| terminatedc(i, hw_tag("FAIL"), eol), a_line)
+                                .map(|(i, msg)| (i, CheckedFail(msg)))
+                        },
+                        |i| {
+                            let (mut i, _) = hw_tag("OK")(i)?;
+                            let mut info = vec![];
+                            while peek!(i, end_of_message).is_err() {
+                                let (rest, line) = precededc(i, eol, a_line)?;
+                                info.push(line);
+                                i = rest;
+                            }
+                            Ok((i, CheckedOk(info)))
+                        },
+                    ))(i)
+                },
+            )
+        },
     ))(input)
 }
 
@@ -566,6 +717,7 @@ mod test {
     use super::{extract_messages, message};
     use crate::protocol::parser::HWProtocolError;
     use crate::protocol::{messages::HWProtocolMessage::*, test::gen_proto_msg};
+    use crate::server::coretypes::AnnouncementTarget;
     use proptest::{proptest, proptest_helper};
 
     #[cfg(test)]
@@ -607,6 +759,122 @@ mod test {
         );
 
         assert_eq!(message(b"CMD\nRND\n\n"), Ok((&b""[..], Rnd(vec![]))));
+        assert_eq!(
+            message(b"CMD\nGREET all of you\n\n"),
+            Ok((
+                &b""[..],
+                CustomCommand("GREET".to_string(), Some("all of you".to_string()))
+            ))
+        );
+        assert_eq!(
+            message(b"CMD\nMUTE troll\n\n"),
+            Err(nom::Err::Failure(HWProtocolError::new()))
+        );
+        assert_eq!(
+            message(b"CMD\nsave room\n\n"),
+            Err(nom::Err::Failure(HWProtocolError::new()))
+        );
+        assert_eq!(
+            message(b"CMD\nSTATS now\n\n"),
+            Err(nom::Err::Failure(HWProtocolError::new()))
+        );
+        assert_eq!(
+            message(b"CMD\nSTATSALL\n\n"),
+            Ok((&b""[..], CustomCommand("STATSALL".to_string(), None)))
+        );
+        assert_eq!(message(b"CMD\nBALANCE\n\n"), Ok((&b""[..], Balance(None))));
+        assert_eq!(
+            message(b"CMD\nbalance 3\n\n"),
+            Ok((&b""[..], Balance(Some(3))))
+        );
+        assert_eq!(
+            message(b"CMD\nAUTOBALANCE yes\n\n"),
+            Ok((&b""[..], AutoBalance(true)))
+        );
+        assert_eq!(
+            message(b"CMD\nMUTE troll 1h10m spam\n\n"),
+            Ok((
+                &b""[..],
+                Mute("troll".to_string(), 4200, Some("spam".to_string()))
+            ))
+        );
+        assert_eq!(
+            message(b"CMD\nmute troll 30\n\n"),
+            Ok((&b""[..], Mute("troll".to_string(), 30, None)))
+        );
+        assert_eq!(
+            message(b"CMD\nUNMUTE troll\n\n"),
+            Ok((&b""[..], Unmute("troll".to_string())))
+        );
+        assert_eq!(
+            message(b"CMD\nANNOUNCE lobby IN 5m EVERY 1h Tournament soon\n\n"),
+            Ok((
+                &b""[..],
+                Announce(
+                    AnnouncementTarget::Lobby,
+                    Some(300),
+                    Some(3600),
+                    "Tournament soon".to_string()
+                )
+            ))
+        );
+        assert_eq!(
+            message(b"CMD\nANNOUNCE 57-58 in the evening\n\n"),
+            Ok((
+                &b""[..],
+                Announce(
+                    AnnouncementTarget::Protocols(57, 58),
+                    None,
+                    None,
+                    "in the evening".to_string()
+                )
+            ))
+        );
+        assert_eq!(
+            message(b"CMD\nCANCELANNOUNCEMENT 2\n\n"),
+            Ok((&b""[..], CancelAnnouncement(2)))
+        );
+        assert_eq!(
+            message(b"CMD\nRANKED Ladder\n\n"),
+            Ok((&b""[..], CreateRankedRoom("Ladder".to_string())))
+        );
+        assert_eq!(message(b"CMD\nRATING\n\n"), Ok((&b""[..], Rating(None))));
+        assert_eq!(
+            message(b"CMD\nMSG bob see you later\n\n"),
+            Ok((
+                &b""[..],
+                PrivateMessage("bob".to_string(), "see you later".to_string())
+            ))
+        );
+        assert_eq!(
+            message(b"CMD\nrating troll\n\n"),
+            Ok((&b""[..], Rating(Some("troll".to_string()))))
+        );
+        assert_eq!(
+            message(b"CMD\nLEADERBOARD\n\n"),
+            Ok((&b""[..], Leaderboard))
+        );
+        assert_eq!(message(b"READY\n\n"), Ok((&b""[..], CheckerReady)));
+        assert_eq!(
+            message(b"CHECKED\nOK\nWINNERS\n1\nRed team\n\n"),
+            Ok((
+                &b""[..],
+                CheckedOk(vec![
+                    "WINNERS".to_string(),
+                    "1".to_string(),
+                    "Red team".to_string()
+                ])
+            ))
+        );
+        assert_eq!(
+            message(b"CHECKED\nFAIL\nerror\n\n"),
+            Ok((&b""[..], CheckedFail("error".to_string())))
+        );
+        assert_eq!(message(b"CMD\nPRESETS\n\n"), Ok((&b""[..], ListPresets)));
+        assert_eq!(
+            message(b"CMD\nLOADPRESET Big map\n\n"),
+            Ok((&b""[..], LoadPreset("Big map".to_string())))
+        );
         assert_eq!(
             message(b"CMD\nRND A B\n\n"),
             Ok((&b""[..], Rnd(vec![String::from("A"), String::from("B")])))
@@ -167,7 +167,7 @@ impl Arbitrary for ServerVar {
 }
 
 pub fn gen_proto_msg() -> BoxedStrategy<HWProtocolMessage> where {
-    let res = (0..=55).no_shrink().prop_flat_map(|i| {
+    let res = (0..=61).no_shrink().prop_flat_map(|i| {
         proto_msg_match!(i, def = Ping,
             0 => Ping(),
             1 => Pong(),
@@ -223,7 +223,13 @@ pub fn gen_proto_msg() -> BoxedStrategy<HWProtocolMessage> where {
             52 => Save(Ascii, Ascii),
             53 => Delete(Ascii),
             54 => SaveRoom(Ascii),
-            55 => LoadRoom(Ascii)
+            55 => LoadRoom(Ascii),
+            56 => ListPresets(),
+            57 => LoadPreset(Ascii),
+            58 => SavePreset(Ascii),
+            59 => DeletePreset(Ascii),
+            60 => PublishPreset(Ascii),
+            61 => UnpublishPreset(Ascii)
         )
     });
     res.boxed()
@@ -1,12 +1,25 @@
 mod actions;
+pub mod announcements;
+pub mod assets;
+pub mod chat_history;
 pub mod client;
+pub mod config;
 pub mod core;
 pub mod coretypes;
 #[cfg(feature = "official-server")]
 mod database;
+mod demo;
+mod desync;
+pub mod federation;
 mod handlers;
+pub mod hooks;
 pub mod indexslab;
 #[cfg(feature = "official-server")]
 pub mod io;
 pub mod network;
+#[cfg(feature = "federation")]
+mod peers;
+pub mod rating;
 pub mod room;
+pub mod room_library;
+mod websocket;
@@ -0,0 +1,107 @@
+use super::coretypes::AnnouncementTarget;
+use std::time::{Duration, Instant};
+
+pub struct Announcement {
+    pub id: u32,
+    pub message: String,
+    pub target: AnnouncementTarget,
+    pub next_time: Instant,
+    /// Recurring announcements are rescheduled after this interval
+    pub interval: Option<Duration>,
+}
+
+/// Announcements delivered by the server timer
+pub struct Announcements {
+    next_id: u32,
+    items: Vec<Announcement>,
+}
+
+impl Announcements {
+    pub fn new() -> Self {
+        Self {
+            next_id: 1,
+            items: Vec::new(),
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        message: String,
+        target: AnnouncementTarget,
+        next_time: Instant,
+        interval: Option<Duration>,
+    ) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.items.push(Announcement {
+            id,
+            message,
+            target,
+            next_time,
+            interval: interval.filter(|i| *i > Duration::from_secs(0)),
+        });
+        id
+    }
+
+    pub fn cancel(&mut self, id: u32) -> bool {
+        let count = self.items.len();
+        self.items.retain(|a| a.id != id);
+        self.items.len() < count
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Announcement> {
+        self.items.iter()
+    }
+
+    /// Returns the announcements that are due, reschedules the recurring ones
+    /// and removes the rest
+    pub fn take_due(&mut self, now: Instant) -> Vec<(AnnouncementTarget, String)> {
+        let mut due = vec![];
+        for announcement in &mut self.items {
+            if announcement.next_time <= now {
+                due.push((announcement.target.clone(), announcement.message.clone()));
+                if let Some(interval) = announcement.interval {
+                    while announcement.next_time <= now {
+                        announcement.next_time += interval;
+                    }
+                }
+            }
+        }
+        self.items
+            .retain(|a| a.interval.is_some() || a.next_time > now);
+        due
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn due_announcements() {
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        let mut announcements = Announcements::new();
+        let once = announcements.add("once".to_string(), AnnouncementTarget::All, start, None);
+        announcements.add(
+            "recurring".to_string(),
+            AnnouncementTarget::Lobby,
+            start + minute,
+            Some(minute),
+        );
+
+        assert_eq!(announcements.take_due(start).len(), 1);
+        assert!(!announcements.cancel(once));
+        assert!(announcements.take_due(start).is_empty());
+
+        let due = announcements.take_due(start + minute * 3);
+        assert_eq!(
+            due,
+            vec![(AnnouncementTarget::Lobby, "recurring".to_string())]
+        );
+        assert_eq!(
+            announcements.iter().next().map(|a| a.next_time),
+            Some(start + minute * 4)
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use crate::server::coretypes::{GameCfg, TeamInfo};
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_GRAVE: &str = "Statue";
+pub const DEFAULT_FORT: &str = "Castle";
+pub const DEFAULT_VOICE_PACK: &str = "Default";
+pub const DEFAULT_FLAG: &str = "hedgewars";
+pub const DEFAULT_HAT: &str = "NoHat";
+
+const BUILTIN_MAP_TYPES: &[&str] = &["+rnd+", "+maze+", "+drawn+", "+perlin+", "+forts+"];
+const BUILTIN_SCRIPTS: &[&str] = &["Normal"];
+
+fn entries(path: &Path) -> io::Result<impl Iterator<Item = (PathBuf, bool)>> {
+    Ok(fs::read_dir(path)?.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let is_dir = entry.file_type().ok()?.is_dir();
+        Some((entry.path(), is_dir))
+    }))
+}
+
+fn dir_names(path: &Path) -> io::Result<HashSet<String>> {
+    Ok(entries(path)?
+        .filter(|(_, is_dir)| *is_dir)
+        .filter_map(|(path, _)| Some(path.file_name()?.to_str()?.to_string()))
+        .collect())
+}
+
+fn file_stems(path: &Path, extensions: &[&str]) -> io::Result<HashSet<String>> {
+    Ok(entries(path)?
+        .filter(|(_, is_dir)| !*is_dir)
+        .filter(|(path, _)| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .map_or(false, |e| extensions.contains(&e))
+        })
+        .filter_map(|(path, _)| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect())
+}
+
+/// Names of the assets available in the game data directory
+/// (usually `share/hedgewars/Data`)
+pub struct AssetIndex {
+    graves: HashSet<String>,
+    forts: HashSet<String>,
+    voice_packs: HashSet<String>,
+    flags: HashSet<String>,
+    hats: HashSet<String>,
+    themes: HashSet<String>,
+    scripts: HashSet<String>,
+    maps: HashSet<String>,
+}
+
+impl AssetIndex {
+    pub fn load<P: AsRef<Path>>(data_dir: P) -> io::Result<Self> {
+        let data_dir = data_dir.as_ref();
+
+        // forts are stored as a pair of images, e.g. CastleL.png and CastleR.png
+        let forts = file_stems(&data_dir.join("Forts"), &["png"])?
+            .into_iter()
+            .filter(|name| name.ends_with('L'))
+            .map(|name| name[..name.len() - 1].to_string())
+            .collect();
+
+        let mut hats = file_stems(&data_dir.join("Graphics/Hats"), &["png"])?;
+        if let Ok(reserved) = file_stems(&data_dir.join("Graphics/Hats/Reserved"), &["png"]) {
+            // reserved hats are prefixed with the 32 character hash of the owner
+            hats.extend(
+                reserved
+                    .iter()
+                    .filter(|name| name.len() > 32 && name.is_char_boundary(32))
+                    .map(|name| format!("Reserved {}", &name[32..])),
+            );
+        }
+
+        let mut scripts = file_stems(&data_dir.join("Scripts/Multiplayer"), &["lua", "hwp"])?;
+        scripts.extend(BUILTIN_SCRIPTS.iter().map(|s| s.to_string()));
+
+        let mut maps = dir_names(&data_dir.join("Maps"))?;
+        maps.extend(BUILTIN_MAP_TYPES.iter().map(|s| s.to_string()));
+
+        Ok(Self {
+            graves: file_stems(&data_dir.join("Graphics/Graves"), &["png"])?,
+            forts,
+            voice_packs: dir_names(&data_dir.join("Sounds/voices"))?,
+            flags: file_stems(&data_dir.join("Graphics/Flags"), &["png"])?,
+            hats,
+            themes: dir_names(&data_dir.join("Themes"))?,
+            scripts,
+            maps,
+        })
+    }
+
+    /// Replaces the unknown assets of the team with the defaults,
+    /// returns the descriptions of the replaced values
+    pub fn validate_team(&self, team: &mut TeamInfo) -> Vec<String> {
+        let mut replaced = vec![];
+        let mut check = |set: &HashSet<String>, value: &mut String, default: &str, kind: &str| {
+            if !set.contains(value) {
+                replaced.push(format!("{} '{}'", kind, value));
+                value.replace_range(.., default);
+            }
+        };
+
+        check(&self.graves, &mut team.grave, DEFAULT_GRAVE, "grave");
+        check(&self.forts, &mut team.fort, DEFAULT_FORT, "fort");
+        check(
+            &self.voice_packs,
+            &mut team.voice_pack,
+            DEFAULT_VOICE_PACK,
+            "voice pack",
+        );
+        check(&self.flags, &mut team.flag, DEFAULT_FLAG, "flag");
+        for hedgehog in team.hedgehogs.iter_mut() {
+            check(&self.hats, &mut hedgehog.hat, DEFAULT_HAT, "hat");
+        }
+        replaced
+    }
+
+    /// Checks the names of the theme, the script and the map in the config,
+    /// other values are always considered valid
+    pub fn is_cfg_valid(&self, cfg: &GameCfg) -> bool {
+        match cfg {
+            GameCfg::Theme(name) => self.themes.contains(name),
+            GameCfg::Script(name) => self.scripts.contains(&name.replace(' ', "_")),
+            GameCfg::MapType(name) => self.maps.contains(name),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::coretypes::HedgehogInfo;
+
+    fn create_data_dir() -> PathBuf {
+        let data_dir = std::env::temp_dir().join(format!("hw-assets-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        for dir in &[
+            "Graphics/Graves",
+            "Graphics/Flags",
+            "Graphics/Hats/Reserved",
+            "Forts",
+            "Sounds/voices/Default",
+            "Themes/Nature",
+            "Scripts/Multiplayer",
+            "Maps/Bamboo",
+        ] {
+            fs::create_dir_all(data_dir.join(dir)).unwrap();
+        }
+        for file in &[
+            "Graphics/Graves/Statue.png",
+            "Graphics/Flags/hedgewars.png",
+            "Graphics/Hats/NoHat.png",
+            "Graphics/Hats/crown.png",
+            "Graphics/Hats/Reserved/0123456789abcdef0123456789abcdefchef.png",
+            "Forts/CastleL.png",
+            "Forts/CastleR.png",
+            "Scripts/Multiplayer/Capture_the_Flag.lua",
+        ] {
+            fs::write(data_dir.join(file), b"").unwrap();
+        }
+        data_dir
+    }
+
+    fn team(fort: &str, flag: &str, hats: &[&str]) -> TeamInfo {
+        let hedgehog = |i: usize| HedgehogInfo {
+            name: format!("hedgehog {}", i),
+            hat: hats.get(i).unwrap_or(&DEFAULT_HAT).to_string(),
+        };
+        TeamInfo {
+            owner: String::new(),
+            name: "team".to_string(),
+            color: 0,
+            grave: DEFAULT_GRAVE.to_string(),
+            fort: fort.to_string(),
+            voice_pack: DEFAULT_VOICE_PACK.to_string(),
+            flag: flag.to_string(),
+            difficulty: 0,
+            hedgehogs_number: 4,
+            hedgehogs: [
+                hedgehog(0),
+                hedgehog(1),
+                hedgehog(2),
+                hedgehog(3),
+                hedgehog(4),
+                hedgehog(5),
+                hedgehog(6),
+                hedgehog(7),
+            ],
+        }
+    }
+
+    #[test]
+    fn validation() {
+        let data_dir = create_data_dir();
+        let index = AssetIndex::load(&data_dir);
+        fs::remove_dir_all(&data_dir).unwrap();
+        let index = index.unwrap();
+
+        let mut valid_team = team("Castle", DEFAULT_FLAG, &["crown", "Reserved chef"]);
+        assert!(index.validate_team(&mut valid_team).is_empty());
+
+        let mut invalid_team = team("CastleL", "pirates", &["crown", "chef"]);
+        assert_eq!(
+            index.validate_team(&mut invalid_team),
+            vec!["fort 'CastleL'", "flag 'pirates'", "hat 'chef'"]
+        );
+        assert_eq!(invalid_team.fort, DEFAULT_FORT);
+        assert_eq!(invalid_team.flag, DEFAULT_FLAG);
+        assert_eq!(invalid_team.hedgehogs[0].hat, "crown");
+        assert_eq!(invalid_team.hedgehogs[1].hat, DEFAULT_HAT);
+
+        for cfg in vec![
+            GameCfg::Theme("Nature".to_string()),
+            GameCfg::Script("Normal".to_string()),
+            GameCfg::Script("Capture the Flag".to_string()),
+            GameCfg::MapType("Bamboo".to_string()),
+            GameCfg::MapType("+forts+".to_string()),
+            GameCfg::Seed("seed".to_string()),
+        ] {
+            assert!(index.is_cfg_valid(&cfg), "{:?}", cfg);
+        }
+        for cfg in vec![
+            GameCfg::Theme("../Nature".to_string()),
+            GameCfg::Script("Racing".to_string()),
+            GameCfg::MapType("Cave".to_string()),
+        ] {
+            assert!(!index.is_cfg_valid(&cfg), "{:?}", cfg);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub struct ChatEntry {
+    pub nick: String,
+    pub msg: String,
+    pub time: Instant,
+}
+
+pub struct ChatHistory {
+    entries: VecDeque<ChatEntry>,
+    capacity: usize,
+}
+
+impl ChatHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, nick: String, msg: String) {
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(ChatEntry {
+                nick,
+                msg,
+                time: Instant::now(),
+            });
+        }
+    }
+
+    pub fn remove_nick(&mut self, nick: &str) {
+        self.entries.retain(|e| e.nick != nick);
+    }
+
+    pub fn recent(&self, max_age: Option<Duration>) -> impl Iterator<Item = &ChatEntry> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(move |e| max_age.map_or(true, |age| now.duration_since(e.time) <= age))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_history() {
+        let mut history = ChatHistory::new(2);
+        history.push("a".to_string(), "1".to_string());
+        history.push("b".to_string(), "2".to_string());
+        history.push("a".to_string(), "3".to_string());
+
+        let msgs: Vec<_> = history.recent(None).map(|e| &e.msg[..]).collect();
+        assert_eq!(msgs, vec!["2", "3"]);
+
+        history.remove_nick("a");
+        let msgs: Vec<_> = history.recent(None).map(|e| &e.msg[..]).collect();
+        assert_eq!(msgs, vec!["2"]);
+
+        let mut disabled = ChatHistory::new(0);
+        disabled.push("a".to_string(), "1".to_string());
+        assert_eq!(disabled.recent(None).count(), 0);
+    }
+}
@@ -1,5 +1,6 @@
-use super::coretypes::ClientId;
+use super::coretypes::{ClientId, RoomId};
 use bitflags::*;
+use std::time::Instant;
 
 bitflags! {
     pub struct ClientFlags: u16 {
@@ -18,6 +19,14 @@ bitflags! {
     }
 }
 
+#[derive(Clone)]
+pub struct Mute {
+    /// The room the mute applies to, the mute is server-wide if not set
+    pub room_id: Option<RoomId>,
+    pub until: Instant,
+    pub reason: String,
+}
+
 pub struct HWClient {
     pub id: ClientId,
     pub room_id: Option<usize>,
@@ -27,6 +36,12 @@ pub struct HWClient {
     pub teams_in_game: u8,
     pub team_indices: Vec<u8>,
     pub clan: Option<u8>,
+    pub last_activity: Instant,
+    /// Set when the room master is warned about losing the master status for being idle
+    pub master_idle_deadline: Option<Instant>,
+    /// Set when the player is warned about being moved to the lobby for being idle
+    pub player_idle_deadline: Option<Instant>,
+    pub mutes: Vec<Mute>,
 }
 
 impl HWClient {
@@ -40,9 +55,29 @@ impl HWClient {
             teams_in_game: 0,
             team_indices: Vec::new(),
             clan: None,
+            last_activity: Instant::now(),
+            master_idle_deadline: None,
+            player_idle_deadline: None,
+            mutes: Vec::new(),
         }
     }
 
+    /// Returns the longest of the mutes applied to the client at the moment
+    pub fn active_mute(&self, now: Instant) -> Option<&Mute> {
+        self.mutes
+            .iter()
+            .filter(|m| m.until > now && (m.room_id.is_none() || m.room_id == self.room_id))
+            .max_by_key(|m| m.until)
+    }
+
+    /// Replaces the mute with the same scope
+    pub fn add_mute(&mut self, mute: Mute) {
+        let now = Instant::now();
+        self.mutes
+            .retain(|m| m.until > now && m.room_id != mute.room_id);
+        self.mutes.push(mute);
+    }
+
     fn contains(&self, mask: ClientFlags) -> bool {
         self.flags.contains(mask)
     }
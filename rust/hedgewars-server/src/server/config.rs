@@ -0,0 +1,210 @@
+use super::coretypes::AnnouncementTarget;
+use serde_derive::Deserialize;
+use serde_yaml;
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Result},
+    time::Duration,
+};
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ChatHistoryConfig {
+    /// Number of messages kept for the lobby, 0 disables the history
+    pub lobby_size: usize,
+    /// Number of messages kept for each room, 0 disables the history
+    pub room_size: usize,
+    /// Messages older than this many seconds are not replayed
+    pub max_age: Option<u64>,
+}
+
+impl ChatHistoryConfig {
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age.map(Duration::from_secs)
+    }
+}
+
+impl Default for ChatHistoryConfig {
+    fn default() -> Self {
+        Self {
+            lobby_size: 20,
+            room_size: 20,
+            max_age: None,
+        }
+    }
+}
+
+/// Idle timeouts in seconds, each check is disabled if its timeout is not set
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct IdleConfig {
+    /// The room master status is passed to another player after this timeout
+    pub master_timeout: Option<u64>,
+    /// Players of a room waiting for the game are moved to the lobby after this timeout
+    pub player_timeout: Option<u64>,
+    /// The players are warned this many seconds before the action is taken
+    pub warning_time: u64,
+}
+
+impl IdleConfig {
+    pub fn master_timeout(&self) -> Option<Duration> {
+        self.master_timeout.map(Duration::from_secs)
+    }
+
+    pub fn player_timeout(&self) -> Option<Duration> {
+        self.player_timeout.map(Duration::from_secs)
+    }
+
+    pub fn warning_time(&self) -> Duration {
+        Duration::from_secs(self.warning_time)
+    }
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            master_timeout: None,
+            player_timeout: None,
+            warning_time: 60,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct IoConfig {
+    /// Number of the threads running the database and file tasks
+    pub workers: usize,
+    /// Tasks submitted while the queue is full are rejected
+    pub queue_size: usize,
+    /// Seconds before a task is reported as failed
+    pub task_timeout: u64,
+    /// MySQL database URL, the account and replay lookups fail without it
+    pub database_url: Option<String>,
+}
+
+impl IoConfig {
+    pub fn task_timeout(&self) -> Duration {
+        Duration::from_secs(self.task_timeout)
+    }
+}
+
+impl Default for IoConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            queue_size: 256,
+            task_timeout: 30,
+            database_url: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct RankedConfig {
+    /// Name of the public preset the ranked rooms are locked to, ranked rooms are disabled without it
+    pub preset: Option<String>,
+    /// Rating of the players before their first ranked game
+    pub initial_rating: i32,
+    /// Maximum rating change of a single game
+    pub k_factor: u32,
+    /// Number of the players shown on the leaderboard
+    pub leaderboard_size: u32,
+}
+
+impl Default for RankedConfig {
+    fn default() -> Self {
+        Self {
+            preset: None,
+            initial_rating: 1500,
+            k_factor: 32,
+            leaderboard_size: 10,
+        }
+    }
+}
+
+/// Links to the other servers sharing the lobby with this one
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FederationConfig {
+    /// Name of this server shown next to its rooms on the other servers
+    pub name: String,
+    /// Address the links of the other servers are accepted on
+    pub listen: Option<String>,
+    /// Addresses of the other servers this server links to
+    pub peers: Vec<String>,
+    /// Secret shared by all the servers of the federation
+    pub secret: String,
+    /// Host and port the clients are redirected to when joining a room of this server
+    pub public_host: String,
+    pub public_port: u16,
+}
+
+impl FederationConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.secret.is_empty() && (self.listen.is_some() || !self.peers.is_empty())
+    }
+}
+
+/// An announcement scheduled at startup
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AnnouncementConfig {
+    pub message: String,
+    pub target: AnnouncementTarget,
+    /// Seconds after the server start before the first delivery
+    pub delay: u64,
+    /// Seconds between the deliveries of a recurring announcement
+    pub interval: Option<u64>,
+}
+
+/// A room that is created at startup and is never removed
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PermanentRoomConfig {
+    pub name: String,
+    pub greeting: String,
+    /// Name of a public preset from the room config library
+    pub preset: Option<String>,
+    pub max_teams: Option<u8>,
+    /// Protocol number of the room, the latest protocol by default
+    pub protocol_number: Option<u16>,
+    pub fixed: bool,
+    pub restrict_join: bool,
+    pub restrict_teams: bool,
+    pub registered_only: bool,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub chat_history: ChatHistoryConfig,
+    pub idle: IdleConfig,
+    pub io: IoConfig,
+    /// Game data directory used to validate team assets and room configs
+    pub data_dir: Option<String>,
+    /// Directory of the room config library, `RoomConfigs` by default
+    pub room_library_dir: Option<String>,
+    pub permanent_rooms: Vec<PermanentRoomConfig>,
+    pub announcements: Vec<AnnouncementConfig>,
+    pub ranked: RankedConfig,
+    pub federation: FederationConfig,
+    /// File the desync reports are appended to
+    pub desync_log: Option<String>,
+}
+
+impl ServerConfig {
+    pub fn room_library_dir(&self) -> &str {
+        self.room_library_dir
+            .as_ref()
+            .map_or("RoomConfigs", |dir| &dir[..])
+    }
+
+    pub fn from_file(filename: &str) -> Result<Self> {
+        let mut reader = File::open(filename)?;
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        serde_yaml::from_str(&contents).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
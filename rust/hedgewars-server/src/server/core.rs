@@ -1,8 +1,15 @@
 use super::{
-    client::HWClient,
-    coretypes::{ClientId, RoomId},
+    announcements::Announcements,
+    assets::AssetIndex,
+    chat_history::ChatHistory,
+    client::{HWClient, Mute},
+    config::{PermanentRoomConfig, ServerConfig},
+    coretypes::{ClientId, RankedGame, RoomConfig, RoomId},
+    federation::Federation,
+    hooks::ServerHooks,
     indexslab::IndexSlab,
-    room::HWRoom,
+    room::{HWRoom, MAX_TEAMS_IN_ROOM},
+    room_library::{PresetScope, RoomLibrary},
 };
 use crate::utils;
 
@@ -10,10 +17,18 @@ use crate::protocol::messages::HWProtocolMessage::Greeting;
 use bitflags::*;
 use log::*;
 use slab;
-use std::{borrow::BorrowMut, iter, num::NonZeroU16};
+use std::{
+    borrow::BorrowMut,
+    collections::{HashMap, VecDeque},
+    iter,
+    num::NonZeroU16,
+    time::{Duration, Instant},
+};
 
 type Slab<T> = slab::Slab<T>;
 
+const MAX_UNCHECKED_GAMES: usize = 256;
+
 pub struct HWAnteClient {
     pub nick: Option<String>,
     pub protocol_number: Option<NonZeroU16>,
@@ -74,22 +89,119 @@ pub struct HWServer {
     pub latest_protocol: u16,
     pub flags: ServerFlags,
     pub greetings: ServerGreetings,
+    pub config: ServerConfig,
+    pub lobby_chat: ChatHistory,
+    pub assets: Option<AssetIndex>,
+    pub hooks: Vec<Box<dyn ServerHooks>>,
+    /// Server-wide mutes of the registered players that are offline
+    pub saved_mutes: HashMap<String, Mute>,
+    pub announcements: Announcements,
+    /// Contents of the preset the ranked rooms are locked to
+    pub ranked_preset: Option<String>,
+    pub federation: Federation,
+    /// Finished ranked games waiting for a checker, the oldest first
+    pub unchecked_games: VecDeque<RankedGame>,
+    /// Ranked games being replayed, keyed by the checker
+    pub games_in_check: HashMap<ClientId, RankedGame>,
+    next_game_id: u32,
 }
 
 impl HWServer {
-    pub fn new(clients_limit: usize, rooms_limit: usize) -> Self {
+    pub fn new(clients_limit: usize, rooms_limit: usize, config: ServerConfig) -> Self {
         let rooms = Slab::with_capacity(rooms_limit);
         let clients = IndexSlab::with_capacity(clients_limit);
-        Self {
+        let mut server = Self {
             clients,
             rooms,
             anteroom: HWAnteroom::new(clients_limit),
             greetings: ServerGreetings::new(),
             latest_protocol: 58,
             flags: ServerFlags::empty(),
+            lobby_chat: ChatHistory::new(config.chat_history.lobby_size),
+            config,
+            assets: None,
+            hooks: Vec::new(),
+            saved_mutes: HashMap::new(),
+            announcements: Announcements::new(),
+            ranked_preset: None,
+            federation: Federation::new(),
+            unchecked_games: VecDeque::new(),
+            games_in_check: HashMap::new(),
+            next_game_id: 0,
+        };
+
+        let now = Instant::now();
+        for announcement in &server.config.announcements {
+            server.announcements.add(
+                announcement.message.clone(),
+                announcement.target.clone(),
+                now + Duration::from_secs(announcement.delay),
+                announcement.interval.map(Duration::from_secs),
+            );
+        }
+
+        let library = RoomLibrary::new(server.config.room_library_dir());
+        for room_config in server.config.permanent_rooms.clone() {
+            if utils::is_name_illegal(&room_config.name) || server.has_room(&room_config.name) {
+                warn!("Invalid permanent room name: {}", room_config.name);
+            } else {
+                server.create_permanent_room(&room_config, &library);
+            }
+        }
+
+        if let Some(ref preset) = server.config.ranked.preset {
+            let result = library
+                .load_preset(&PresetScope::Public, preset)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| {
+                    serde_yaml::from_str::<RoomConfig>(&contents)
+                        .map(|_| contents)
+                        .map_err(|e| e.to_string())
+                });
+            match result {
+                Ok(contents) => server.ranked_preset = Some(contents),
+                Err(e) => warn!("Unable to load the ranked preset {}: {}", preset, e),
+            }
+        }
+        server
+    }
+
+    fn create_permanent_room(&mut self, config: &PermanentRoomConfig, library: &RoomLibrary) {
+        let room = allocate_room(&mut self.rooms);
+        room.name = config.name.clone();
+        room.greeting = config.greeting.clone();
+        room.protocol_number = config.protocol_number.unwrap_or(self.latest_protocol);
+        room.max_teams = config
+            .max_teams
+            .unwrap_or(MAX_TEAMS_IN_ROOM)
+            .max(2)
+            .min(MAX_TEAMS_IN_ROOM);
+        room.chat_history = ChatHistory::new(self.config.chat_history.room_size);
+
+        room.set_is_permanent(true);
+        room.set_is_fixed(config.fixed);
+        room.set_join_restriction(config.restrict_join);
+        room.set_team_add_restriction(config.restrict_teams);
+        room.set_unregistered_players_restriction(config.registered_only);
+
+        if let Some(ref preset) = config.preset {
+            let result = library
+                .load_preset(&PresetScope::Public, preset)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| room.set_config_preset(&contents).map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                warn!(
+                    "Unable to load the preset {} for room {}: {}",
+                    preset, room.name, e
+                );
+            }
         }
     }
 
+    pub fn register_hooks(&mut self, hooks: Box<dyn ServerHooks>) {
+        self.hooks.push(hooks);
+    }
+
     pub fn add_client(&mut self, client_id: ClientId, data: HWAnteClient) {
         if let (Some(protocol), Some(nick)) = (data.protocol_number, data.nick) {
             let mut client = HWClient::new(client_id, protocol.get(), nick);
@@ -99,7 +211,47 @@ impl HWServer {
     }
 
     pub fn remove_client(&mut self, client_id: ClientId) {
-        self.clients.remove(client_id);
+        if let Some(game) = self.games_in_check.remove(&client_id) {
+            self.unchecked_games.push_front(game);
+        }
+        if let Some(client) = self.clients.remove(client_id) {
+            if client.is_registered() {
+                let now = Instant::now();
+                let mute = client
+                    .mutes
+                    .into_iter()
+                    .find(|m| m.room_id.is_none() && m.until > now);
+                if let Some(mute) = mute {
+                    self.saved_mutes.retain(|_, m| m.until > now);
+                    self.saved_mutes.insert(client.nick, mute);
+                }
+            }
+        }
+    }
+
+    /// Queues the game for a checker, the oldest game is dropped if the queue is full
+    pub fn add_unchecked_game(&mut self, teams: Vec<(String, String)>, demo: Vec<String>) {
+        if self.unchecked_games.len() >= MAX_UNCHECKED_GAMES {
+            if let Some(game) = self.unchecked_games.pop_front() {
+                warn!("Ranked game {} was dropped before it was checked", game.id);
+            }
+        }
+        self.next_game_id = self.next_game_id.wrapping_add(1);
+        self.unchecked_games.push_back(RankedGame {
+            id: self.next_game_id,
+            teams,
+            demo,
+        });
+    }
+
+    /// Restores the server-wide mute the registered client had when it left
+    pub fn restore_mute(&mut self, client_id: ClientId) {
+        let client = &mut self.clients[client_id];
+        if let Some(mute) = self.saved_mutes.remove(&client.nick) {
+            if mute.until > Instant::now() {
+                client.add_mute(mute);
+            }
+        }
     }
 
     pub fn get_greetings(&self, client_id: ClientId) -> &str {
@@ -117,12 +269,14 @@ impl HWServer {
         name: String,
         password: Option<String>,
     ) -> RoomId {
-        create_room(
+        let room_id = create_room(
             &mut self.clients[creator_id],
             &mut self.rooms,
             name,
             password,
-        )
+        );
+        self.rooms[room_id].chat_history = ChatHistory::new(self.config.chat_history.room_size);
+        room_id
     }
 
     #[inline]
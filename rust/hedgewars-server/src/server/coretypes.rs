@@ -159,6 +159,14 @@ pub struct Replay {
     pub message_log: Vec<String>,
 }
 
+/// A finished ranked game, the ratings are updated once a checker replays it
+pub struct RankedGame {
+    pub id: u32,
+    /// Names of the teams that took part in the game and the nicks of their owners
+    pub teams: Vec<(String, String)>,
+    pub demo: Vec<String>,
+}
+
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum VoteType {
     Kick(String),
@@ -168,6 +176,31 @@ pub enum VoteType {
     HedgehogsPerTeam(u8),
 }
 
+#[derive(PartialEq, Eq, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnouncementTarget {
+    All,
+    Lobby,
+    /// Clients with the protocol numbers in the inclusive range
+    Protocols(u16, u16),
+}
+
+impl AnnouncementTarget {
+    pub fn to_protocol(&self) -> String {
+        match self {
+            AnnouncementTarget::All => "ALL".to_string(),
+            AnnouncementTarget::Lobby => "LOBBY".to_string(),
+            AnnouncementTarget::Protocols(min, max) => format!("{}-{}", min, max),
+        }
+    }
+}
+
+impl Default for AnnouncementTarget {
+    fn default() -> Self {
+        AnnouncementTarget::All
+    }
+}
+
 pub struct Vote {
     pub is_pro: bool,
     pub is_forced: bool,
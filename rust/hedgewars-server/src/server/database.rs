@@ -2,7 +2,7 @@ use mysql;
 use mysql::{error::DriverError, error::Error, from_row_opt, params};
 use openssl::sha::sha1;
 
-use super::handlers::AccountInfo;
+use super::{handlers::AccountInfo, rating::PlayerRating};
 use crate::server::handlers::Sha1Digest;
 
 const GET_ACCOUNT_QUERY: &str =
@@ -18,6 +18,23 @@ const STORE_STATS_QUERY: &str = r"INSERT INTO gameserver_stats
 
 const GET_REPLAY_NAME_QUERY: &str = r"SELECT filename FROM achievements WHERE id = :id";
 
+const GET_RATING_QUERY: &str = r"SELECT users.name, ratings.rating, ratings.games
+     FROM gameserver_ratings AS ratings JOIN users ON users.uid = ratings.uid
+     WHERE users.name = :username";
+
+const LOCK_RATING_QUERY: &str = r"SELECT ratings.rating
+     FROM gameserver_ratings AS ratings JOIN users ON users.uid = ratings.uid
+     WHERE users.name = :username FOR UPDATE";
+
+const STORE_RATING_QUERY: &str = r"INSERT INTO gameserver_ratings
+            (uid, rating, games)
+            SELECT uid, :rating, 1 FROM users WHERE name = :username
+            ON DUPLICATE KEY UPDATE rating = :rating, games = games + 1";
+
+const GET_LEADERBOARD_QUERY: &str = r"SELECT users.name, ratings.rating, ratings.games
+     FROM gameserver_ratings AS ratings JOIN users ON users.uid = ratings.uid
+     ORDER BY ratings.rating DESC LIMIT :size";
+
 struct ServerStatistics {
     rooms: u32,
     players: u32,
@@ -79,6 +96,22 @@ impl Database {
         }
     }
 
+    /// Checkers send the stored password hash itself instead of salting it
+    pub fn is_checker_account(&mut self, nick: &str, password_hash: &str) -> Result<bool, Error> {
+        if let Some(pool) = &self.pool {
+            if let Some(row) = pool.first_exec(GET_ACCOUNT_QUERY, params! { "username" => nick })? {
+                let (mut password, is_admin, _) = from_row_opt::<(String, i32, i32)>(row)?;
+                let is_checker = !password.is_empty() && password == password_hash && is_admin == 1;
+                password.replace_range(.., "🦔🦔🦔🦔🦔🦔🦔🦔");
+                Ok(is_checker)
+            } else {
+                Ok(false)
+            }
+        } else {
+            Err(DriverError::SetupError.into())
+        }
+    }
+
     pub fn store_stats(&mut self, stats: &ServerStatistics) -> Result<(), Error> {
         if let Some(pool) = &self.pool {
             for mut stmt in pool.prepare(STORE_STATS_QUERY).into_iter() {
@@ -111,6 +144,80 @@ impl Database {
             Err(DriverError::SetupError.into())
         }
     }
+
+    pub fn get_rating(&mut self, nick: &str) -> Result<Option<PlayerRating>, Error> {
+        if let Some(pool) = &self.pool {
+            if let Some(row) = pool.first_exec(GET_RATING_QUERY, params! { "username" => nick })? {
+                let (nick, rating, games) = from_row_opt::<(String, i32, u32)>(row)?;
+                Ok(Some(PlayerRating {
+                    nick,
+                    rating,
+                    games,
+                }))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Err(DriverError::SetupError.into())
+        }
+    }
+
+    /// Locks the ratings of the players and stores the ones computed by `update` in a single
+    /// transaction, returns the old and the new rating of each player
+    pub fn update_ratings<F>(
+        &mut self,
+        nicks: &[String],
+        initial_rating: i32,
+        update: F,
+    ) -> Result<Vec<(i32, i32)>, Error>
+    where
+        F: FnOnce(&[i32]) -> Vec<i32>,
+    {
+        if let Some(pool) = &self.pool {
+            let mut transaction = pool.start_transaction(false, None, None)?;
+            let mut old_ratings = vec![];
+            for nick in nicks {
+                let row =
+                    transaction.first_exec(LOCK_RATING_QUERY, params! { "username" => nick })?;
+                old_ratings.push(match row {
+                    Some(row) => from_row_opt::<i32>(row)?,
+                    None => initial_rating,
+                });
+            }
+
+            let new_ratings = update(&old_ratings);
+            for (nick, rating) in nicks.iter().zip(&new_ratings) {
+                transaction.prep_exec(
+                    STORE_RATING_QUERY,
+                    params! {
+                        "username" => nick,
+                        "rating" => rating,
+                    },
+                )?;
+            }
+            transaction.commit()?;
+            Ok(old_ratings.into_iter().zip(new_ratings).collect())
+        } else {
+            Err(DriverError::SetupError.into())
+        }
+    }
+
+    pub fn get_leaderboard(&mut self, size: u32) -> Result<Vec<PlayerRating>, Error> {
+        if let Some(pool) = &self.pool {
+            let mut ratings = vec![];
+            for row in pool.prep_exec(GET_LEADERBOARD_QUERY, params! { "size" => size })? {
+                let (nick, rating, games) = from_row_opt::<(String, i32, u32)>(row?)?;
+                ratings.push(PlayerRating {
+                    nick,
+                    rating,
+                    games,
+                });
+            }
+            Ok(ratings)
+        } else {
+            Err(DriverError::SetupError.into())
+        }
+    }
 }
 
 fn get_hash(protocol_number: u16, web_password: &str, salt1: &str, salt2: &str) -> Sha1Digest {
@@ -0,0 +1,256 @@
+use crate::{
+    server::coretypes::{RoomConfig, TeamInfo},
+    utils::to_engine_msg,
+};
+
+/// Map types the engine generates itself instead of loading a map
+const GENERATED_MAP_TYPES: &[&str] = &["+rnd+", "+maze+", "+drawn+", "+perlin+", "+forts+"];
+
+/// Engine game flags of the boolean settings at the start of a scheme
+const GAME_FLAGS: [u32; 25] = [
+    0x0000_1000,
+    0x0000_0010,
+    0x0000_0004,
+    0x0000_0008,
+    0x0000_0020,
+    0x0000_0040,
+    0x0000_0080,
+    0x0000_0100,
+    0x0000_0200,
+    0x0000_0400,
+    0x0000_0800,
+    0x0000_2000,
+    0x0000_4000,
+    0x0000_8000,
+    0x0001_0000,
+    0x0002_0000,
+    0x0004_0000,
+    0x0008_0000,
+    0x0010_0000,
+    0x0020_0000,
+    0x0040_0000,
+    0x0080_0000,
+    0x0100_0000,
+    0x0200_0000,
+    0x0400_0000,
+];
+
+/// Engine commands and multipliers of the numeric scheme settings following the flags
+const SCHEME_PARAMS: [(&str, i64); 17] = [
+    ("e$damagepct", 1),
+    ("e$turntime", 1000),
+    ("", 0),
+    ("e$sd_turns", 1),
+    ("e$casefreq", 1),
+    ("e$minestime", 1000),
+    ("e$minesnum", 1),
+    ("e$minedudpct", 1),
+    ("e$explosives", 1),
+    ("e$airmines", 1),
+    ("e$healthprob", 1),
+    ("e$hcaseamount", 1),
+    ("e$waterrise", 1),
+    ("e$healthdec", 1),
+    ("e$ropepct", 1),
+    ("e$getawaytime", 1),
+    ("e$worldedge", 1),
+];
+
+const SCRIPT_PARAM_INDEX: usize = 42;
+const INITIAL_HEALTH_INDEX: usize = 27;
+const MIN_AMMO_LENGTH: usize = 201;
+
+fn engine_msg(msg: &str) -> Option<String> {
+    if msg.len() > u8::max_value() as usize {
+        None
+    } else {
+        Some(to_engine_msg(msg.bytes()))
+    }
+}
+
+fn ammo_commands(ammo: &str, scheme: &[String]) -> Option<Vec<String>> {
+    let part = ammo.len() / 4;
+    let mut commands = vec![
+        format!("eammloadt {}", ammo.get(..part)?),
+        format!("eammprob {}", ammo.get(part..part * 2)?),
+        format!("eammdelay {}", ammo.get(part * 2..part * 3)?),
+        format!("eammreinf {}", ammo.get(part * 3..)?),
+    ];
+    if scheme[14] == "true" || scheme[20] == "false" {
+        commands.push("eammstore".to_string());
+    }
+    Some(commands)
+}
+
+/** Builds the engine demo of a game from its config, teams and message log,
+ * in the form the official server sends to the replay checker.
+ *
+ * Returns `None` if the config lacks the scheme or ammo settings the engine needs.
+ * Games on drawn maps are not converted as the map data is compressed.
+ */
+pub fn game_demo(
+    config: &RoomConfig,
+    teams: &[TeamInfo],
+    msg_log: &[String],
+) -> Option<Vec<String>> {
+    let scheme = &config.scheme.settings;
+    let ammo = config.ammo.settings.as_ref()?;
+    if scheme.len() <= SCRIPT_PARAM_INDEX
+        || ammo.len() < MIN_AMMO_LENGTH
+        || config.map_generator == 3
+    {
+        return None;
+    }
+
+    let mut commands = vec!["TD".to_string()];
+    if config.script != "Normal" {
+        commands.push(format!(
+            "escript Scripts/Multiplayer/{}.lua",
+            config.script.replace(' ', "_")
+        ));
+    }
+    if !GENERATED_MAP_TYPES.contains(&&config.map_type[..]) {
+        commands.push(format!("emap {}", config.map_type));
+    }
+    commands.push(format!("etheme {}", config.theme));
+    commands.push(format!("eseed {}", config.seed));
+
+    let flags = scheme
+        .iter()
+        .zip(GAME_FLAGS.iter())
+        .filter(|(value, _)| *value != "false")
+        .fold(0, |flags, (_, flag)| flags | flag);
+    commands.push(format!("e$gmflags {}", flags));
+    for (value, (name, multiplier)) in scheme[GAME_FLAGS.len()..].iter().zip(SCHEME_PARAMS.iter()) {
+        if !name.is_empty() {
+            let value = value.parse::<i64>().unwrap_or(0) * multiplier;
+            commands.push(format!("{} {}", name, value));
+        }
+    }
+    let script_param = scheme[SCRIPT_PARAM_INDEX].get(1..).unwrap_or("");
+    if !script_param.is_empty() {
+        commands.push(format!("e$scriptparam {}", script_param));
+    }
+
+    commands.push(format!("e$template_filter {}", config.template));
+    commands.push(format!("e$feature_size {}", config.feature_size));
+    commands.push(format!("e$mapgen {}", config.map_generator));
+    if config.map_generator == 1 || config.map_generator == 2 {
+        commands.push(format!("e$maze_size {}", config.maze_size));
+    }
+
+    let ammo = ammo_commands(ammo, scheme)?;
+    let health = &scheme[INITIAL_HEALTH_INDEX];
+    for team in teams {
+        commands.extend(ammo.iter().cloned());
+        commands.push(format!(
+            "eaddteam <hash> {} {}",
+            (1 + u32::from(team.color)) * 2_113_696,
+            team.name
+        ));
+        commands.push("erdriven".to_string());
+        commands.push(format!("efort {}", team.fort));
+        for hedgehog in team.hedgehogs.iter().take(team.hedgehogs_number as usize) {
+            commands.push(format!(
+                "eaddhh {} {} {}",
+                team.difficulty, health, hedgehog.name
+            ));
+            commands.push(format!("ehat {}", hedgehog.hat));
+        }
+    }
+
+    let mut demo = commands
+        .iter()
+        .map(|c| engine_msg(c))
+        .collect::<Option<Vec<_>>>()?;
+    demo.extend(msg_log.iter().cloned());
+    demo.push(engine_msg("!")?);
+    Some(demo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::coretypes::{Ammo, HedgehogInfo, Scheme};
+    use base64::decode;
+
+    fn config() -> RoomConfig {
+        let mut settings = vec!["false".to_string(); GAME_FLAGS.len()];
+        settings[0] = "true".to_string();
+        settings.extend((0..SCHEME_PARAMS.len()).map(|i| i.to_string()));
+        settings.push("!".to_string());
+        RoomConfig {
+            ammo: Ammo {
+                name: "Default".to_string(),
+                settings: Some("1234".repeat(51)),
+            },
+            scheme: Scheme {
+                name: "Default".to_string(),
+                settings,
+            },
+            ..RoomConfig::new()
+        }
+    }
+
+    fn team(name: &str) -> TeamInfo {
+        let hedgehog = || HedgehogInfo {
+            name: "hog".to_string(),
+            hat: "NoHat".to_string(),
+        };
+        TeamInfo {
+            owner: String::new(),
+            name: name.to_string(),
+            color: 1,
+            grave: String::new(),
+            fort: "Castle".to_string(),
+            voice_pack: String::new(),
+            flag: String::new(),
+            difficulty: 0,
+            hedgehogs_number: 1,
+            hedgehogs: [
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+            ],
+        }
+    }
+
+    fn commands(demo: &[String]) -> Vec<String> {
+        demo.iter()
+            .map(|msg| String::from_utf8(decode(msg).unwrap()[1..].to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn demo() {
+        let msg_log = vec![to_engine_msg(b"N".iter().cloned())];
+        let demo = game_demo(&config(), &[team("Red")], &msg_log).unwrap();
+        let commands = commands(&demo);
+
+        assert_eq!(commands[0], "TD");
+        assert!(commands.contains(&"e$gmflags 4096".to_string()));
+        assert!(commands.contains(&"e$turntime 1000".to_string()));
+        assert!(commands.contains(&"eaddteam <hash> 4227392 Red".to_string()));
+        assert!(commands.contains(&"eaddhh 0 2 hog".to_string()));
+        assert_eq!(
+            commands.iter().filter(|c| c.starts_with("eaddhh")).count(),
+            1
+        );
+        assert_eq!(commands[commands.len() - 2], "N");
+        assert_eq!(commands[commands.len() - 1], "!");
+    }
+
+    #[test]
+    fn incomplete_config() {
+        assert!(game_demo(&RoomConfig::new(), &[team("Red")], &[]).is_none());
+
+        let mut config = config();
+        config.map_generator = 3;
+        assert!(game_demo(&config, &[team("Red")], &[]).is_none());
+    }
+}
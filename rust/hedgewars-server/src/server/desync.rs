@@ -0,0 +1,48 @@
+use serde_derive::Serialize;
+use serde_yaml;
+use std::{
+    fs::OpenOptions,
+    io::{Error, ErrorKind, Result, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Diagnostic record of a desync, appended to the log as a separate YAML document
+#[derive(Serialize)]
+pub struct DesyncReport {
+    pub time: u64,
+    pub room: String,
+    /// Engine time of the checksums
+    pub ticks: u32,
+    pub checksums: Vec<(String, String)>,
+    pub msg_log: Vec<String>,
+}
+
+impl DesyncReport {
+    pub fn new(
+        room: String,
+        ticks: u32,
+        checksums: Vec<(String, String)>,
+        msg_log: Vec<String>,
+    ) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs());
+        Self {
+            time,
+            room,
+            ticks,
+            checksums,
+            msg_log,
+        }
+    }
+
+    pub fn write_to(&self, filename: &str) -> Result<()> {
+        let contents =
+            serde_yaml::to_string(self).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let mut writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filename)?;
+        writeln!(writer, "{}", contents)
+    }
+}
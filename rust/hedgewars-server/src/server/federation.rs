@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+
+pub type PeerId = usize;
+
+/// Messages of the link between two federated servers.
+/// A message is a sequence of lines terminated by an empty line
+#[derive(Clone, Debug, PartialEq)]
+pub enum PeerMessage {
+    /// Name of the server, the address its clients are redirected to and the challenge
+    /// the other side must answer with the shared secret
+    Hello {
+        name: String,
+        host: String,
+        port: u16,
+        challenge: String,
+    },
+    Auth(String),
+    /// Snapshot of all the rooms of the server
+    Rooms(Vec<RemoteRoom>),
+    /// `to` is addressed as `nick@server`, `from` is the nick on the sending server
+    PrivateMessage {
+        from: String,
+        to: String,
+        msg: String,
+    },
+    /// Sent back when the recipient of a private message is not online,
+    /// `from` is addressed as `nick@server`
+    PrivateMessageFailed {
+        from: String,
+        to: String,
+    },
+}
+
+impl PeerMessage {
+    pub fn to_raw_protocol(&self) -> String {
+        let mut lines = match self {
+            PeerMessage::Hello {
+                name,
+                host,
+                port,
+                challenge,
+            } => vec![
+                "HELLO".to_string(),
+                name.clone(),
+                host.clone(),
+                port.to_string(),
+                challenge.clone(),
+            ],
+            PeerMessage::Auth(proof) => vec!["AUTH".to_string(), proof.clone()],
+            PeerMessage::Rooms(rooms) => {
+                let mut lines = vec!["ROOMS".to_string()];
+                lines.extend(rooms.iter().map(|room| {
+                    let mut fields = vec![room.protocol_number.to_string()];
+                    fields.extend(room.info.iter().cloned());
+                    fields.join("\t")
+                }));
+                lines
+            }
+            PeerMessage::PrivateMessage { from, to, msg } => {
+                vec!["PM".to_string(), from.clone(), to.clone(), msg.clone()]
+            }
+            PeerMessage::PrivateMessageFailed { from, to } => {
+                vec!["PMFAIL".to_string(), from.clone(), to.clone()]
+            }
+        };
+        lines.push("\n".to_string());
+        lines.join("\n")
+    }
+
+    /// Parses the lines of a message without the terminating empty line
+    pub fn parse(lines: &[String]) -> Option<PeerMessage> {
+        let (command, args) = lines.split_first()?;
+        match (&command[..], args) {
+            ("HELLO", [name, host, port, challenge]) => Some(PeerMessage::Hello {
+                name: name.clone(),
+                host: host.clone(),
+                port: port.parse().ok()?,
+                challenge: challenge.clone(),
+            }),
+            ("AUTH", [proof]) => Some(PeerMessage::Auth(proof.clone())),
+            ("ROOMS", rooms) => {
+                let rooms = rooms
+                    .iter()
+                    .map(|line| {
+                        let mut fields = line.split('\t');
+                        let protocol_number = fields.next()?.parse().ok()?;
+                        Some(RemoteRoom {
+                            protocol_number,
+                            info: fields.map(String::from).collect(),
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some(PeerMessage::Rooms(rooms))
+            }
+            ("PM", [from, to, msg]) => Some(PeerMessage::PrivateMessage {
+                from: from.clone(),
+                to: to.clone(),
+                msg: msg.clone(),
+            }),
+            ("PMFAIL", [from, to]) => Some(PeerMessage::PrivateMessageFailed {
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Events of the links delivered to the server
+pub enum PeerEvent {
+    Connected {
+        peer_id: PeerId,
+        name: String,
+        host: String,
+        port: u16,
+    },
+    Message(PeerId, PeerMessage),
+    Disconnected(PeerId),
+}
+
+/// A room hosted on another server
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteRoom {
+    pub protocol_number: u16,
+    /// Room fields in the order of `HWRoom::info`
+    pub info: Vec<String>,
+}
+
+impl RemoteRoom {
+    pub fn name(&self) -> &str {
+        self.info.get(1).map_or("", |name| &name[..])
+    }
+}
+
+pub struct RemoteServer {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub rooms: Vec<RemoteRoom>,
+}
+
+impl RemoteServer {
+    /// Remote rooms are shown with the server name to avoid clashing with the local rooms
+    pub fn room_name(&self, room: &RemoteRoom) -> String {
+        format!("{} @{}", room.name(), self.name)
+    }
+
+    pub fn room_info(&self, room: &RemoteRoom) -> Vec<String> {
+        let mut info = room.info.clone();
+        if let Some(name) = info.get_mut(1) {
+            *name = self.room_name(room);
+        }
+        info
+    }
+}
+
+/// The other servers of the federation linked to this server
+pub struct Federation {
+    pub servers: HashMap<PeerId, RemoteServer>,
+}
+
+impl Federation {
+    pub fn new() -> Self {
+        Self {
+            servers: HashMap::new(),
+        }
+    }
+
+    pub fn find_room(&self, name: &str) -> Option<(&RemoteServer, &RemoteRoom)> {
+        self.servers.values().find_map(|server| {
+            server
+                .rooms
+                .iter()
+                .find(|room| server.room_name(room) == name)
+                .map(|room| (server, room))
+        })
+    }
+
+    pub fn protocol_rooms(&self, protocol: u16) -> impl Iterator<Item = Vec<String>> + '_ {
+        self.servers.values().flat_map(move |server| {
+            server
+                .rooms
+                .iter()
+                .filter(move |room| room.protocol_number == protocol)
+                .map(move |room| server.room_info(room))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(msg: &PeerMessage) -> Vec<String> {
+        let raw = msg.to_raw_protocol();
+        assert!(raw.ends_with("\n\n"));
+        raw.trim_end_matches('\n')
+            .split('\n')
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn message_roundtrip() {
+        let messages = vec![
+            PeerMessage::Hello {
+                name: "eu".to_string(),
+                host: "eu.example.org".to_string(),
+                port: 46631,
+                challenge: "abc".to_string(),
+            },
+            PeerMessage::Auth("0123".to_string()),
+            PeerMessage::Rooms(vec![]),
+            PeerMessage::Rooms(vec![RemoteRoom {
+                protocol_number: 58,
+                info: vec!["-g".to_string(), "Room".to_string(), "".to_string()],
+            }]),
+            PeerMessage::PrivateMessage {
+                from: "alice".to_string(),
+                to: "bob".to_string(),
+                msg: "hi there".to_string(),
+            },
+            PeerMessage::PrivateMessageFailed {
+                from: "alice@us".to_string(),
+                to: "bob@eu".to_string(),
+            },
+        ];
+        for msg in messages {
+            assert_eq!(PeerMessage::parse(&lines(&msg)), Some(msg));
+        }
+    }
+
+    #[test]
+    fn remote_rooms() {
+        let mut federation = Federation::new();
+        federation.servers.insert(
+            0,
+            RemoteServer {
+                name: "eu".to_string(),
+                host: "eu.example.org".to_string(),
+                port: 46631,
+                rooms: vec![RemoteRoom {
+                    protocol_number: 58,
+                    info: vec!["-".to_string(), "Room".to_string()],
+                }],
+            },
+        );
+
+        let (server, _) = federation.find_room("Room @eu").unwrap();
+        assert_eq!(server.host, "eu.example.org");
+        assert!(federation.find_room("Room").is_none());
+        assert_eq!(
+            federation.protocol_rooms(58).collect::<Vec<_>>(),
+            vec![vec!["-".to_string(), "Room @eu".to_string()]]
+        );
+        assert_eq!(federation.protocol_rooms(57).count(), 0);
+    }
+}
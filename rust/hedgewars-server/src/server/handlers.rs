@@ -5,7 +5,12 @@ use super::{
     actions::{Destination, DestinationGroup},
     core::HWServer,
     coretypes::{ClientId, Replay, RoomId},
+    desync::DesyncReport,
+    federation::{PeerEvent, PeerMessage},
+    hooks::{self, HookResult},
+    rating::{PlayerRating, RatingChange},
     room::RoomSave,
+    room_library::PresetScope,
 };
 use crate::{
     protocol::messages::{server_chat, HWProtocolMessage, HWServerMessage, HWServerMessage::*},
@@ -18,15 +23,22 @@ use rand::{thread_rng, RngCore};
 
 mod checker;
 mod common;
+mod federation;
+mod idle;
 mod inroom;
 mod lobby;
 mod loggingin;
+#[cfg(test)]
+pub mod test;
 
 use self::loggingin::LoginResult;
 use crate::protocol::messages::global_chat;
 use crate::protocol::messages::HWProtocolMessage::EngineMessage;
 use crate::server::coretypes::{GameCfg, TeamInfo};
-use std::fmt::{Formatter, LowerHex};
+use std::{
+    fmt::{Formatter, LowerHex},
+    time::{Duration, Instant},
+};
 
 #[derive(PartialEq)]
 pub struct Sha1Digest([u8; 20]);
@@ -61,6 +73,11 @@ pub enum IoTask {
         client_salt: String,
         server_salt: String,
     },
+    /// Checkers log in with the password hash stored for an account with the admin rights
+    GetCheckerAccount {
+        nick: String,
+        password_hash: String,
+    },
     GetReplay {
         id: u32,
     },
@@ -73,19 +90,96 @@ pub enum IoTask {
         room_id: RoomId,
         filename: String,
     },
+    ListPresets {
+        nick: Option<String>,
+    },
+    /// Private presets of `nick` take precedence over the public ones
+    LoadPreset {
+        room_id: RoomId,
+        nick: Option<String>,
+        name: String,
+    },
+    SavePreset {
+        scope: PresetScope,
+        name: String,
+        contents: String,
+    },
+    DeletePreset {
+        scope: PresetScope,
+        name: String,
+    },
+    /// Players without a rating start from `initial_rating`
+    UpdateRatings {
+        winners: Vec<String>,
+        losers: Vec<String>,
+        initial_rating: i32,
+        k_factor: u32,
+    },
+    GetRating {
+        nick: String,
+    },
+    GetLeaderboard {
+        size: u32,
+    },
+    SaveDesyncReport {
+        filename: String,
+        report: DesyncReport,
+    },
+}
+
+impl IoTask {
+    /// The result reported when the task times out or cannot be queued
+    pub fn failure_result(&self) -> IoResult {
+        match self {
+            IoTask::GetAccount { .. } => IoResult::Account(None),
+            IoTask::GetCheckerAccount { .. } => IoResult::CheckerAccount(false),
+            IoTask::GetReplay { .. } => IoResult::Replay(None),
+            IoTask::SaveRoom { room_id, .. } => IoResult::SaveRoom(*room_id, false),
+            IoTask::LoadRoom { room_id, .. } => IoResult::LoadRoom(*room_id, None),
+            IoTask::ListPresets { .. } => IoResult::PresetList {
+                public: vec![],
+                private: vec![],
+            },
+            IoTask::LoadPreset { room_id, name, .. } => {
+                IoResult::LoadPreset(*room_id, name.clone(), None)
+            }
+            IoTask::SavePreset { name, .. } => IoResult::SavePreset(name.clone(), false),
+            IoTask::DeletePreset { name, .. } => IoResult::DeletePreset(name.clone(), false),
+            IoTask::UpdateRatings {
+                winners, losers, ..
+            } => IoResult::UpdateRatings(winners.iter().chain(losers).cloned().collect(), None),
+            IoTask::GetRating { nick } => IoResult::Rating(nick.clone(), None),
+            IoTask::GetLeaderboard { .. } => IoResult::Leaderboard(None),
+            IoTask::SaveDesyncReport { .. } => IoResult::SaveDesyncReport(false),
+        }
+    }
 }
 
 pub enum IoResult {
     Account(Option<AccountInfo>),
+    CheckerAccount(bool),
     Replay(Option<Replay>),
     SaveRoom(RoomId, bool),
     LoadRoom(RoomId, Option<String>),
+    PresetList {
+        public: Vec<String>,
+        private: Vec<String>,
+    },
+    LoadPreset(RoomId, String, Option<String>),
+    SavePreset(String, bool),
+    DeletePreset(String, bool),
+    /// Nicks of the players of the game and their rating changes
+    UpdateRatings(Vec<String>, Option<Vec<RatingChange>>),
+    Rating(String, Option<PlayerRating>),
+    Leaderboard(Option<Vec<PlayerRating>>),
+    SaveDesyncReport(bool),
 }
 
 pub struct Response {
     client_id: ClientId,
     messages: Vec<PendingMessage>,
-    io_tasks: Vec<IoTask>,
+    io_tasks: Vec<(ClientId, IoTask)>,
+    peer_messages: Vec<PeerMessage>,
     removed_clients: Vec<ClientId>,
 }
 
@@ -95,13 +189,17 @@ impl Response {
             client_id,
             messages: vec![],
             io_tasks: vec![],
+            peer_messages: vec![],
             removed_clients: vec![],
         }
     }
 
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.messages.is_empty() && self.removed_clients.is_empty() && self.io_tasks.is_empty()
+        self.messages.is_empty()
+            && self.removed_clients.is_empty()
+            && self.io_tasks.is_empty()
+            && self.peer_messages.is_empty()
     }
 
     #[inline]
@@ -121,7 +219,19 @@ impl Response {
 
     #[inline]
     pub fn request_io(&mut self, task: IoTask) {
-        self.io_tasks.push(task)
+        self.io_tasks.push((self.client_id, task))
+    }
+
+    /// The task is not cancelled when the client disconnects
+    #[inline]
+    pub fn request_server_io(&mut self, task: IoTask) {
+        self.io_tasks.push((ClientId::max_value(), task))
+    }
+
+    /// The message is sent to all the linked servers
+    #[inline]
+    pub fn send_peers(&mut self, message: PeerMessage) {
+        self.peer_messages.push(message)
     }
 
     pub fn extract_messages<'a, 'b: 'a>(
@@ -143,9 +253,13 @@ impl Response {
         self.removed_clients.drain(..)
     }
 
-    pub fn extract_io_tasks(&mut self) -> impl Iterator<Item = IoTask> + '_ {
+    pub fn extract_io_tasks(&mut self) -> impl Iterator<Item = (ClientId, IoTask)> + '_ {
         self.io_tasks.drain(..)
     }
+
+    pub fn extract_peer_messages(&mut self) -> impl Iterator<Item = PeerMessage> + '_ {
+        self.peer_messages.drain(..)
+    }
 }
 
 impl Extend<PendingMessage> for Response {
@@ -156,6 +270,34 @@ impl Extend<PendingMessage> for Response {
     }
 }
 
+/// Messages of the muted players are not recorded, even if they were sent on their behalf
+pub fn record_chat_history(server: &mut HWServer, response: &Response) {
+    let now = Instant::now();
+    for message in &response.messages {
+        if let (HWServerMessage::ChatMsg { nick, msg }, Destination::ToAll { group, .. }) =
+            (&message.message, &message.destination)
+        {
+            let is_muted = server
+                .find_client(nick)
+                .map_or(false, |c| c.active_mute(now).is_some());
+            if is_muted {
+                continue;
+            }
+
+            let history = match group {
+                DestinationGroup::All | DestinationGroup::Lobby => Some(&mut server.lobby_chat),
+                DestinationGroup::Room(id) => {
+                    server.rooms.get_mut(*id).map(|r| &mut r.chat_history)
+                }
+                DestinationGroup::Protocol(_) => None,
+            };
+            if let Some(history) = history {
+                history.push(nick.clone(), msg.clone());
+            }
+        }
+    }
+}
+
 fn get_recipients(
     server: &HWServer,
     client_id: ClientId,
@@ -184,6 +326,16 @@ fn get_recipients(
     }
 }
 
+fn complete_login(server: &mut HWServer, client_id: ClientId, response: &mut Response) {
+    if hooks::on_login(server, client_id, response) == HookResult::Veto {
+        server.remove_client(client_id);
+        response.add(Bye("Login refused".to_string()).send_self());
+        response.remove_client(client_id);
+    } else {
+        common::join_lobby(server, response);
+    }
+}
+
 pub fn handle(
     server: &mut HWServer,
     client_id: ClientId,
@@ -199,7 +351,7 @@ pub fn handle(
                     LoginResult::Complete => {
                         if let Some(client) = server.anteroom.remove_client(client_id) {
                             server.add_client(client_id, client);
-                            common::join_lobby(server, response);
+                            complete_login(server, client_id, response);
                         }
                     }
                     LoginResult::Exit => {
@@ -208,6 +360,43 @@ pub fn handle(
                     }
                 }
             } else if server.clients.contains(client_id) {
+                if !matches!(message, HWProtocolMessage::Pong) {
+                    let client = &mut server.clients[client_id];
+                    client.last_activity = Instant::now();
+                    client.master_idle_deadline = None;
+                    client.player_idle_deadline = None;
+                }
+
+                if let HWProtocolMessage::Chat(_)
+                | HWProtocolMessage::TeamChat(_)
+                | HWProtocolMessage::PrivateMessage(..) = message
+                {
+                    let now = Instant::now();
+                    if let Some(mute) = server.clients[client_id].active_mute(now) {
+                        let msg = format!(
+                            "You are muted for {} more: {}",
+                            utils::format_duration(mute.until - now),
+                            mute.reason
+                        );
+                        response.add(server_chat(msg).send_self());
+                        return;
+                    }
+                }
+
+                let hook_result = match message {
+                    HWProtocolMessage::Chat(ref msg) => {
+                        hooks::on_chat(server, client_id, msg, response)
+                    }
+                    HWProtocolMessage::CreateRoom(ref name, _)
+                    | HWProtocolMessage::CreateRankedRoom(ref name) => {
+                        hooks::on_room_create(server, client_id, name, response)
+                    }
+                    _ => HookResult::Pass,
+                };
+                if hook_result == HookResult::Veto {
+                    return;
+                }
+
                 match message {
                     HWProtocolMessage::Quit(Some(msg)) => {
                         common::remove_client(server, response, "User quit: ".to_string() + &msg);
@@ -247,6 +436,56 @@ pub fn handle(
                                 .add(server_chat("Player is not online.".to_string()).send_self())
                         }
                     }
+                    HWProtocolMessage::PrivateMessage(nick, msg) => {
+                        let from = server.clients[client_id].nick.clone();
+                        if !federation::deliver_private_message(
+                            server, &from, &nick, &msg, response,
+                        ) && !federation::send_remote_private_message(
+                            server, &from, &nick, &msg, response,
+                        ) {
+                            response
+                                .add(server_chat("Player is not online.".to_string()).send_self());
+                        }
+                    }
+                    HWProtocolMessage::Mute(nick, duration, reason) => {
+                        let duration = Duration::from_secs(duration.into());
+                        common::mute_client(server, &nick, duration, reason, response);
+                    }
+                    HWProtocolMessage::Unmute(nick) => {
+                        common::unmute_client(server, &nick, response);
+                    }
+                    HWProtocolMessage::Announce(target, delay, interval, msg) => {
+                        if !server.clients[client_id].is_admin() {
+                            response.add(Warning("Access denied.".to_string()).send_self());
+                        } else {
+                            let next_time =
+                                Instant::now() + Duration::from_secs(delay.unwrap_or(0).into());
+                            let interval = interval.map(|i| Duration::from_secs(i.into()));
+                            let id = server.announcements.add(msg, target, next_time, interval);
+                            response.add(
+                                server_chat(format!("Announcement #{} scheduled.", id)).send_self(),
+                            );
+                        }
+                    }
+                    HWProtocolMessage::ListAnnouncements => {
+                        if !server.clients[client_id].is_admin() {
+                            response.add(Warning("Access denied.".to_string()).send_self());
+                        } else {
+                            common::get_announcements(server, response);
+                        }
+                    }
+                    HWProtocolMessage::CancelAnnouncement(id) => {
+                        if !server.clients[client_id].is_admin() {
+                            response.add(Warning("Access denied.".to_string()).send_self());
+                        } else if server.announcements.cancel(id) {
+                            response.add(
+                                server_chat(format!("Announcement #{} cancelled.", id)).send_self(),
+                            );
+                        } else {
+                            response
+                                .add(server_chat(format!("No announcement #{}.", id)).send_self());
+                        }
+                    }
                     HWProtocolMessage::ToggleServerRegisteredOnly => {
                         if !server.clients[client_id].is_admin() {
                             response.add(Warning("Access denied.".to_string()).send_self());
@@ -276,6 +515,15 @@ pub fn handle(
                                 .add(server_chat("Super power activated.".to_string()).send_self())
                         }
                     }
+                    HWProtocolMessage::CustomCommand(name, args) => {
+                        let args = args.as_ref().map(|s| &s[..]);
+                        if !hooks::on_command(server, client_id, &name, args, response) {
+                            response.add(
+                                Warning(format!("Unknown command: /{}", name.to_lowercase()))
+                                    .send_self(),
+                            );
+                        }
+                    }
                     HWProtocolMessage::Watch(id) => {
                         #[cfg(feature = "official-server")]
                         {
@@ -290,6 +538,9 @@ pub fn handle(
                             );
                         }
                     }
+                    _ if server.clients[client_id].is_checker() => {
+                        checker::handle(server, client_id, response, message)
+                    }
                     _ => match server.clients[client_id].room_id {
                         None => lobby::handle(server, client_id, response, message),
                         Some(room_id) => {
@@ -317,6 +568,24 @@ pub fn handle_client_loss(server: &mut HWServer, client_id: ClientId, response:
     }
 }
 
+pub fn handle_server_tick(server: &mut HWServer) -> Vec<Response> {
+    let now = Instant::now();
+    let mut responses = idle::handle_idle_check(server, now);
+
+    // announcements are not sent on behalf of any client
+    let mut response = Response::new(ClientId::max_value());
+    common::send_announcements(server, now, &mut response);
+    if server.config.federation.is_enabled() {
+        response.send_peers(federation::get_room_list(server));
+    }
+    responses.push(response);
+    responses
+}
+
+pub fn handle_peer_event(server: &mut HWServer, response: &mut Response, event: PeerEvent) {
+    federation::handle_event(server, response, event);
+}
+
 pub fn handle_io_result(
     server: &mut HWServer,
     client_id: ClientId,
@@ -331,14 +600,43 @@ pub fn handle_io_result(
                         .send_self(),
                 );
                 response.remove_client(client_id);
-            } else {
+            } else if server.anteroom.clients.contains(client_id) {
+                let nick_owner = server.anteroom.clients[client_id]
+                    .nick
+                    .as_ref()
+                    .and_then(|nick| loggingin::find_nick_owner(server.clients.iter(), nick));
+
+                match nick_owner {
+                    Some(owner_id) if info.is_registered => {
+                        common::disconnect_client(
+                            server,
+                            owner_id,
+                            response,
+                            "Ghost session replaced".to_string(),
+                        );
+                    }
+                    Some(_) => {
+                        let client = &mut server.anteroom.clients[client_id];
+                        if let LoginResult::Exit = loggingin::reject_nick(client, response) {
+                            server.anteroom.remove_client(client_id);
+                            response.remove_client(client_id);
+                        }
+                        return;
+                    }
+                    None => (),
+                }
+
                 response.add(ServerAuth(format!("{:x}", info.server_hash)).send_self());
                 if let Some(client) = server.anteroom.remove_client(client_id) {
                     server.add_client(client_id, client);
                     let client = &mut server.clients[client_id];
                     client.set_is_registered(info.is_registered);
                     client.set_is_admin(info.is_admin);
-                    client.set_is_contributor(info.is_admin)
+                    client.set_is_contributor(info.is_admin);
+                    if info.is_registered {
+                        server.restore_mute(client_id);
+                    }
+                    complete_login(server, client_id, response);
                 }
             }
         }
@@ -346,6 +644,16 @@ pub fn handle_io_result(
             response.add(Error("Authentication failed.".to_string()).send_self());
             response.remove_client(client_id);
         }
+        IoResult::CheckerAccount(true) => {
+            if let Some(client) = server.anteroom.remove_client(client_id) {
+                server.add_client(client_id, client);
+                response.add(LogonPassed.send_self());
+            }
+        }
+        IoResult::CheckerAccount(false) => {
+            response.add(Bye("No checker rights".to_string()).send_self());
+            response.remove_client(client_id);
+        }
         IoResult::Replay(Some(replay)) => {
             let protocol = server.clients[client_id].protocol_number;
             let start_msg = if protocol < 58 {
@@ -392,5 +700,100 @@ pub fn handle_io_result(
         IoResult::LoadRoom(_, None) => {
             response.add(Warning("Unable to load the room configs.".to_string()).send_self());
         }
+        IoResult::PresetList { public, private } => {
+            let list = |names: Vec<String>| {
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            };
+            response.add(server_chat(format!("Room config presets: {}", list(public))).send_self());
+            if server.clients[client_id].is_registered() {
+                response.add(
+                    server_chat(format!("Your room config presets: {}", list(private))).send_self(),
+                );
+            }
+        }
+        IoResult::LoadPreset(room_id, name, Some(contents)) => {
+            if let Some(room) = server.rooms.get_mut(room_id) {
+                match room.set_config_preset(&contents) {
+                    Ok(_) => {
+                        response.add(
+                            server_chat(format!("Room config preset {} loaded", name))
+                                .send_all()
+                                .in_room(room_id),
+                        );
+                        common::update_room_config(server, room_id, response);
+                    }
+                    Err(e) => {
+                        warn!("Error while deserializing the room config preset: {}", e);
+                        response.add(
+                            Warning("Unable to deserialize the room config preset.".to_string())
+                                .send_self(),
+                        );
+                    }
+                }
+            }
+        }
+        IoResult::LoadPreset(_, name, None) => {
+            response.add(Warning(format!("No such room config preset: {}", name)).send_self());
+        }
+        IoResult::SavePreset(name, true) => {
+            response.add(server_chat(format!("Room config preset {} saved", name)).send_self());
+        }
+        IoResult::SavePreset(name, false) => {
+            response.add(
+                Warning(format!("Unable to save the room config preset {}.", name)).send_self(),
+            );
+        }
+        IoResult::DeletePreset(name, true) => {
+            response.add(server_chat(format!("Room config preset {} deleted", name)).send_self());
+        }
+        IoResult::DeletePreset(name, false) => {
+            response.add(Warning(format!("No such room config preset: {}", name)).send_self());
+        }
+        IoResult::UpdateRatings(players, Some(changes)) => {
+            let changes: Vec<_> = changes
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{} {} ({:+})",
+                        c.nick,
+                        c.new_rating,
+                        c.new_rating - c.old_rating
+                    )
+                })
+                .collect();
+            let msg = server_chat(format!("Ratings updated: {}", changes.join(", ")));
+            common::send_to_players(server, &players, msg, response);
+        }
+        IoResult::UpdateRatings(players, None) => {
+            let msg = server_chat("Unable to update the ratings.".to_string());
+            common::send_to_players(server, &players, msg, response);
+        }
+        IoResult::Rating(_, Some(rating)) => {
+            let msg = format!(
+                "{}: rating {}, {} ranked games",
+                rating.nick, rating.rating, rating.games
+            );
+            response.add(server_chat(msg).send_self());
+        }
+        IoResult::Rating(nick, None) => {
+            response.add(server_chat(format!("{} has no rating.", nick)).send_self());
+        }
+        IoResult::Leaderboard(Some(ratings)) => {
+            if ratings.is_empty() {
+                response.add(server_chat("The leaderboard is empty.".to_string()).send_self());
+            }
+            for (place, rating) in ratings.iter().enumerate() {
+                let msg = format!("{}. {} {}", place + 1, rating.nick, rating.rating);
+                response.add(server_chat(msg).send_self());
+            }
+        }
+        IoResult::Leaderboard(None) => {
+            response.add(Warning("Unable to get the leaderboard.".to_string()).send_self());
+        }
+        IoResult::SaveDesyncReport(_) => (),
     }
 }
@@ -1,13 +1,155 @@
 use log::*;
-use mio;
 
+use super::{common, Response};
 use crate::{
-    protocol::messages::HWProtocolMessage,
+    protocol::messages::{HWProtocolMessage, HWServerMessage::Replay},
     server::{core::HWServer, coretypes::ClientId},
 };
 
-pub fn handle(_server: &mut HWServer, _client_id: ClientId, message: HWProtocolMessage) {
+/// Returns the winning teams from the statistics the engine printed while replaying the game
+fn checked_winners(info: &[String]) -> Option<Vec<String>> {
+    let position = info.iter().position(|line| line == "WINNERS")?;
+    let count: usize = info.get(position + 1)?.parse().ok()?;
+    let winners = info.get(position + 2..position + 2 + count)?;
+    Some(winners.to_vec())
+}
+
+/// Sends the oldest unchecked game to a ready checker, if there are both
+pub fn send_unchecked_game(server: &mut HWServer, response: &mut Response) {
+    if server.unchecked_games.is_empty() {
+        return;
+    }
+
+    let checker_id = server
+        .clients
+        .iter()
+        .find(|(_, c)| c.is_checker() && c.is_ready())
+        .map(|(id, _)| id);
+    if let Some(checker_id) = checker_id {
+        if let Some(game) = server.unchecked_games.pop_front() {
+            server.clients[checker_id].set_is_ready(false);
+            response.add(Replay(game.demo.clone()).send(checker_id));
+            server.games_in_check.insert(checker_id, game);
+        }
+    }
+}
+
+pub fn handle(
+    server: &mut HWServer,
+    client_id: ClientId,
+    response: &mut Response,
+    message: HWProtocolMessage,
+) {
     match message {
+        HWProtocolMessage::CheckerReady => {
+            if !server.games_in_check.contains_key(&client_id) {
+                server.clients[client_id].set_is_ready(true);
+                send_unchecked_game(server, response);
+            }
+        }
+        HWProtocolMessage::CheckedOk(info) => {
+            if let Some(game) = server.games_in_check.remove(&client_id) {
+                match checked_winners(&info) {
+                    Some(winners) => {
+                        common::update_ratings(server, &game.teams, &winners, response)
+                    }
+                    None => info!("Ranked game {} has no winners", game.id),
+                }
+            }
+        }
+        HWProtocolMessage::CheckedFail(msg) => {
+            if let Some(game) = server.games_in_check.remove(&client_id) {
+                warn!("Checking ranked game {} failed: {}", game.id, msg);
+            }
+        }
         _ => warn!("Unknown command"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::handlers::{test::*, IoTask};
+
+    fn info(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn winners() {
+        let stats = info(&[
+            "WINNERS",
+            "2",
+            "Red",
+            "Blue",
+            "ACHIEVEMENT",
+            "a",
+            "b",
+            "c",
+            "d",
+        ]);
+        assert_eq!(
+            checked_winners(&stats),
+            Some(vec!["Red".to_string(), "Blue".to_string()])
+        );
+        assert_eq!(checked_winners(&info(&["DRAW"])), None);
+        assert_eq!(checked_winners(&info(&["WINNERS", "2", "Red"])), None);
+    }
+
+    #[test]
+    fn check() {
+        let mut server = server();
+        add_client(&mut server, 0, "checker");
+        server.clients[0].set_is_checker(true);
+        let teams = vec![
+            ("Red".to_string(), "alice".to_string()),
+            ("Blue".to_string(), "bob".to_string()),
+        ];
+        server.add_unchecked_game(teams, vec!["demo".to_string()]);
+
+        let mut response = Response::new(0);
+        handle(
+            &mut server,
+            0,
+            &mut response,
+            HWProtocolMessage::CheckerReady,
+        );
+        assert_eq!(messages(&server, &mut response), vec!["REPLAY\ndemo\n\n"]);
+        assert!(server.unchecked_games.is_empty());
+
+        // the game is checked again if the checker leaves
+        server.remove_client(0);
+        assert_eq!(server.unchecked_games.len(), 1);
+        add_client(&mut server, 0, "checker");
+        server.clients[0].set_is_checker(true);
+        handle(
+            &mut server,
+            0,
+            &mut Response::new(0),
+            HWProtocolMessage::CheckerReady,
+        );
+
+        let mut response = Response::new(0);
+        let stats = info(&["WINNERS", "1", "Blue"]);
+        handle(
+            &mut server,
+            0,
+            &mut response,
+            HWProtocolMessage::CheckedOk(stats),
+        );
+        let tasks: Vec<_> = response.extract_io_tasks().collect();
+        match &tasks[..] {
+            [(
+                _,
+                IoTask::UpdateRatings {
+                    winners, losers, ..
+                },
+            )] => {
+                assert_eq!(winners, &vec!["bob".to_string()]);
+                assert_eq!(losers, &vec!["alice".to_string()]);
+            }
+            _ => panic!("expected a rating update"),
+        }
+        assert!(server.games_in_check.is_empty());
+    }
+}
@@ -1,25 +1,31 @@
 use crate::{
-    protocol::messages::server_chat,
     protocol::messages::{
         add_flags, remove_flags,
         HWProtocolMessage::{self, Rnd},
         HWServerMessage::{self, *},
         ProtocolFlags as Flags,
     },
+    protocol::messages::{global_chat, server_chat},
     server::{
-        client::HWClient,
+        chat_history::ChatEntry,
+        client::{HWClient, Mute},
         core::HWServer,
-        coretypes::{ClientId, GameCfg, RoomId, TeamInfo, Vote, VoteType},
-        room::HWRoom,
+        coretypes::{AnnouncementTarget, ClientId, GameCfg, RoomId, TeamInfo, Vote, VoteType},
+        hooks::{self, HookResult},
+        room::{GameInfo, HWRoom},
     },
-    utils::to_engine_msg,
+    utils::{self, to_engine_msg},
 };
 
 use super::Response;
 
 use crate::server::coretypes::RoomConfig;
 use rand::{self, seq::SliceRandom, thread_rng, Rng};
-use std::{iter::once, mem::replace};
+use std::{
+    iter::once,
+    mem::replace,
+    time::{Duration, Instant},
+};
 
 pub fn rnd_reply(options: &[String]) -> HWServerMessage {
     let mut rng = thread_rng();
@@ -78,6 +84,12 @@ pub fn join_lobby(server: &mut HWServer, response: &mut Response) {
             .iter()
             .filter(|(_, r)| r.protocol_number == client.protocol_number)
             .flat_map(|(_, r)| r.info(r.master_id.map(|id| &server.clients[id])))
+            .chain(
+                server
+                    .federation
+                    .protocol_rooms(client.protocol_number)
+                    .flatten(),
+            )
             .collect(),
     );
 
@@ -97,6 +109,24 @@ pub fn join_lobby(server: &mut HWServer, response: &mut Response) {
 
     response.add(server_msg.send_self());
     response.add(rooms_msg.send_self());
+
+    let max_age = server.config.chat_history.max_age();
+    get_chat_history(server.lobby_chat.recent(max_age), client_id, response);
+}
+
+pub fn get_chat_history<'a, I>(entries: I, to_client: ClientId, response: &mut Response)
+where
+    I: Iterator<Item = &'a ChatEntry>,
+{
+    for entry in entries {
+        response.add(
+            ChatMsg {
+                nick: entry.nick.clone(),
+                msg: entry.msg.clone(),
+            }
+            .send(to_client),
+        );
+    }
 }
 
 pub fn remove_teams(
@@ -150,7 +180,7 @@ fn remove_client_from_room(
     msg: &str,
 ) {
     room.players_number -= 1;
-    if room.players_number > 0 || room.is_fixed() {
+    if room.players_number > 0 || room.is_fixed() || room.is_permanent() {
         if client.is_ready() && room.ready_players_number > 0 {
             room.ready_players_number -= 1;
         }
@@ -186,7 +216,7 @@ fn remove_client_from_room(
 
     client.room_id = None;
 
-    let update_msg = if room.players_number == 0 && !room.is_fixed() {
+    let update_msg = if room.players_number == 0 && !room.is_fixed() && !room.is_permanent() {
         RoomRemove(room.name.clone())
     } else {
         RoomUpdated(room.name.clone(), room.info(Some(&client)))
@@ -237,6 +267,11 @@ pub fn enter_room(
     let nick = server.clients[client_id].nick.clone();
     server.move_to_room(client_id, room_id);
 
+    let room = &server.rooms[room_id];
+    let is_new_master = room.master_id.is_none()
+        && !room.is_fixed()
+        && can_be_master(&server.clients[client_id], room);
+
     response.add(RoomJoined(vec![nick.clone()]).send_all().in_room(room_id));
     response.add(ClientFlags(add_flags(&[Flags::InRoom]), vec![nick]).send_all());
     let nicks = server.collect_nicks(|(_, c)| c.room_id == Some(room_id));
@@ -260,6 +295,9 @@ pub fn enter_room(
         response.add(ClientFlags(add_flags(&[*flag]), replace(nicks, vec![])).send_self());
     }
 
+    let max_age = server.config.chat_history.max_age();
+    get_chat_history(room.chat_history.recent(max_age), client_id, response);
+
     if !room.greeting.is_empty() {
         response.add(
             ChatMsg {
@@ -269,6 +307,10 @@ pub fn enter_room(
             .send_self(),
         );
     }
+
+    if is_new_master {
+        set_room_master(server, room_id, client_id, response);
+    }
 }
 
 pub fn exit_room(server: &mut HWServer, client_id: ClientId, response: &mut Response, msg: &str) {
@@ -280,37 +322,65 @@ pub fn exit_room(server: &mut HWServer, client_id: ClientId, response: &mut Resp
         remove_client_from_room(client, room, response, msg);
 
         if !room.is_fixed() {
-            if room.players_number == 0 {
+            if room.players_number == 0 && !room.is_permanent() {
                 server.rooms.remove(room_id);
             } else if room.master_id == None {
-                let new_master_id = server.room_clients(room_id).next();
+                let new_master_id = server
+                    .room_clients(room_id)
+                    .find(|id| can_be_master(&server.clients[*id], &server.rooms[room_id]));
                 if let Some(new_master_id) = new_master_id {
-                    let new_master_nick = server.clients[new_master_id].nick.clone();
-                    let room = &mut server.rooms[room_id];
-                    room.master_id = Some(new_master_id);
-                    server.clients[new_master_id].set_is_master(true);
+                    set_room_master(server, room_id, new_master_id, response);
+                }
+            }
+        }
+    }
+}
 
-                    if room.protocol_number < 42 {
-                        room.name = new_master_nick.clone();
-                    }
+/// Only registered players can take over permanent rooms on the official server
+pub fn can_be_master(client: &HWClient, room: &HWRoom) -> bool {
+    !client.is_checker()
+        && (!room.is_permanent() || !cfg!(feature = "official-server") || client.is_registered())
+}
 
-                    room.set_join_restriction(false);
-                    room.set_team_add_restriction(false);
-                    room.set_unregistered_players_restriction(true);
+fn set_room_master(
+    server: &mut HWServer,
+    room_id: RoomId,
+    new_master_id: ClientId,
+    response: &mut Response,
+) {
+    let new_master_nick = server.clients[new_master_id].nick.clone();
+    let room = &mut server.rooms[room_id];
+    room.master_id = Some(new_master_id);
+    server.clients[new_master_id].set_is_master(true);
 
-                    response.add(
-                        ClientFlags(add_flags(&[Flags::RoomMaster]), vec![new_master_nick])
-                            .send_all()
-                            .in_room(room.id),
-                    );
-                }
-            }
+    if !room.is_permanent() {
+        if room.protocol_number < 42 {
+            room.name = new_master_nick.clone();
         }
+
+        room.set_join_restriction(false);
+        room.set_team_add_restriction(false);
+        room.set_unregistered_players_restriction(true);
     }
+
+    response.add(
+        ClientFlags(add_flags(&[Flags::RoomMaster]), vec![new_master_nick])
+            .send_all()
+            .in_room(room.id),
+    );
 }
 
 pub fn remove_client(server: &mut HWServer, response: &mut Response, msg: String) {
     let client_id = response.client_id();
+    disconnect_client(server, client_id, response, msg);
+}
+
+pub fn disconnect_client(
+    server: &mut HWServer,
+    client_id: ClientId,
+    response: &mut Response,
+    msg: String,
+) {
     let client = &mut server.clients[client_id];
     let nick = client.nick.clone();
 
@@ -319,7 +389,7 @@ pub fn remove_client(server: &mut HWServer, response: &mut Response, msg: String
     server.remove_client(client_id);
 
     response.add(LobbyLeft(nick, msg.to_string()).send_all());
-    response.add(Bye("User quit: ".to_string() + &msg).send_self());
+    response.add(Bye("User quit: ".to_string() + &msg).send(client_id));
     response.remove_client(client_id);
 }
 
@@ -344,6 +414,22 @@ pub fn get_room_config(room: &HWRoom, to_client: ClientId, response: &mut Respon
     get_room_config_impl(room.active_config(), to_client, response);
 }
 
+pub fn update_room_config(server: &HWServer, room_id: RoomId, response: &mut Response) {
+    let room = &server.rooms[room_id];
+    let room_master = if let Some(id) = room.master_id {
+        Some(&server.clients[id])
+    } else {
+        None
+    };
+    get_room_update(None, room, room_master, response);
+
+    for (_, client) in server.clients.iter() {
+        if client.room_id == Some(room_id) {
+            get_room_config(room, client.id, response);
+        }
+    }
+}
+
 pub fn get_teams<'a, I>(teams: I, to_client: ClientId, response: &mut Response)
 where
     I: Iterator<Item = &'a TeamInfo>,
@@ -410,6 +496,9 @@ pub fn apply_voting_result(
                     let id = client.id;
                     response.add(Kicked.send(id));
                     exit_room(server, id, response, "kicked");
+                    if let Some(room) = server.rooms.get_mut(room_id) {
+                        room.chat_history.remove_nick(&nick);
+                    }
                 }
             }
         }
@@ -421,19 +510,7 @@ pub fn apply_voting_result(
                         .send_all()
                         .in_room(room_id),
                 );
-                let room = &server.rooms[room_id];
-                let room_master = if let Some(id) = room.master_id {
-                    Some(&server.clients[id])
-                } else {
-                    None
-                };
-                get_room_update(None, room, room_master, response);
-
-                for (_, client) in server.clients.iter() {
-                    if client.room_id == Some(room_id) {
-                        super::common::get_room_config(&server.rooms[room_id], client.id, response);
-                    }
-                }
+                update_room_config(server, room_id, response);
             }
         }
         VoteType::Pause => {
@@ -519,7 +596,171 @@ pub fn submit_vote(server: &mut HWServer, vote: Vote, response: &mut Response) {
     }
 }
 
+pub fn balance_clans(
+    server: &mut HWServer,
+    room_id: RoomId,
+    clans_number: u8,
+    response: &mut Response,
+) {
+    let room = &mut server.rooms[room_id];
+    for index in room.balance_clans(clans_number) {
+        let team = &room.teams[index].1;
+        response.add(
+            TeamColor(team.name.clone(), team.color)
+                .send_all()
+                .in_room(room_id),
+        );
+    }
+
+    for (owner_id, _) in &room.teams {
+        server.clients[*owner_id].clan = room.find_team_color(*owner_id);
+    }
+}
+
+/// Admins mute players server-wide, room masters only within their room
+pub fn mute_client(
+    server: &mut HWServer,
+    nick: &str,
+    duration: Duration,
+    reason: Option<String>,
+    response: &mut Response,
+) {
+    let client = &server.clients[response.client_id()];
+    let target = match server.find_client(nick) {
+        Some(target) => target,
+        None => {
+            response.add(server_chat("Player is not online.".to_string()).send_self());
+            return;
+        }
+    };
+
+    let room_id = if client.is_admin() {
+        None
+    } else if client.is_master() && client.room_id.is_some() && target.room_id == client.room_id {
+        client.room_id
+    } else {
+        response.add(Warning("Access denied.".to_string()).send_self());
+        return;
+    };
+
+    if target.is_admin() {
+        response.add(Warning("Access denied.".to_string()).send_self());
+        return;
+    }
+
+    let target_id = target.id;
+    let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+    let msg = format!(
+        "{} is muted for {}: {}",
+        target.nick,
+        utils::format_duration(duration),
+        reason
+    );
+
+    server.clients[target_id].add_mute(Mute {
+        room_id,
+        until: Instant::now() + duration,
+        reason,
+    });
+
+    match room_id {
+        Some(room_id) => {
+            server.rooms[room_id].chat_history.remove_nick(nick);
+            response.add(server_chat(msg).send_all().in_room(room_id));
+        }
+        None => {
+            server.lobby_chat.remove_nick(nick);
+            for (_, room) in server.rooms.iter_mut() {
+                room.chat_history.remove_nick(nick);
+            }
+            response.add(server_chat(msg).send_many(vec![response.client_id(), target_id]));
+        }
+    }
+}
+
+pub fn unmute_client(server: &mut HWServer, nick: &str, response: &mut Response) {
+    let client = &server.clients[response.client_id()];
+    let is_admin = client.is_admin();
+    let room_id = match client.room_id {
+        Some(room_id) if client.is_master() => Some(room_id),
+        _ if is_admin => None,
+        _ => {
+            response.add(Warning("Access denied.".to_string()).send_self());
+            return;
+        }
+    };
+
+    let is_unmuted = match server.find_client_mut(nick) {
+        Some(target) => {
+            let mutes_count = target.mutes.len();
+            target
+                .mutes
+                .retain(|m| !is_admin && (m.room_id.is_none() || m.room_id != room_id));
+            target.mutes.len() < mutes_count
+        }
+        None => is_admin && server.saved_mutes.remove(nick).is_some(),
+    };
+
+    let msg = if is_unmuted {
+        format!("{} is no longer muted.", nick)
+    } else {
+        format!("{} is not muted.", nick)
+    };
+    response.add(server_chat(msg).send_self());
+}
+
+pub fn send_announcements(server: &mut HWServer, now: Instant, response: &mut Response) {
+    for (target, msg) in server.announcements.take_due(now) {
+        let msg = global_chat(msg);
+        match target {
+            AnnouncementTarget::All => response.add(msg.send_all()),
+            AnnouncementTarget::Lobby => response.add(msg.send_all().in_lobby()),
+            AnnouncementTarget::Protocols(min, max) => {
+                let clients = server
+                    .collect_clients(|(_, c)| min <= c.protocol_number && c.protocol_number <= max);
+                response.add(msg.send_many(clients));
+            }
+        }
+    }
+}
+
+pub fn get_announcements(server: &HWServer, response: &mut Response) {
+    let now = Instant::now();
+    let mut count = 0;
+    for announcement in server.announcements.iter() {
+        let mut info = format!(
+            "#{} to {} in {}",
+            announcement.id,
+            announcement.target.to_protocol(),
+            utils::format_duration(announcement.next_time.saturating_duration_since(now))
+        );
+        if let Some(interval) = announcement.interval {
+            info.push_str(&format!(", every {}", utils::format_duration(interval)));
+        }
+        info.push_str(&format!(": {}", announcement.message));
+        response.add(server_chat(info).send_self());
+        count += 1;
+    }
+    if count == 0 {
+        response.add(server_chat("No announcements scheduled.".to_string()).send_self());
+    }
+}
+
 pub fn start_game(server: &mut HWServer, room_id: RoomId, response: &mut Response) {
+    let room = &server.rooms[room_id];
+    if room.protocol_number <= 43 && room.players_number != room.ready_players_number {
+        response.add(Warning("Not all players are ready".to_string()).send_self());
+        return;
+    } else if room.game_info.is_some() {
+        response.add(Warning("The game is already in progress".to_string()).send_self());
+        return;
+    }
+
+    if room.is_auto_balanced() {
+        let clans_number = room.clans_number().max(2);
+        balance_clans(server, room_id, clans_number, response);
+    }
+
     let (room_clients, room_nicks): (Vec<_>, Vec<_>) = server
         .clients
         .iter()
@@ -531,11 +772,8 @@ pub fn start_game(server: &mut HWServer, room_id: RoomId, response: &mut Respons
         response.add(
             Warning("The game can't be started with less than two clans!".to_string()).send_self(),
         );
-    } else if room.protocol_number <= 43 && room.players_number != room.ready_players_number {
-        response.add(Warning("Not all players are ready".to_string()).send_self());
-    } else if room.game_info.is_some() {
-        response.add(Warning("The game is already in progress".to_string()).send_self());
-    } else {
+    } else if hooks::on_game_start(server, room_id, response) == HookResult::Pass {
+        let room = &mut server.rooms[room_id];
         room.start_round();
         for id in room_clients {
             let c = &mut server.clients[id];
@@ -558,6 +796,80 @@ pub fn start_game(server: &mut HWServer, room_id: RoomId, response: &mut Respons
     }
 }
 
+pub fn send_to_players(
+    server: &HWServer,
+    nicks: &[String],
+    msg: HWServerMessage,
+    response: &mut Response,
+) {
+    let ids = server.collect_clients(|(_, c)| nicks.contains(&c.nick));
+    response.add(msg.send_many(ids));
+}
+
+/// Requests the rating updates of the owners of the teams that took part in a ranked game,
+/// `teams` are the names and owners of the teams
+pub fn update_ratings(
+    server: &HWServer,
+    teams: &[(String, String)],
+    winning_teams: &[String],
+    response: &mut Response,
+) {
+    let mut winners: Vec<String> = vec![];
+    let mut losers: Vec<String> = vec![];
+    for (name, owner) in teams {
+        let players = if winning_teams.contains(name) {
+            &mut winners
+        } else {
+            &mut losers
+        };
+        if !players.contains(owner) {
+            players.push(owner.clone());
+        }
+    }
+    losers.retain(|nick| !winners.contains(nick));
+
+    if winners.is_empty() || losers.is_empty() {
+        let players: Vec<_> = teams.iter().map(|(_, owner)| owner.clone()).collect();
+        let msg = server_chat("The game has no winner, the ratings are not updated.".to_string());
+        send_to_players(server, &players, msg, response);
+    } else {
+        let config = &server.config.ranked;
+        response.request_server_io(super::IoTask::UpdateRatings {
+            winners,
+            losers,
+            initial_rating: config.initial_rating,
+            k_factor: config.k_factor,
+        });
+    }
+}
+
+/// Rates a ranked game at once if it ended by the other clans leaving,
+/// otherwise queues its demo for a checker to find out the winners
+fn rate_game(server: &mut HWServer, room_id: RoomId, info: &GameInfo, response: &mut Response) {
+    let teams: Vec<_> = info
+        .teams_at_start
+        .iter()
+        .map(|(_, t)| (t.name.clone(), t.owner.clone()))
+        .collect();
+    if let Some(winners) = info.forfeit_winners() {
+        update_ratings(server, &teams, &winners, response);
+    } else if let Some(demo) = info.demo() {
+        server.add_unchecked_game(teams, demo);
+        response.add(
+            server_chat("The game result will be checked before updating the ratings.".to_string())
+                .send_all()
+                .in_room(room_id),
+        );
+        super::checker::send_unchecked_game(server, response);
+    } else {
+        response.add(
+            server_chat("The game result is unknown, the ratings are not updated.".to_string())
+                .send_all()
+                .in_room(room_id),
+        );
+    }
+}
+
 pub fn end_game(server: &mut HWServer, room_id: RoomId, response: &mut Response) {
     let room = &mut server.rooms[room_id];
     room.ready_players_number = 1;
@@ -569,7 +881,8 @@ pub fn end_game(server: &mut HWServer, room_id: RoomId, response: &mut Response)
     get_room_update(None, room, room_master, response);
     response.add(RoundFinished.send_all().in_room(room_id));
 
-    if let Some(info) = replace(&mut room.game_info, None) {
+    let game_info = replace(&mut room.game_info, None);
+    if let Some(ref info) = game_info {
         for (_, client) in server.clients.iter() {
             if client.room_id == Some(room_id) && client.is_joined_mid_game() {
                 super::common::get_room_config(room, client.id, response);
@@ -608,13 +921,23 @@ pub fn end_game(server: &mut HWServer, room_id: RoomId, response: &mut Response)
         };
         response.add(msg.send_all().in_room(room_id));
     }
+
+    if let Some(ref info) = game_info.filter(|_| server.rooms[room_id].is_ranked()) {
+        rate_game(server, room_id, info, response);
+    }
+
+    hooks::on_game_end(server, room_id, response);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::protocol::messages::HWServerMessage::ChatMsg;
-    use crate::server::actions::PendingMessage;
+    use crate::server::{
+        actions::PendingMessage,
+        chat_history::ChatHistory,
+        handlers::{handle, record_chat_history, test::*},
+    };
 
     fn reply2string(r: HWServerMessage) -> String {
         match r {
@@ -630,6 +953,40 @@ mod tests {
         }
     }
 
+    #[test]
+    fn start_game_balancing() {
+        let mut server = server();
+        add_client(&mut server, 0, "alice");
+        add_client(&mut server, 1, "bob");
+        let room_id = add_room(&mut server, "room", &[0, 1]);
+        add_team(&mut server, room_id, 0, "Red");
+        add_team(&mut server, room_id, 1, "Blue");
+        server.rooms[room_id].set_is_auto_balanced(true);
+        let colors = |server: &HWServer| -> Vec<u8> {
+            server.rooms[room_id]
+                .teams
+                .iter()
+                .map(|(_, t)| t.color)
+                .collect()
+        };
+
+        // the teams are not balanced when the game cannot start anyway
+        server.rooms[room_id].start_round();
+        let mut response = Response::new(0);
+        start_game(&mut server, room_id, &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["WARNING\nThe game is already in progress\n\n"]
+        );
+        assert_eq!(colors(&server), vec![0, 0]);
+
+        server.rooms[room_id].game_info = None;
+        let mut response = Response::new(0);
+        start_game(&mut server, room_id, &mut response);
+        assert!(messages(&server, &mut response).contains(&"RUN_GAME\n\n".to_string()));
+        assert_eq!(colors(&server), vec![0, 1]);
+    }
+
     /// This test terminates almost surely.
     #[test]
     fn test_handle_rnd_empty() {
@@ -658,4 +1015,191 @@ mod tests {
             }
         }
     }
+
+    fn history_nicks(history: &ChatHistory) -> Vec<&str> {
+        history.recent(None).map(|e| &e.nick[..]).collect()
+    }
+
+    fn chat(nick: &str) -> PendingMessage {
+        ChatMsg {
+            nick: nick.to_string(),
+            msg: "hi".to_string(),
+        }
+        .send_all()
+    }
+
+    #[test]
+    fn mute_clears_chat_history() {
+        let mut server = server();
+        add_client(&mut server, 0, "admin");
+        add_client(&mut server, 1, "target");
+        add_client(&mut server, 2, "master");
+        server.clients[0].set_is_admin(true);
+        let room_id = add_room(&mut server, "room", &[2, 1]);
+        for nick in &["target", "admin"] {
+            server.lobby_chat.push(nick.to_string(), "hi".to_string());
+            let room = &mut server.rooms[room_id];
+            room.chat_history.push(nick.to_string(), "hi".to_string());
+        }
+
+        let duration = Duration::from_secs(60);
+        mute_client(&mut server, "target", duration, None, &mut Response::new(2));
+        assert_eq!(
+            history_nicks(&server.rooms[room_id].chat_history),
+            vec!["admin"]
+        );
+        assert_eq!(history_nicks(&server.lobby_chat), vec!["target", "admin"]);
+
+        mute_client(&mut server, "target", duration, None, &mut Response::new(0));
+        assert_eq!(history_nicks(&server.lobby_chat), vec!["admin"]);
+    }
+
+    #[test]
+    fn muted_chat_is_not_recorded() {
+        let mut server = server();
+        add_client(&mut server, 0, "admin");
+        add_client(&mut server, 1, "target");
+        server.clients[0].set_is_admin(true);
+
+        let mut response = Response::new(1);
+        response.add(chat("target"));
+        record_chat_history(&mut server, &response);
+        assert_eq!(history_nicks(&server.lobby_chat), vec!["target"]);
+
+        let duration = Duration::from_secs(60);
+        mute_client(&mut server, "target", duration, None, &mut Response::new(0));
+        let mut response = Response::new(1);
+        response.add(chat("target"));
+        response.add(chat("admin"));
+        record_chat_history(&mut server, &response);
+        assert_eq!(history_nicks(&server.lobby_chat), vec!["admin"]);
+    }
+
+    fn mute_server() -> HWServer {
+        let mut server = server();
+        add_client(&mut server, 0, "admin");
+        add_client(&mut server, 1, "target");
+        add_client(&mut server, 2, "master");
+        server.clients[0].set_is_admin(true);
+        server
+    }
+
+    #[test]
+    fn mute_and_unmute() {
+        let mut server = mute_server();
+        let duration = Duration::from_secs(90);
+        let room_id = add_room(&mut server, "room", &[2, 1]);
+
+        let mut response = Response::new(1);
+        mute_client(&mut server, "master", duration, None, &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["WARNING\nAccess denied.\n\n"]
+        );
+        let mut response = Response::new(2);
+        mute_client(&mut server, "nobody", duration, None, &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\n[server]\nPlayer is not online.\n\n"]
+        );
+
+        let mut response = Response::new(2);
+        let reason = Some("spam".to_string());
+        mute_client(&mut server, "target", duration, reason, &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\n[server]\ntarget is muted for 1m 30s: spam\n\n"]
+        );
+        let now = Instant::now();
+        assert_eq!(
+            server.clients[1].active_mute(now).unwrap().room_id,
+            Some(room_id)
+        );
+
+        // a room mute does not apply outside of the room
+        exit_room(&mut server, 1, &mut Response::new(1), "part");
+        assert!(server.clients[1].active_mute(now).is_none());
+        server.move_to_room(1, room_id);
+
+        let mut response = Response::new(2);
+        unmute_client(&mut server, "target", &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\n[server]\ntarget is no longer muted.\n\n"]
+        );
+        assert!(server.clients[1].mutes.is_empty());
+
+        mute_client(&mut server, "target", duration, None, &mut Response::new(0));
+        let mut response = Response::new(2);
+        unmute_client(&mut server, "target", &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\n[server]\ntarget is not muted.\n\n"]
+        );
+        let mut response = Response::new(0);
+        unmute_client(&mut server, "target", &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\n[server]\ntarget is no longer muted.\n\n"]
+        );
+        assert!(server.clients[1].mutes.is_empty());
+    }
+
+    #[test]
+    fn mute_expiry() {
+        let mut server = mute_server();
+        let duration = Duration::from_secs(60);
+        mute_client(&mut server, "target", duration, None, &mut Response::new(0));
+
+        let now = Instant::now();
+        assert!(server.clients[1].active_mute(now).is_some());
+        assert!(server.clients[1].active_mute(now + duration).is_none());
+
+        let mut response = Response::new(1);
+        handle(
+            &mut server,
+            1,
+            &mut response,
+            HWProtocolMessage::Chat("hi".to_string()),
+        );
+        assert!(
+            messages(&server, &mut response)[0].starts_with("CHAT\n[server]\nYou are muted for")
+        );
+
+        // an expired mute is dropped when a new one is added
+        server.clients[1].mutes[0].until = now;
+        mute_client(&mut server, "target", duration, None, &mut Response::new(2));
+        assert_eq!(server.clients[1].mutes.len(), 1);
+    }
+
+    #[test]
+    fn saved_mutes() {
+        let mut server = mute_server();
+        server.clients[1].set_is_registered(true);
+        let duration = Duration::from_secs(60);
+        mute_client(&mut server, "target", duration, None, &mut Response::new(0));
+        mute_client(&mut server, "master", duration, None, &mut Response::new(0));
+
+        // only the mutes of the registered players are kept
+        server.remove_client(1);
+        server.remove_client(2);
+        assert!(server.saved_mutes.contains_key("target"));
+        assert!(!server.saved_mutes.contains_key("master"));
+
+        add_client(&mut server, 1, "target");
+        server.restore_mute(1);
+        assert!(server.clients[1].active_mute(Instant::now()).is_some());
+        assert!(server.saved_mutes.is_empty());
+
+        // an admin can lift the mute of an offline player
+        server.clients[1].set_is_registered(true);
+        server.remove_client(1);
+        let mut response = Response::new(0);
+        unmute_client(&mut server, "target", &mut response);
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\n[server]\ntarget is no longer muted.\n\n"]
+        );
+        assert!(server.saved_mutes.is_empty());
+    }
 }
@@ -0,0 +1,262 @@
+use super::Response;
+use crate::{
+    protocol::messages::{
+        server_chat,
+        HWServerMessage::{ChatMsg, RoomAdd, RoomRemove, RoomUpdated},
+    },
+    server::{
+        core::HWServer,
+        federation::{PeerEvent, PeerId, PeerMessage, RemoteRoom, RemoteServer},
+    },
+};
+
+/// Snapshot of the local rooms sent to the linked servers
+pub fn get_room_list(server: &HWServer) -> PeerMessage {
+    PeerMessage::Rooms(
+        server
+            .rooms
+            .iter()
+            .map(|(_, room)| RemoteRoom {
+                protocol_number: room.protocol_number,
+                info: room.info(room.master_id.map(|id| &server.clients[id])),
+            })
+            .collect(),
+    )
+}
+
+/// Delivers a private message to a local client, returns `false` if there is no such client
+pub fn deliver_private_message(
+    server: &HWServer,
+    from: &str,
+    to: &str,
+    msg: &str,
+    response: &mut Response,
+) -> bool {
+    match server.find_client(to) {
+        Some(client) => {
+            let msg = ChatMsg {
+                nick: from.to_string(),
+                msg: format!("[PM] {}", msg),
+            };
+            response.add(msg.send(client.id));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Players of the other servers are addressed as `nick@server`,
+/// returns `false` if there is no such server
+pub fn send_remote_private_message(
+    server: &HWServer,
+    from: &str,
+    to: &str,
+    msg: &str,
+    response: &mut Response,
+) -> bool {
+    let is_remote = match to.rfind('@') {
+        Some(index) => server
+            .federation
+            .servers
+            .values()
+            .any(|remote| remote.name == to[index + 1..]),
+        None => false,
+    };
+    if is_remote {
+        response.send_peers(PeerMessage::PrivateMessage {
+            from: from.to_string(),
+            to: to.to_string(),
+            msg: msg.to_string(),
+        });
+    }
+    is_remote
+}
+
+/// Returns the nick of a local player from its federation address
+fn local_nick<'a>(server: &HWServer, address: &'a str) -> Option<&'a str> {
+    let suffix = format!("@{}", server.config.federation.name);
+    if address.ends_with(&suffix) {
+        Some(&address[..address.len() - suffix.len()])
+    } else {
+        None
+    }
+}
+
+fn update_rooms(remote: &RemoteServer, old_rooms: &[RemoteRoom], response: &mut Response) {
+    for room in old_rooms {
+        if !remote.rooms.iter().any(|r| r.name() == room.name()) {
+            response.add(
+                RoomRemove(remote.room_name(room))
+                    .send_all()
+                    .with_protocol(room.protocol_number),
+            );
+        }
+    }
+
+    for room in &remote.rooms {
+        let msg = match old_rooms.iter().find(|r| r.name() == room.name()) {
+            Some(old_room) if old_room == room => continue,
+            Some(_) => RoomUpdated(remote.room_name(room), remote.room_info(room)),
+            None => RoomAdd(remote.room_info(room)),
+        };
+        response.add(msg.send_all().with_protocol(room.protocol_number));
+    }
+}
+
+fn remove_server(server: &mut HWServer, peer_id: PeerId, response: &mut Response) {
+    if let Some(remote) = server.federation.servers.remove(&peer_id) {
+        for room in &remote.rooms {
+            response.add(
+                RoomRemove(remote.room_name(room))
+                    .send_all()
+                    .with_protocol(room.protocol_number),
+            );
+        }
+    }
+}
+
+pub fn handle_event(server: &mut HWServer, response: &mut Response, event: PeerEvent) {
+    match event {
+        PeerEvent::Connected {
+            peer_id,
+            name,
+            host,
+            port,
+        } => {
+            server.federation.servers.insert(
+                peer_id,
+                RemoteServer {
+                    name,
+                    host,
+                    port,
+                    rooms: vec![],
+                },
+            );
+            response.send_peers(get_room_list(server));
+        }
+        PeerEvent::Message(peer_id, PeerMessage::Rooms(rooms)) => {
+            if let Some(remote) = server.federation.servers.get_mut(&peer_id) {
+                let old_rooms = std::mem::replace(&mut remote.rooms, rooms);
+                update_rooms(remote, &old_rooms, response);
+            }
+        }
+        PeerEvent::Message(peer_id, PeerMessage::PrivateMessage { from, to, msg }) => {
+            if let (Some(remote), Some(nick)) = (
+                server.federation.servers.get(&peer_id),
+                local_nick(server, &to),
+            ) {
+                let from = format!("{}@{}", from, remote.name);
+                if !deliver_private_message(server, &from, nick, &msg, response) {
+                    response.send_peers(PeerMessage::PrivateMessageFailed { from, to });
+                }
+            }
+        }
+        PeerEvent::Message(_, PeerMessage::PrivateMessageFailed { from, .. }) => {
+            if let Some(client) = local_nick(server, &from).and_then(|n| server.find_client(n)) {
+                response.add(server_chat("Player is not online.".to_string()).send(client.id));
+            }
+        }
+        PeerEvent::Message(..) => (),
+        PeerEvent::Disconnected(peer_id) => remove_server(server, peer_id, response),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{config::ServerConfig, handlers::test::*};
+
+    fn server_with_peer() -> HWServer {
+        let mut config = ServerConfig::default();
+        config.federation.name = "us".to_string();
+        let mut server = server_with_config(config);
+        server.federation.servers.insert(
+            0,
+            RemoteServer {
+                name: "eu".to_string(),
+                host: "eu.example.org".to_string(),
+                port: 46631,
+                rooms: vec![],
+            },
+        );
+        add_client(&mut server, 0, "alice");
+        server
+    }
+
+    fn private_message(from: &str, to: &str) -> PeerEvent {
+        PeerEvent::Message(
+            0,
+            PeerMessage::PrivateMessage {
+                from: from.to_string(),
+                to: to.to_string(),
+                msg: "hi".to_string(),
+            },
+        )
+    }
+
+    #[test]
+    fn remote_private_message() {
+        let mut server = server_with_peer();
+
+        let mut response = Response::new(0);
+        assert!(send_remote_private_message(
+            &server,
+            "alice",
+            "bob@eu",
+            "hi",
+            &mut response
+        ));
+        assert_eq!(response.extract_peer_messages().count(), 1);
+        assert!(!send_remote_private_message(
+            &server,
+            "alice",
+            "bob@asia",
+            "hi",
+            &mut response
+        ));
+        assert!(!send_remote_private_message(
+            &server,
+            "alice",
+            "bob",
+            "hi",
+            &mut response
+        ));
+        assert_eq!(response.extract_peer_messages().count(), 0);
+
+        let mut response = Response::new(0);
+        handle_event(
+            &mut server,
+            &mut response,
+            private_message("bob", "alice@us"),
+        );
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\nbob@eu\n[PM] hi\n\n"]
+        );
+
+        let mut response = Response::new(0);
+        handle_event(
+            &mut server,
+            &mut response,
+            private_message("bob", "carol@us"),
+        );
+        assert_eq!(
+            response.extract_peer_messages().collect::<Vec<_>>(),
+            vec![PeerMessage::PrivateMessageFailed {
+                from: "bob@eu".to_string(),
+                to: "carol@us".to_string(),
+            }]
+        );
+
+        let mut response = Response::new(0);
+        let failure = PeerMessage::PrivateMessageFailed {
+            from: "alice@us".to_string(),
+            to: "carol@eu".to_string(),
+        };
+        handle_event(&mut server, &mut response, PeerEvent::Message(0, failure));
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["CHAT\n[server]\nPlayer is not online.\n\n"]
+        );
+    }
+}
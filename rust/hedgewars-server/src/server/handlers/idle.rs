@@ -0,0 +1,237 @@
+use super::{common, Response};
+use crate::{
+    protocol::messages::server_chat,
+    server::{
+        client::HWClient,
+        core::HWServer,
+        coretypes::{ClientId, RoomId},
+    },
+};
+use std::time::{Duration, Instant};
+
+enum IdleStatus {
+    Active,
+    Warned,
+    TimedOut,
+}
+
+fn idle_time(client: &HWClient, now: Instant) -> Duration {
+    now.saturating_duration_since(client.last_activity)
+}
+
+fn update_idle_status(
+    deadline: &mut Option<Instant>,
+    idle_time: Duration,
+    timeout: Duration,
+    warning_time: Duration,
+    now: Instant,
+) -> IdleStatus {
+    match *deadline {
+        Some(deadline) if now >= deadline => IdleStatus::TimedOut,
+        Some(_) => IdleStatus::Active,
+        None if idle_time + warning_time >= timeout => {
+            *deadline = Some(now + warning_time);
+            IdleStatus::Warned
+        }
+        None => IdleStatus::Active,
+    }
+}
+
+fn check_masters(server: &mut HWServer, now: Instant, responses: &mut Vec<Response>) {
+    let config = &server.config.idle;
+    let (timeout, warning_time) = match config.master_timeout() {
+        Some(timeout) => (timeout, config.warning_time()),
+        None => return,
+    };
+
+    let masters: Vec<(RoomId, ClientId)> = server
+        .rooms
+        .iter()
+        .filter(|(_, r)| r.game_info.is_none() && !r.is_fixed())
+        .filter_map(|(id, r)| r.master_id.map(|master_id| (id, master_id)))
+        .collect();
+
+    for (room_id, master_id) in masters {
+        let room = &server.rooms[room_id];
+        let new_master_id = server
+            .room_clients(room_id)
+            .filter(|id| *id != master_id)
+            .map(|id| &server.clients[id])
+            .filter(|c| {
+                common::can_be_master(c, room) && idle_time(c, now) + warning_time < timeout
+            })
+            .max_by_key(|c| c.last_activity)
+            .map(|c| c.id);
+
+        let new_master_id = match new_master_id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let mut response = Response::new(master_id);
+        let master = &mut server.clients[master_id];
+        let idle_time = idle_time(master, now);
+        match update_idle_status(
+            &mut master.master_idle_deadline,
+            idle_time,
+            timeout,
+            warning_time,
+            now,
+        ) {
+            IdleStatus::Active => (),
+            IdleStatus::Warned => {
+                let msg = format!(
+                    "You will lose the room master status in {} seconds unless you become active.",
+                    warning_time.as_secs()
+                );
+                response.add(server_chat(msg).send_self());
+            }
+            IdleStatus::TimedOut => {
+                server.clients[master_id].master_idle_deadline = None;
+                let msg = format!(
+                    "{} was idle for too long, {} is the new room master.",
+                    server.clients[master_id].nick, server.clients[new_master_id].nick
+                );
+                common::change_master(server, room_id, new_master_id, &mut response);
+                response.add(server_chat(msg).send_all().in_room(room_id));
+            }
+        }
+        responses.push(response);
+    }
+}
+
+fn check_players(server: &mut HWServer, now: Instant, responses: &mut Vec<Response>) {
+    let config = &server.config.idle;
+    let (timeout, warning_time) = match config.player_timeout() {
+        Some(timeout) => (timeout, config.warning_time()),
+        None => return,
+    };
+
+    let rooms = &server.rooms;
+    let players = server.collect_clients(|(_, c)| {
+        !c.is_checker()
+            && !c.is_master()
+            && c.room_id
+                .map_or(false, |room_id| rooms[room_id].game_info.is_none())
+    });
+
+    for client_id in players {
+        let mut response = Response::new(client_id);
+        let client = &mut server.clients[client_id];
+        let idle_time = idle_time(client, now);
+        match update_idle_status(
+            &mut client.player_idle_deadline,
+            idle_time,
+            timeout,
+            warning_time,
+            now,
+        ) {
+            IdleStatus::Active => (),
+            IdleStatus::Warned => {
+                let msg = format!(
+                    "You will be moved to the lobby in {} seconds unless you become active.",
+                    warning_time.as_secs()
+                );
+                response.add(server_chat(msg).send_self());
+            }
+            IdleStatus::TimedOut => {
+                server.clients[client_id].player_idle_deadline = None;
+                common::exit_room(server, client_id, &mut response, "idle");
+                response.add(
+                    server_chat("You were moved to the lobby for being idle.".to_string())
+                        .send_self(),
+                );
+            }
+        }
+        responses.push(response);
+    }
+}
+
+pub fn handle_idle_check(server: &mut HWServer, now: Instant) -> Vec<Response> {
+    let mut responses = vec![];
+    check_masters(server, now, &mut responses);
+    check_players(server, now, &mut responses);
+    responses
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        config::{IdleConfig, ServerConfig},
+        handlers::test::*,
+    };
+
+    fn server(master_timeout: Option<u64>, player_timeout: Option<u64>) -> HWServer {
+        let mut server = server_with_config(ServerConfig {
+            idle: IdleConfig {
+                master_timeout,
+                player_timeout,
+                warning_time: 10,
+            },
+            ..ServerConfig::default()
+        });
+        for (id, nick) in ["master", "player", "other"].iter().enumerate() {
+            add_client(&mut server, id, nick);
+        }
+        add_room(&mut server, "room", &[0, 1, 2]);
+        server
+    }
+
+    fn after(start: Instant, seconds: u64) -> Instant {
+        start + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn idle_master() {
+        let mut server = server(Some(100), Some(200));
+        let start = server.clients[0].last_activity;
+        server.clients[1].last_activity = after(start, 90);
+
+        handle_idle_check(&mut server, after(start, 95));
+        assert!(server.clients[0].master_idle_deadline.is_some());
+        assert!(server.clients[0].player_idle_deadline.is_none());
+
+        handle_idle_check(&mut server, after(start, 106));
+        assert_eq!(server.rooms[0].master_id, Some(1));
+        assert!(server.clients[1].is_master());
+        assert!(!server.clients[0].is_master());
+        assert!(server.clients[0].master_idle_deadline.is_none());
+
+        // the former master is warned before being moved as a player
+        handle_idle_check(&mut server, after(start, 195));
+        assert!(server.clients[0].player_idle_deadline.is_some());
+        assert_eq!(server.clients[0].room_id, Some(0));
+    }
+
+    #[test]
+    fn idle_players() {
+        let mut server = server(None, Some(100));
+        let start = server.clients[0].last_activity;
+        server.clients[2].last_activity = after(start, 90);
+
+        handle_idle_check(&mut server, after(start, 95));
+        assert!(server.clients[1].player_idle_deadline.is_some());
+        assert!(server.clients[2].player_idle_deadline.is_none());
+        assert!(server.clients[0].player_idle_deadline.is_none());
+
+        handle_idle_check(&mut server, after(start, 106));
+        assert_eq!(server.clients[1].room_id, None);
+        assert_eq!(server.clients[2].room_id, Some(0));
+        assert_eq!(server.clients[0].room_id, Some(0));
+    }
+
+    #[test]
+    fn empty_rooms() {
+        let mut server = server(None, Some(100));
+        let start = server.clients[0].last_activity;
+        handle_idle_check(&mut server, after(start, 95));
+        handle_idle_check(&mut server, after(start, 106));
+        assert_eq!(server.clients[1].room_id, None);
+        assert_eq!(server.clients[2].room_id, None);
+
+        // non-fixed rooms do not outlive their last player, so there are no empty ones to expire
+        common::exit_room(&mut server, 0, &mut Response::new(0), "bye");
+        assert!(!server.has_room("room"));
+    }
+}
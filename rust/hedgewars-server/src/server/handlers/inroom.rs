@@ -1,6 +1,11 @@
 use mio;
 
 use super::common::rnd_reply;
+#[cfg(feature = "official-server")]
+use crate::server::{
+    desync::DesyncReport,
+    room_library::{is_valid_name, PresetScope},
+};
 use crate::utils::to_engine_msg;
 use crate::{
     protocol::messages::{
@@ -81,6 +86,19 @@ fn is_msg_timed(msg: &[u8]) -> bool {
         .is_some()
 }
 
+/// '#' increments the high word of the engine ticks,
+/// timed messages carry the low word in their last two bytes
+fn update_engine_ticks(ticks: u32, msg: &[u8]) -> u32 {
+    match msg.get(1) {
+        Some(b'#') => (ticks & !0xFFFF).wrapping_add(0x1_0000),
+        Some(_) if is_msg_timed(msg) && msg.len() >= 4 => {
+            let low = u16::from_be_bytes([msg[msg.len() - 2], msg[msg.len() - 1]]);
+            (ticks & !0xFFFF) | u32::from(low)
+        }
+        _ => ticks,
+    }
+}
+
 fn voting_description(kind: &VoteType) -> String {
     format!(
         "New voting started: {}",
@@ -104,6 +122,94 @@ fn room_message_flag(msg: &HWProtocolMessage) -> RoomFlags {
     }
 }
 
+#[cfg(feature = "official-server")]
+const INVALID_NAME_WARNING: &str =
+    "Names must be 1-40 characters long and contain only letters, digits, spaces, '-' and '_'.";
+
+#[cfg(feature = "official-server")]
+fn save_preset(room: &HWRoom, scope: PresetScope, name: String, response: &mut super::Response) {
+    match room.get_config_preset() {
+        Ok(contents) => response.request_io(super::IoTask::SavePreset {
+            scope,
+            name,
+            contents,
+        }),
+        Err(e) => {
+            warn!("Error while serializing the room config: {}", e);
+            response.add(Warning("Unable to serialize the room config.".to_string()).send_self())
+        }
+    }
+}
+
+fn report_desync(server: &HWServer, room_id: RoomId, ticks: u32, response: &mut super::Response) {
+    let room = &server.rooms[room_id];
+    let info = match room.game_info {
+        Some(ref info) => info,
+        None => return,
+    };
+    let nick = |id: &ClientId| {
+        if server.clients.contains(*id) {
+            &server.clients[*id].nick[..]
+        } else {
+            "?"
+        }
+    };
+    let nicks = |ids: &[ClientId]| ids.iter().map(nick).collect::<Vec<_>>().join(", ");
+
+    let groups = info.checksum_groups(ticks);
+    if groups.len() < 2 {
+        return;
+    }
+    // the clients outside of the largest group are the ones that desynced
+    let msg = if groups[0].len() > groups[1].len() {
+        format!(
+            "Desync detected at tick {}: {} disagree with {}",
+            ticks,
+            nicks(&groups[1..].concat()),
+            nicks(&groups[0])
+        )
+    } else {
+        format!(
+            "Desync detected at tick {}: no majority between {}",
+            ticks,
+            groups
+                .iter()
+                .map(|ids| nicks(ids))
+                .collect::<Vec<_>>()
+                .join(" / ")
+        )
+    };
+    response.add(server_chat(msg).send_all().in_room(room_id));
+}
+
+/// Only the first desync report at the ticks is written as the following ones repeat the message log
+#[cfg(feature = "official-server")]
+fn save_desync_report(
+    server: &mut HWServer,
+    room_id: RoomId,
+    ticks: u32,
+    response: &mut super::Response,
+) {
+    let filename = match server.config.desync_log {
+        Some(ref filename) => filename.clone(),
+        None => return,
+    };
+    let clients = &server.clients;
+    let room = &mut server.rooms[room_id];
+    if let Some(ref mut info) = room.game_info {
+        if info.reported_desyncs.insert(ticks) {
+            let checksums = info.checksums[&ticks]
+                .iter()
+                .filter(|(id, _)| clients.contains(*id))
+                .map(|(id, c)| (clients[*id].nick.clone(), c.clone()))
+                .collect();
+            let report =
+                DesyncReport::new(room.name.clone(), ticks, checksums, info.msg_log.clone());
+            response.request_server_io(super::IoTask::SaveDesyncReport { filename, report });
+        }
+    }
+}
+
 pub fn handle(
     server: &mut HWServer,
     client_id: ClientId,
@@ -233,6 +339,20 @@ pub fn handle(
                         .send_self(),
                 );
             } else {
+                if let Some(ref assets) = server.assets {
+                    let mut replaced = assets.validate_team(&mut info);
+                    if !replaced.is_empty() {
+                        replaced.dedup();
+                        response.add(
+                            Warning(format!(
+                                "Unknown assets were replaced with defaults: {}",
+                                replaced.join(", ")
+                            ))
+                            .send_self(),
+                        );
+                    }
+                }
+
                 info.owner = client.nick.clone();
                 let team = room.add_team(client.id, *info, client.protocol_number < 42);
                 client.teams_in_game += 1;
@@ -333,8 +453,22 @@ pub fn handle(
         Cfg(cfg) => {
             if room.is_fixed() {
                 response.add(Warning("Access denied.".to_string()).send_self());
+            } else if room.is_ranked() && matches!(cfg, GameCfg::Scheme(..) | GameCfg::Ammo(..)) {
+                response.add(
+                    Warning("The scheme and the weapons of a ranked room are locked.".to_string())
+                        .send_self(),
+                );
             } else if !client.is_master() {
                 response.add(Error("You're not the room master!".to_string()).send_self());
+            } else if !server
+                .assets
+                .as_ref()
+                .map_or(true, |a| a.is_cfg_valid(&cfg))
+            {
+                response.add(
+                    Warning("The map, theme or script is not available on the server.".to_string())
+                        .send_self(),
+                );
             } else {
                 let cfg = match cfg {
                     GameCfg::Scheme(name, mut values) => {
@@ -363,6 +497,10 @@ pub fn handle(
         #[cfg(feature = "official-server")]
         SaveRoom(filename) => {
             if client.is_admin() {
+                if !is_valid_name(&filename) {
+                    response.add(Warning(INVALID_NAME_WARNING.to_string()).send_self());
+                    return;
+                }
                 match room.get_saves() {
                     Ok(contents) => response.request_io(super::IoTask::SaveRoom {
                         room_id,
@@ -382,9 +520,69 @@ pub fn handle(
         #[cfg(feature = "official-server")]
         LoadRoom(filename) => {
             if client.is_admin() {
-                response.request_io(super::IoTask::LoadRoom { room_id, filename });
+                if !is_valid_name(&filename) {
+                    response.add(Warning(INVALID_NAME_WARNING.to_string()).send_self());
+                } else {
+                    response.request_io(super::IoTask::LoadRoom { room_id, filename });
+                }
+            }
+        }
+        #[cfg(feature = "official-server")]
+        ListPresets => {
+            let nick = Some(client.nick.clone()).filter(|_| client.is_registered());
+            response.request_io(super::IoTask::ListPresets { nick });
+        }
+        #[cfg(feature = "official-server")]
+        LoadPreset(name) => {
+            if room.is_fixed() || room.is_ranked() {
+                response.add(Warning("Access denied.".to_string()).send_self());
+            } else if !client.is_master() {
+                response.add(Error("You're not the room master!".to_string()).send_self());
+            } else if !is_valid_name(&name) {
+                response.add(Warning(INVALID_NAME_WARNING.to_string()).send_self());
+            } else {
+                let nick = Some(client.nick.clone()).filter(|_| client.is_registered());
+                response.request_io(super::IoTask::LoadPreset {
+                    room_id,
+                    nick,
+                    name,
+                });
             }
         }
+        #[cfg(feature = "official-server")]
+        SavePreset(_) | DeletePreset(_) if !client.is_registered() => {
+            response.add(
+                Warning("Only registered players can have their own presets.".to_string())
+                    .send_self(),
+            );
+        }
+        #[cfg(feature = "official-server")]
+        PublishPreset(_) | UnpublishPreset(_) if !client.is_admin() => {
+            response.add(Warning("Access denied.".to_string()).send_self());
+        }
+        #[cfg(feature = "official-server")]
+        SavePreset(name) | PublishPreset(name) | DeletePreset(name) | UnpublishPreset(name)
+            if !is_valid_name(&name) =>
+        {
+            response.add(Warning(INVALID_NAME_WARNING.to_string()).send_self());
+        }
+        #[cfg(feature = "official-server")]
+        SavePreset(name) => {
+            let scope = PresetScope::Private(client.nick.clone());
+            save_preset(room, scope, name, response);
+        }
+        #[cfg(feature = "official-server")]
+        PublishPreset(name) => save_preset(room, PresetScope::Public, name, response),
+        #[cfg(feature = "official-server")]
+        DeletePreset(name) => {
+            let scope = PresetScope::Private(client.nick.clone());
+            response.request_io(super::IoTask::DeletePreset { scope, name });
+        }
+        #[cfg(feature = "official-server")]
+        UnpublishPreset(name) => {
+            let scope = PresetScope::Public;
+            response.request_io(super::IoTask::DeletePreset { scope, name });
+        }
         Delete(name) => {
             if !room.delete_config(&name) {
                 response.add(Warning(format!("Save doesn't exist: {}", name)).send_self());
@@ -422,6 +620,9 @@ pub fn handle(
                         Some(format!("Available maps: {}", names.join(", ")))
                     }
                 }
+                VoteType::Map(Some(_)) if room.is_ranked() => {
+                    Some("/callvote map: The config of a ranked room is locked!".to_string())
+                }
                 VoteType::Map(Some(name)) => {
                     if room.saves.get(&name[..]).is_some() {
                         None
@@ -485,12 +686,46 @@ pub fn handle(
                 response,
             );
         }
+        ToggleRegisteredOnly if room.is_ranked() => {
+            response.add(
+                Warning("Ranked rooms only allow registered players.".to_string()).send_self(),
+            );
+        }
         ToggleRestrictJoin | ToggleRestrictTeams | ToggleRegisteredOnly => {
             if client.is_master() {
                 room.flags.toggle(room_message_flag(&message));
                 super::common::get_room_update(None, room, Some(&client), response);
             }
         }
+        Balance(clans_number) => {
+            if !client.is_master() {
+                response.add(Error("You're not the room master!".to_string()).send_self());
+            } else if room.game_info.is_some() {
+                response.add(Warning("The game is already in progress".to_string()).send_self());
+            } else {
+                let clans_number = clans_number.unwrap_or(2);
+                if clans_number < 2 || clans_number > MAX_TEAMS_IN_ROOM {
+                    response.add(
+                        Warning("/balance: specify number from 2 to 8".to_string()).send_self(),
+                    );
+                } else {
+                    super::common::balance_clans(server, room_id, clans_number, response);
+                }
+            }
+        }
+        AutoBalance(value) => {
+            if !client.is_master() {
+                response.add(Error("You're not the room master!".to_string()).send_self());
+            } else {
+                room.set_is_auto_balanced(value);
+                let msg = if value {
+                    "Clans will be balanced when the game starts."
+                } else {
+                    "Automatic clan balancing disabled."
+                };
+                response.add(server_chat(msg.to_string()).send_all().in_room(room_id));
+            }
+        }
         StartGame => {
             super::common::start_game(server, room_id, response);
         }
@@ -508,6 +743,7 @@ pub fn handle(
                     }
                 });
 
+                let ordered = valid.clone();
                 let em_response = encode(&valid.flat_map(|msg| msg).cloned().collect::<Vec<_>>());
                 if !em_response.is_empty() {
                     response.add(
@@ -517,6 +753,7 @@ pub fn handle(
                             .but_self(),
                     );
                 }
+                let mut desyncs = vec![];
                 let em_log = encode(&non_empty.flat_map(|msg| msg).cloned().collect::<Vec<_>>());
                 if let Some(ref mut info) = room.game_info {
                     if !em_log.is_empty() {
@@ -525,6 +762,25 @@ pub fn handle(
                     if let Some(msg) = sync_msg {
                         info.sync_msg = msg;
                     }
+
+                    // the checksums are not timed, so they are keyed by the time of the last timed message
+                    let mut ticks = info.engine_ticks.get(&client_id).cloned().unwrap_or(0);
+                    for msg in ordered {
+                        ticks = update_engine_ticks(ticks, msg);
+                        if msg.get(1) == Some(&b'M') {
+                            let checksum = String::from_utf8_lossy(&msg[2..]).into_owned();
+                            if info.add_checksum(client_id, ticks, checksum) {
+                                desyncs.push(ticks);
+                            }
+                        }
+                    }
+                    info.engine_ticks.insert(client_id, ticks);
+                }
+
+                for ticks in desyncs {
+                    report_desync(server, room_id, ticks, response);
+                    #[cfg(feature = "official-server")]
+                    save_desync_report(server, room_id, ticks, response);
                 }
             }
         }
@@ -613,3 +869,83 @@ pub fn handle(
         _ => warn!("Unimplemented!"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::handlers::{test::*, Response};
+
+    fn engine_message(messages: &[&[u8]]) -> HWProtocolMessage {
+        let mut data = vec![];
+        for msg in messages {
+            data.push(msg.len() as u8);
+            data.extend_from_slice(msg);
+        }
+        HWProtocolMessage::EngineMessage(encode(&data))
+    }
+
+    fn send_checksum(server: &mut HWServer, client_id: ClientId, checksum: &[u8]) -> Vec<String> {
+        let mut response = Response::new(client_id);
+        let checksum = [&b"M"[..], checksum].concat();
+        let message = engine_message(&[b"+\x00\x10", &checksum]);
+        handle(server, client_id, &mut response, 0, message);
+        messages(server, &mut response)
+            .into_iter()
+            .filter(|msg| msg.contains("Desync"))
+            .collect()
+    }
+
+    #[test]
+    fn engine_ticks() {
+        assert_eq!(update_engine_ticks(0, b"\x03+\x01\x02"), 0x0102);
+        assert_eq!(update_engine_ticks(0x0102, b"\x01#"), 0x1_0000);
+        assert_eq!(update_engine_ticks(0x1_0000, b"\x03+\x00\x05"), 0x1_0005);
+        assert_eq!(update_engine_ticks(0x1_0005, b"\x04M1|2"), 0x1_0005);
+    }
+
+    #[test]
+    fn desync_minority() {
+        let mut server = server();
+        for (id, nick) in ["a", "b", "c"].iter().enumerate() {
+            add_client(&mut server, id, nick);
+            server.clients[id].teams_in_game = 1;
+        }
+        add_room(&mut server, "room", &[0, 1, 2]);
+        server.rooms[0].start_round();
+
+        assert!(send_checksum(&mut server, 0, b"1|2").is_empty());
+        let report = send_checksum(&mut server, 1, b"1|3");
+        assert!(report[0].contains("Desync detected at tick 16: no majority between a / b"));
+        let report = send_checksum(&mut server, 2, b"1|2");
+        assert!(report[0].contains("Desync detected at tick 16: b disagree with a, c"));
+    }
+
+    #[cfg(feature = "official-server")]
+    #[test]
+    fn desync_log() {
+        use crate::server::{config::ServerConfig, handlers::IoTask};
+
+        let mut server = server_with_config(ServerConfig {
+            desync_log: Some("desync.log".to_string()),
+            ..ServerConfig::default()
+        });
+        for (id, nick) in ["a", "b", "c"].iter().enumerate() {
+            add_client(&mut server, id, nick);
+            server.clients[id].teams_in_game = 1;
+        }
+        add_room(&mut server, "room", &[0, 1, 2]);
+        server.rooms[0].start_round();
+
+        let mut reports = 0;
+        for (id, checksum) in [&b"M1|2"[..], b"M1|3", b"M1|2"].iter().enumerate() {
+            let mut response = Response::new(id);
+            let message = engine_message(&[b"+\x00\x10", checksum]);
+            handle(&mut server, id, &mut response, 0, message);
+            reports += response
+                .extract_io_tasks()
+                .filter(|(_, task)| matches!(task, IoTask::SaveDesyncReport { .. }))
+                .count();
+        }
+        assert_eq!(reports, 1);
+    }
+}
@@ -16,6 +16,58 @@ use crate::{
 use log::*;
 use std::{collections::HashSet, convert::identity};
 
+fn create_room(
+    server: &mut HWServer,
+    client_id: ClientId,
+    response: &mut super::Response,
+    name: String,
+    password: Option<String>,
+    ranked_preset: Option<String>,
+) {
+    if is_name_illegal(&name) {
+        response.add(Warning("Illegal room name! A room name must be between 1-40 characters long, must not have a trailing or leading space and must not have any of these characters: $()*+?[]^{|}".to_string()).send_self());
+    } else if server.has_room(&name) {
+        response.add(Warning("A room with the same name already exists.".to_string()).send_self());
+    } else {
+        let flags_msg = ClientFlags(
+            add_flags(&[Flags::RoomMaster, Flags::Ready]),
+            vec![server.clients[client_id].nick.clone()],
+        );
+
+        let room_id = server.create_room(client_id, name, password);
+        if let Some(preset) = ranked_preset {
+            let room = &mut server.rooms[room_id];
+            room.set_is_ranked(true);
+            room.set_unregistered_players_restriction(true);
+            if let Err(e) = room.set_config_preset(&preset) {
+                warn!("Error while deserializing the ranked preset: {}", e);
+            }
+        }
+
+        let room = &server.rooms[room_id];
+        let client = &server.clients[client_id];
+
+        response.add(
+            RoomAdd(room.info(Some(&client)))
+                .send_all()
+                .with_protocol(room.protocol_number),
+        );
+        response.add(RoomJoined(vec![client.nick.clone()]).send_self());
+        response.add(flags_msg.send_self());
+
+        response
+            .add(ClientFlags(add_flags(&[Flags::InRoom]), vec![client.nick.clone()]).send_self());
+
+        if room.is_ranked() {
+            super::common::get_room_config(room, client_id, response);
+            response.add(
+                server_chat("This room is ranked, the game settings are locked.".to_string())
+                    .send_self(),
+            );
+        }
+    };
+}
+
 pub fn handle(
     server: &mut HWServer,
     client_id: ClientId,
@@ -25,34 +77,30 @@ pub fn handle(
     use crate::protocol::messages::HWProtocolMessage::*;
     match message {
         CreateRoom(name, password) => {
-            if is_name_illegal(&name) {
-                response.add(Warning("Illegal room name! A room name must be between 1-40 characters long, must not have a trailing or leading space and must not have any of these characters: $()*+?[]^{|}".to_string()).send_self());
-            } else if server.has_room(&name) {
+            create_room(server, client_id, response, name, password, None);
+        }
+        CreateRankedRoom(name) => {
+            if !server.clients[client_id].is_registered() {
                 response.add(
-                    Warning("A room with the same name already exists.".to_string()).send_self(),
+                    Warning("Only registered players can play ranked games.".to_string())
+                        .send_self(),
                 );
+            } else if let Some(preset) = server.ranked_preset.clone() {
+                create_room(server, client_id, response, name, None, Some(preset));
             } else {
-                let flags_msg = ClientFlags(
-                    add_flags(&[Flags::RoomMaster, Flags::Ready]),
-                    vec![server.clients[client_id].nick.clone()],
-                );
-
-                let room_id = server.create_room(client_id, name, password);
-                let room = &server.rooms[room_id];
-                let client = &server.clients[client_id];
-
                 response.add(
-                    RoomAdd(room.info(Some(&client)))
-                        .send_all()
-                        .with_protocol(room.protocol_number),
-                );
-                response.add(RoomJoined(vec![client.nick.clone()]).send_self());
-                response.add(flags_msg.send_self());
-
-                response.add(
-                    ClientFlags(add_flags(&[Flags::InRoom]), vec![client.nick.clone()]).send_self(),
+                    Warning("Ranked games are not available on this server.".to_string())
+                        .send_self(),
                 );
-            };
+            }
+        }
+        Rating(nick) => {
+            let nick = nick.unwrap_or_else(|| server.clients[client_id].nick.clone());
+            response.request_io(super::IoTask::GetRating { nick });
+        }
+        Leaderboard => {
+            let size = server.config.ranked.leaderboard_size;
+            response.request_io(super::IoTask::GetLeaderboard { size });
         }
         Chat(msg) => {
             response.add(
@@ -77,6 +125,11 @@ pub fn handle(
                         Warning("Room version incompatible to your Hedgewars version!".to_string())
                             .send_self(),
                     );
+                } else if room.is_ranked() && !client.is_registered() {
+                    response.add(
+                        Warning("Only registered players can join ranked rooms.".to_string())
+                            .send_self(),
+                    );
                 } else if room.is_join_restricted() {
                     response.add(
                         Warning(
@@ -89,6 +142,8 @@ pub fn handle(
                 } else if let Some(room_id) = room_id {
                     super::common::enter_room(server, client_id, room_id, response);
                 }
+            } else if let Some((remote, _)) = server.federation.find_room(&name) {
+                response.add(Redirect(remote.port, Some(remote.host.clone())).send_self());
             } else {
                 response.add(Warning("No such room.".to_string()).send_self());
             }
@@ -100,8 +155,15 @@ pub fn handle(
             }) = server.find_client(&nick)
             {
                 let room = &server.rooms[*room_id];
-                response.add(Joining(room.name.clone()).send_self());
-                super::common::enter_room(server, client_id, *room_id, response);
+                if room.is_ranked() && !server.clients[client_id].is_registered() {
+                    response.add(
+                        Warning("Only registered players can join ranked rooms.".to_string())
+                            .send_self(),
+                    );
+                } else {
+                    response.add(Joining(room.name.clone()).send_self());
+                    super::common::enter_room(server, client_id, *room_id, response);
+                }
             }
         }
         SetServerVar(var) => {
@@ -25,26 +25,48 @@ pub enum LoginResult {
     Exit,
 }
 
+/// A session of a registered player can be replaced by a new one that passes
+/// the password check, so its nick isn't considered taken until then
+fn is_ghost_candidate(client: &HWClient) -> bool {
+    cfg!(feature = "official-server") && client.is_registered()
+}
+
+pub fn find_nick_owner<'a, I>(mut other_clients: I, nick: &str) -> Option<ClientId>
+where
+    I: Iterator<Item = (ClientId, &'a HWClient)>,
+{
+    other_clients
+        .find(|(_, c)| !c.is_checker() && c.nick == nick)
+        .map(|(id, _)| id)
+}
+
+pub fn reject_nick(client: &mut HWAnteClient, response: &mut super::Response) -> LoginResult {
+    if client.protocol_number.unwrap().get() < 38 {
+        response.add(Bye("User quit: Nickname is already in use".to_string()).send_self());
+        LoginResult::Exit
+    } else {
+        client.nick = None;
+        response.add(Notice("NickAlreadyInUse".to_string()).send_self());
+        LoginResult::Unchanged
+    }
+}
+
 fn completion_result<'a, I>(
-    mut other_clients: I,
+    other_clients: I,
     client: &mut HWAnteClient,
     response: &mut super::Response,
 ) -> LoginResult
 where
     I: Iterator<Item = (ClientId, &'a HWClient)>,
 {
-    let has_nick_clash =
-        other_clients.any(|(_, c)| !c.is_checker() && c.nick == *client.nick.as_ref().unwrap());
+    let has_nick_clash = find_nick_owner(
+        other_clients.filter(|(_, c)| !is_ghost_candidate(c)),
+        client.nick.as_ref().unwrap(),
+    )
+    .is_some();
 
     if has_nick_clash {
-        if client.protocol_number.unwrap().get() < 38 {
-            response.add(Bye("User quit: Nickname is already in use".to_string()).send_self());
-            LoginResult::Exit
-        } else {
-            client.nick = None;
-            response.add(Notice("NickAlreadyInUse".to_string()).send_self());
-            LoginResult::Unchanged
-        }
+        reject_nick(client, response)
     } else {
         #[cfg(feature = "official-server")]
         {
@@ -133,9 +155,13 @@ pub fn handle(
                 LoginResult::Unchanged
             } else {
                 client.protocol_number = NonZeroU16::new(protocol);
-                client.nick = Some(nick);
+                client.nick = Some(nick.clone());
                 client.is_checker = true;
-                LoginResult::Complete
+                response.request_io(super::IoTask::GetCheckerAccount {
+                    nick,
+                    password_hash: password,
+                });
+                LoginResult::Unchanged
             }
         }
         _ => {
@@ -144,3 +170,102 @@ pub fn handle(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "official-server")]
+    use crate::server::handlers::{handle_io_result, AccountInfo, IoResult, Sha1Digest};
+    use crate::server::handlers::{test::*, Response};
+
+    const NICK_IN_USE: &str = "NOTICE\nNickAlreadyInUse\n\n";
+
+    fn log_in(server: &mut HWServer, client_id: ClientId, nick: &str) -> Vec<String> {
+        server.anteroom.add_client(client_id, "salt".to_string());
+        let mut response = Response::new(client_id);
+        handle(
+            server,
+            client_id,
+            &mut response,
+            HWProtocolMessage::Proto(58),
+        );
+        handle(
+            server,
+            client_id,
+            &mut response,
+            HWProtocolMessage::Nick(nick.to_string()),
+        );
+        messages(server, &mut response)
+    }
+
+    #[cfg(feature = "official-server")]
+    fn account(is_registered: bool) -> IoResult {
+        IoResult::Account(Some(AccountInfo {
+            is_registered,
+            is_admin: false,
+            is_contributor: false,
+            server_hash: Sha1Digest::new([0; 20]),
+        }))
+    }
+
+    #[test]
+    fn nick_clash() {
+        let mut server = server();
+        add_client(&mut server, 0, "nick");
+
+        let messages = log_in(&mut server, 1, "nick");
+        assert!(messages.contains(&NICK_IN_USE.to_string()));
+        assert_eq!(server.anteroom.clients[1].nick, None);
+    }
+
+    #[cfg(feature = "official-server")]
+    #[test]
+    fn ghost_replaced() {
+        let mut server = server();
+        add_client(&mut server, 0, "nick");
+        server.clients[0].set_is_registered(true);
+
+        let messages = log_in(&mut server, 1, "nick");
+        assert!(messages.contains(&"ASKPASSWORD\nsalt\n\n".to_string()));
+        assert!(!messages.contains(&NICK_IN_USE.to_string()));
+
+        let mut response = Response::new(1);
+        handle_io_result(&mut server, 1, &mut response, account(true));
+        assert!(!server.clients.contains(0));
+        assert_eq!(server.clients[1].nick, "nick");
+        assert!(response.extract_removed_clients().eq(vec![0]));
+    }
+
+    #[cfg(feature = "official-server")]
+    #[test]
+    fn ghost_kept_for_unregistered_player() {
+        let mut server = server();
+        add_client(&mut server, 0, "nick");
+        server.clients[0].set_is_registered(true);
+        log_in(&mut server, 1, "nick");
+
+        let mut response = Response::new(1);
+        handle_io_result(&mut server, 1, &mut response, account(false));
+        assert!(messages(&server, &mut response).contains(&NICK_IN_USE.to_string()));
+        assert!(server.clients.contains(0));
+        assert_eq!(server.anteroom.clients[1].nick, None);
+    }
+
+    #[cfg(feature = "official-server")]
+    #[test]
+    fn ghost_kept_on_failed_authentication() {
+        let mut server = server();
+        add_client(&mut server, 0, "nick");
+        server.clients[0].set_is_registered(true);
+        log_in(&mut server, 1, "nick");
+
+        let mut response = Response::new(1);
+        handle_io_result(&mut server, 1, &mut response, IoResult::Account(None));
+        assert_eq!(
+            messages(&server, &mut response),
+            vec!["ERROR\nAuthentication failed.\n\n"]
+        );
+        assert!(response.extract_removed_clients().eq(vec![1]));
+        assert!(server.clients.contains(0));
+    }
+}
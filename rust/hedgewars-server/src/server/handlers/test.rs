@@ -0,0 +1,74 @@
+use super::Response;
+use crate::server::{
+    config::ServerConfig,
+    core::{HWAnteClient, HWServer},
+    coretypes::{ClientId, HedgehogInfo, RoomId, TeamInfo},
+};
+use std::num::NonZeroU16;
+
+pub fn server_with_config(config: ServerConfig) -> HWServer {
+    HWServer::new(16, 16, config)
+}
+
+pub fn server() -> HWServer {
+    server_with_config(ServerConfig::default())
+}
+
+pub fn add_client(server: &mut HWServer, client_id: ClientId, nick: &str) {
+    server.add_client(
+        client_id,
+        HWAnteClient {
+            nick: Some(nick.to_string()),
+            protocol_number: NonZeroU16::new(58),
+            server_salt: String::new(),
+            is_checker: false,
+        },
+    );
+}
+
+/// Creates a room with the first client as the master and the rest as players
+pub fn add_room(server: &mut HWServer, name: &str, client_ids: &[ClientId]) -> RoomId {
+    let room_id = server.create_room(client_ids[0], name.to_string(), None);
+    for client_id in &client_ids[1..] {
+        server.move_to_room(*client_id, room_id);
+    }
+    room_id
+}
+
+/// Adds a team of four hedgehogs in the first clan
+pub fn add_team(server: &mut HWServer, room_id: RoomId, owner_id: ClientId, name: &str) {
+    let hedgehog = || HedgehogInfo {
+        name: String::new(),
+        hat: String::new(),
+    };
+    let team = TeamInfo {
+        owner: server.clients[owner_id].nick.clone(),
+        name: name.to_string(),
+        color: 0,
+        grave: String::new(),
+        fort: String::new(),
+        voice_pack: String::new(),
+        flag: String::new(),
+        difficulty: 0,
+        hedgehogs_number: 4,
+        hedgehogs: [
+            hedgehog(),
+            hedgehog(),
+            hedgehog(),
+            hedgehog(),
+            hedgehog(),
+            hedgehog(),
+            hedgehog(),
+            hedgehog(),
+        ],
+    };
+    server.rooms[room_id].teams.push((owner_id, team));
+}
+
+/// The messages of the response in the raw protocol form
+pub fn messages(server: &HWServer, response: &mut Response) -> Vec<String> {
+    response
+        .extract_messages(server)
+        .map(|(_, msg)| msg.to_raw_protocol())
+        .collect()
+}
@@ -0,0 +1,223 @@
+use super::{
+    core::HWServer,
+    coretypes::{ClientId, RoomId},
+    handlers::Response,
+};
+use std::mem::replace;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HookResult {
+    Pass,
+    Veto,
+}
+
+/** Callbacks for custom server logic, registered with `NetworkLayerBuilder::with_hooks`.
+ *
+ * Every callback has a default implementation, so an implementor only needs
+ * to override the events it is interested in. Vetoing callbacks cancel the
+ * default action; the hook is expected to tell the client why.
+ */
+pub trait ServerHooks {
+    fn on_login(
+        &mut self,
+        _server: &mut HWServer,
+        _client_id: ClientId,
+        _response: &mut Response,
+    ) -> HookResult {
+        HookResult::Pass
+    }
+
+    fn on_chat(
+        &mut self,
+        _server: &mut HWServer,
+        _client_id: ClientId,
+        _room_id: Option<RoomId>,
+        _msg: &str,
+        _response: &mut Response,
+    ) -> HookResult {
+        HookResult::Pass
+    }
+
+    fn on_room_create(
+        &mut self,
+        _server: &mut HWServer,
+        _client_id: ClientId,
+        _name: &str,
+        _response: &mut Response,
+    ) -> HookResult {
+        HookResult::Pass
+    }
+
+    fn on_game_start(
+        &mut self,
+        _server: &mut HWServer,
+        _room_id: RoomId,
+        _response: &mut Response,
+    ) -> HookResult {
+        HookResult::Pass
+    }
+
+    fn on_game_end(&mut self, _server: &mut HWServer, _room_id: RoomId, _response: &mut Response) {
+    }
+
+    /// Returns `true` if the command was recognized by the hook
+    fn on_command(
+        &mut self,
+        _server: &mut HWServer,
+        _client_id: ClientId,
+        _name: &str,
+        _args: Option<&str>,
+        _response: &mut Response,
+    ) -> bool {
+        false
+    }
+}
+
+/// Hooks are taken out of the server while they run, so events raised from inside a hook are not reported again
+fn run_hooks<F>(server: &mut HWServer, mut f: F) -> HookResult
+where
+    F: FnMut(&mut dyn ServerHooks, &mut HWServer) -> HookResult,
+{
+    let mut hooks = replace(&mut server.hooks, Vec::new());
+    let result = hooks
+        .iter_mut()
+        .map(|h| f(h.as_mut(), server))
+        .find(|r| *r == HookResult::Veto)
+        .unwrap_or(HookResult::Pass);
+    hooks.extend(server.hooks.drain(..));
+    server.hooks = hooks;
+    result
+}
+
+pub fn on_login(server: &mut HWServer, client_id: ClientId, response: &mut Response) -> HookResult {
+    run_hooks(server, |h, s| h.on_login(s, client_id, response))
+}
+
+pub fn on_chat(
+    server: &mut HWServer,
+    client_id: ClientId,
+    msg: &str,
+    response: &mut Response,
+) -> HookResult {
+    let room_id = server.clients[client_id].room_id;
+    run_hooks(server, |h, s| h.on_chat(s, client_id, room_id, msg, response))
+}
+
+pub fn on_room_create(
+    server: &mut HWServer,
+    client_id: ClientId,
+    name: &str,
+    response: &mut Response,
+) -> HookResult {
+    run_hooks(server, |h, s| h.on_room_create(s, client_id, name, response))
+}
+
+pub fn on_game_start(server: &mut HWServer, room_id: RoomId, response: &mut Response) -> HookResult {
+    run_hooks(server, |h, s| h.on_game_start(s, room_id, response))
+}
+
+pub fn on_game_end(server: &mut HWServer, room_id: RoomId, response: &mut Response) {
+    run_hooks(server, |h, s| {
+        h.on_game_end(s, room_id, response);
+        HookResult::Pass
+    });
+}
+
+pub fn on_command(
+    server: &mut HWServer,
+    client_id: ClientId,
+    name: &str,
+    args: Option<&str>,
+    response: &mut Response,
+) -> bool {
+    let result = run_hooks(server, |h, s| {
+        if h.on_command(s, client_id, name, args, response) {
+            HookResult::Veto
+        } else {
+            HookResult::Pass
+        }
+    });
+    result == HookResult::Veto
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocol::messages::{HWProtocolMessage, HWServerMessage::Warning},
+        server::handlers::{handle, test::*},
+    };
+
+    struct NoSpam;
+
+    impl ServerHooks for NoSpam {
+        fn on_chat(
+            &mut self,
+            _server: &mut HWServer,
+            _client_id: ClientId,
+            _room_id: Option<RoomId>,
+            msg: &str,
+            response: &mut Response,
+        ) -> HookResult {
+            if msg.contains("spam") {
+                response.add(Warning("No spam, please.".to_string()).send_self());
+                HookResult::Veto
+            } else {
+                HookResult::Pass
+            }
+        }
+
+        fn on_command(
+            &mut self,
+            _server: &mut HWServer,
+            _client_id: ClientId,
+            name: &str,
+            _args: Option<&str>,
+            response: &mut Response,
+        ) -> bool {
+            let known = name.eq_ignore_ascii_case("rules");
+            if known {
+                response.add(Warning("Be nice.".to_string()).send_self());
+            }
+            known
+        }
+    }
+
+    fn run(server: &mut HWServer, message: HWProtocolMessage) -> Vec<String> {
+        let mut response = Response::new(0);
+        handle(server, 0, &mut response, message);
+        messages(server, &mut response)
+    }
+
+    #[test]
+    fn hooks() {
+        let mut server = server();
+        add_client(&mut server, 0, "alice");
+        add_client(&mut server, 1, "bob");
+        server.register_hooks(Box::new(NoSpam));
+
+        assert_eq!(
+            run(&mut server, HWProtocolMessage::Chat("hello".to_string())),
+            vec!["CHAT\nalice\nhello\n\n"]
+        );
+        assert_eq!(
+            run(&mut server, HWProtocolMessage::Chat("buy spam".to_string())),
+            vec!["WARNING\nNo spam, please.\n\n"]
+        );
+
+        assert_eq!(
+            run(
+                &mut server,
+                HWProtocolMessage::CustomCommand("RULES".to_string(), None)
+            ),
+            vec!["WARNING\nBe nice.\n\n"]
+        );
+        assert_eq!(
+            run(
+                &mut server,
+                HWProtocolMessage::CustomCommand("HELP".to_string(), None)
+            ),
+            vec!["WARNING\nUnknown command: /help\n\n"]
+        );
+    }
+}
@@ -1,13 +1,18 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{Error, ErrorKind, Read, Result, Write},
-    sync::mpsc,
+    collections::HashSet,
+    fs::File,
+    io::{Error, ErrorKind, Read, Result},
+    sync::{mpsc, Arc, Mutex},
     thread,
 };
 
 use crate::server::{
+    config::IoConfig,
+    coretypes::Replay,
     database::Database,
     handlers::{IoResult, IoTask},
+    rating::{self, RatingChange},
+    room_library::{PresetScope, RoomLibrary},
 };
 use log::*;
 use mio::{Evented, Poll, PollOpt};
@@ -15,116 +20,346 @@ use mio_extras::channel;
 
 pub type RequestId = u32;
 
-pub struct IOThread {
-    core_tx: mpsc::Sender<(RequestId, IoTask)>,
-    core_rx: channel::Receiver<(RequestId, IoResult)>,
+type TaskReceiver = Arc<Mutex<mpsc::Receiver<(RequestId, IoTask)>>>;
+type CancelledRequests = Arc<Mutex<HashSet<RequestId>>>;
+
+struct IoWorker {
+    db: Database,
+    library: RoomLibrary,
+    tasks: TaskReceiver,
+    results: channel::Sender<(RequestId, IoResult)>,
+    cancelled: CancelledRequests,
 }
 
-impl IOThread {
-    pub fn new() -> Self {
-        let (core_tx, io_rx) = mpsc::channel();
-        let (io_tx, core_rx) = channel::channel();
+impl IoWorker {
+    fn run(mut self) {
+        loop {
+            let task = self.tasks.lock().unwrap().recv();
+            let (request_id, task) = match task {
+                Ok(task) => task,
+                Err(mpsc::RecvError) => break,
+            };
 
-        let mut db = Database::new();
-        db.connect("localhost");
-
-        thread::spawn(move || {
-            while let Ok((request_id, task)) = io_rx.try_recv() {
-                let response = match task {
-                    IoTask::GetAccount {
-                        nick,
-                        protocol,
-                        password_hash,
-                        client_salt,
-                        server_salt,
-                    } => {
-                        match db.get_account(
-                            &nick,
-                            protocol,
-                            &password_hash,
-                            &client_salt,
-                            &server_salt,
-                        ) {
-                            Ok(account) => IoResult::Account(account),
-                            Err(e) => {
-                                warn!("Unable to get account data: {}", e);
-                                IoResult::Account(None)
-                            }
-                        }
-                    }
+            if self.cancelled.lock().unwrap().remove(&request_id) {
+                continue;
+            }
 
-                    IoTask::GetReplay { id } => {
-                        let result = match db.get_replay_name(id) {
-                            Ok(Some(filename)) => {
-                                let filename = format!(
-                                    "checked/{}",
-                                    if filename.starts_with("replays/") {
-                                        &filename[8..]
-                                    } else {
-                                        &filename
-                                    }
-                                );
-                                match load_file(&filename) {
-                                    Ok(contents) => Some(unimplemented!()),
-                                    Err(e) => {
-                                        warn!(
-                                            "Error while writing the room config file \"{}\": {}",
-                                            filename, e
-                                        );
-                                        None
-                                    }
-                                }
-                            }
-                            Ok(None) => None,
-                            Err(e) => {
-                                warn!("Unable to get replay name: {}", e);
-                                None
-                            }
-                        };
-                        IoResult::Replay(result)
-                    }
+            let result = self.handle_task(task);
+            if self.results.send((request_id, result)).is_err() {
+                break;
+            }
+        }
+    }
 
-                    IoTask::SaveRoom {
-                        room_id,
-                        filename,
-                        contents,
-                    } => {
-                        let result = match save_file(&filename, &contents) {
-                            Ok(()) => true,
-                            Err(e) => {
-                                warn!(
-                                    "Error while writing the room config file \"{}\": {}",
-                                    filename, e
-                                );
-                                false
-                            }
-                        };
-                        IoResult::SaveRoom(room_id, result)
+    fn update_ratings(
+        &mut self,
+        winners: &[String],
+        losers: &[String],
+        initial_rating: i32,
+        k_factor: u32,
+    ) -> std::result::Result<Vec<RatingChange>, mysql::error::Error> {
+        let players: Vec<_> = winners.iter().chain(losers).cloned().collect();
+        let ratings = self
+            .db
+            .update_ratings(&players, initial_rating, |ratings| {
+                let (winner_ratings, loser_ratings) = ratings.split_at(winners.len());
+                let (winner_deltas, loser_deltas) =
+                    rating::elo_changes(winner_ratings, loser_ratings, k_factor);
+                let deltas = winner_deltas.iter().chain(&loser_deltas);
+                ratings.iter().zip(deltas).map(|(r, d)| r + d).collect()
+            })?;
+
+        Ok(players
+            .into_iter()
+            .zip(ratings)
+            .map(|(nick, (old_rating, new_rating))| RatingChange {
+                nick,
+                old_rating,
+                new_rating,
+            })
+            .collect())
+    }
+
+    fn handle_task(&mut self, task: IoTask) -> IoResult {
+        match task {
+            IoTask::GetAccount {
+                nick,
+                protocol,
+                password_hash,
+                client_salt,
+                server_salt,
+            } => {
+                match self.db.get_account(
+                    &nick,
+                    protocol,
+                    &password_hash,
+                    &client_salt,
+                    &server_salt,
+                ) {
+                    Ok(account) => IoResult::Account(account),
+                    Err(e) => {
+                        warn!("Unable to get account data: {}", e);
+                        IoResult::Account(None)
                     }
+                }
+            }
+
+            IoTask::GetCheckerAccount {
+                nick,
+                password_hash,
+            } => {
+                let result = self
+                    .db
+                    .is_checker_account(&nick, &password_hash)
+                    .unwrap_or_else(|e| {
+                        warn!("Unable to get account data: {}", e);
+                        false
+                    });
+                IoResult::CheckerAccount(result)
+            }
 
-                    IoTask::LoadRoom { room_id, filename } => {
-                        let result = match load_file(&filename) {
-                            Ok(contents) => Some(contents),
+            IoTask::GetReplay { id } => {
+                let result = match self.db.get_replay_name(id) {
+                    Ok(Some(filename)) => {
+                        let filename = format!(
+                            "checked/{}",
+                            if filename.starts_with("replays/") {
+                                &filename[8..]
+                            } else {
+                                &filename
+                            }
+                        );
+                        match load_replay(&filename) {
+                            Ok(replay) => Some(replay),
                             Err(e) => {
                                 warn!(
-                                    "Error while writing the room config file \"{}\": {}",
+                                    "Error while reading the replay file \"{}\": {}",
                                     filename, e
                                 );
                                 None
                             }
-                        };
-                        IoResult::LoadRoom(room_id, result)
+                        }
+                    }
+                    Ok(None) => None,
+                    Err(e) => {
+                        warn!("Unable to get replay name: {}", e);
+                        None
+                    }
+                };
+                IoResult::Replay(result)
+            }
+
+            IoTask::SaveRoom {
+                room_id,
+                filename,
+                contents,
+            } => {
+                let result = match self.library.save_room(&filename, &contents) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!(
+                            "Error while writing the room config file \"{}\": {}",
+                            filename, e
+                        );
+                        false
+                    }
+                };
+                IoResult::SaveRoom(room_id, result)
+            }
+
+            IoTask::LoadRoom { room_id, filename } => {
+                let result = match self.library.load_room(&filename) {
+                    Ok(contents) => Some(contents),
+                    Err(e) => {
+                        warn!(
+                            "Error while writing the room config file \"{}\": {}",
+                            filename, e
+                        );
+                        None
+                    }
+                };
+                IoResult::LoadRoom(room_id, result)
+            }
+
+            IoTask::ListPresets { nick } => {
+                let list = |scope| {
+                    self.library.list_presets(&scope).unwrap_or_else(|e| {
+                        warn!("Error while listing the room config presets: {}", e);
+                        vec![]
+                    })
+                };
+                IoResult::PresetList {
+                    public: list(PresetScope::Public),
+                    private: nick.map_or(vec![], |nick| list(PresetScope::Private(nick))),
+                }
+            }
+
+            IoTask::LoadPreset {
+                room_id,
+                nick,
+                name,
+            } => {
+                let private = nick.and_then(|nick| {
+                    self.library
+                        .load_preset(&PresetScope::Private(nick), &name)
+                        .ok()
+                });
+                let result =
+                    private.or_else(|| self.library.load_preset(&PresetScope::Public, &name).ok());
+                IoResult::LoadPreset(room_id, name, result)
+            }
+
+            IoTask::SavePreset {
+                scope,
+                name,
+                contents,
+            } => {
+                let result = match self.library.save_preset(&scope, &name, &contents) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!(
+                            "Error while writing the room config preset \"{}\": {}",
+                            name, e
+                        );
+                        false
                     }
                 };
-                io_tx.send((request_id, response));
+                IoResult::SavePreset(name, result)
             }
-        });
 
-        Self { core_rx, core_tx }
+            IoTask::DeletePreset { scope, name } => {
+                let result = self.library.delete_preset(&scope, &name).is_ok();
+                IoResult::DeletePreset(name, result)
+            }
+
+            IoTask::UpdateRatings {
+                winners,
+                losers,
+                initial_rating,
+                k_factor,
+            } => {
+                let result = self
+                    .update_ratings(&winners, &losers, initial_rating, k_factor)
+                    .map_err(|e| warn!("Unable to update the ratings: {}", e))
+                    .ok();
+                let players = winners.into_iter().chain(losers).collect();
+                IoResult::UpdateRatings(players, result)
+            }
+
+            IoTask::GetRating { nick } => {
+                let result = match self.db.get_rating(&nick) {
+                    Ok(rating) => rating,
+                    Err(e) => {
+                        warn!("Unable to get the rating of {}: {}", nick, e);
+                        None
+                    }
+                };
+                IoResult::Rating(nick, result)
+            }
+
+            IoTask::GetLeaderboard { size } => {
+                let result = self
+                    .db
+                    .get_leaderboard(size)
+                    .map_err(|e| warn!("Unable to get the leaderboard: {}", e))
+                    .ok();
+                IoResult::Leaderboard(result)
+            }
+
+            IoTask::SaveDesyncReport { filename, report } => {
+                let result = match report.write_to(&filename) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("Unable to write the desync report to {}: {}", filename, e);
+                        false
+                    }
+                };
+                IoResult::SaveDesyncReport(result)
+            }
+        }
+    }
+}
+
+/// A fixed number of worker threads running the tasks from a bounded queue
+pub struct IoPool {
+    core_tx: Option<mpsc::SyncSender<(RequestId, IoTask)>>,
+    core_rx: channel::Receiver<(RequestId, IoResult)>,
+    result_tx: channel::Sender<(RequestId, IoResult)>,
+    cancelled: CancelledRequests,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl IoPool {
+    pub fn new(library: RoomLibrary, config: &IoConfig) -> Self {
+        let (core_tx, io_rx) = mpsc::sync_channel(config.queue_size);
+        let (io_tx, core_rx) = channel::channel();
+        let tasks = Arc::new(Mutex::new(io_rx));
+        let cancelled = Arc::new(Mutex::new(HashSet::new()));
+
+        if config.database_url.is_none() {
+            warn!("No database is configured");
+        }
+
+        let workers = (0..config.workers.max(1))
+            .map(|index| {
+                let mut db = Database::new();
+                if let Some(ref url) = config.database_url {
+                    if let Err(e) = db.connect(url) {
+                        warn!("Unable to connect to the database: {}", e);
+                    }
+                }
+
+                let worker = IoWorker {
+                    db,
+                    library: library.clone(),
+                    tasks: tasks.clone(),
+                    results: io_tx.clone(),
+                    cancelled: cancelled.clone(),
+                };
+                thread::Builder::new()
+                    .name(format!("io-worker-{}", index))
+                    .spawn(move || worker.run())
+                    .expect("Unable to start an IO worker")
+            })
+            .collect();
+
+        Self {
+            core_tx: Some(core_tx),
+            core_rx,
+            result_tx: io_tx,
+            cancelled,
+            workers,
+        }
+    }
+
+    /// Returns the task back if the queue is full or the pool is shut down
+    pub fn send(&self, request_id: RequestId, task: IoTask) -> std::result::Result<(), IoTask> {
+        match self.core_tx {
+            Some(ref tx) => tx.try_send((request_id, task)).map_err(|e| match e {
+                mpsc::TrySendError::Full((_, task)) => task,
+                mpsc::TrySendError::Disconnected((_, task)) => task,
+            }),
+            None => Err(task),
+        }
+    }
+
+    /// Delivers the result as if it was produced by a worker
+    pub fn send_result(&self, request_id: RequestId, result: IoResult) {
+        if self.result_tx.send((request_id, result)).is_err() {
+            warn!(
+                "Unable to deliver the result of the IO request {}",
+                request_id
+            );
+        }
+    }
+
+    /// The workers skip the cancelled tasks that are not started yet
+    pub fn cancel(&self, request_id: RequestId) {
+        self.cancelled.lock().unwrap().insert(request_id);
     }
 
-    pub fn send(&self, request_id: RequestId, task: IoTask) {
-        self.core_tx.send((request_id, task)).unwrap();
+    pub fn forget_cancelled(&self, request_id: RequestId) {
+        self.cancelled.lock().unwrap().remove(&request_id);
     }
 
     pub fn try_recv(&self) -> Option<(RequestId, IoResult)> {
@@ -139,16 +374,47 @@ impl IOThread {
         self.core_rx
             .register(poll, token, mio::Ready::readable(), PollOpt::edge())
     }
+
+    /// Closes the queue and waits for the workers to finish the queued tasks
+    pub fn shutdown(&mut self) {
+        self.core_tx = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                warn!("An IO worker panicked");
+            }
+        }
+    }
+}
+
+impl Drop for IoPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Replays are stored as the Haskell `show` of a tuple of the teams at the start of the game,
+/// the map and game parameters and the engine messages, which is not supported yet
+fn parse_replay(_contents: &str) -> Result<Replay> {
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        "the replay format is not supported",
+    ))
 }
 
-fn save_file(filename: &str, contents: &str) -> Result<()> {
-    let mut writer = OpenOptions::new().create(true).write(true).open(filename)?;
-    writer.write_all(contents.as_bytes())
+fn load_replay(filename: &str) -> Result<Replay> {
+    let mut contents = String::new();
+    File::open(filename)?.read_to_string(&mut contents)?;
+    parse_replay(&contents)
 }
 
-fn load_file(filename: &str) -> Result<String> {
-    let mut reader = File::open(filename)?;
-    let mut result = String::new();
-    reader.read_to_string(&mut result)?;
-    Ok(result)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replay() {
+        let contents = r#"([TeamInfo {teamowner = "owner", teamname = "team"}],fromList [("SEED","{abc}")],fromList [],["AQ=="])"#;
+        let error = parse_replay(contents).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
 }
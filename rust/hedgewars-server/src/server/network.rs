@@ -17,14 +17,24 @@ use mio_extras::timer;
 use netbuf;
 use slab::Slab;
 
-use super::{core::HWServer, coretypes::ClientId, handlers};
+use super::{
+    assets::AssetIndex, config::ServerConfig, core::HWServer, coretypes::ClientId, handlers,
+    hooks::ServerHooks, websocket::WebSocket,
+};
 use crate::{
     protocol::{messages::*, ProtocolDecoder},
     utils,
 };
 
 #[cfg(feature = "official-server")]
-use super::io::{IOThread, RequestId};
+use super::{
+    config::IoConfig,
+    io::{IoPool, RequestId},
+    room_library::RoomLibrary,
+};
+
+#[cfg(feature = "federation")]
+use super::peers::PeerLinks;
 
 use crate::protocol::messages::HWServerMessage::Redirect;
 use crate::server::handlers::{IoResult, IoTask};
@@ -36,11 +46,12 @@ use openssl::{
         SslMethod, SslOptions, SslStream, SslStreamBuilder, SslVerifyMode,
     },
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const MAX_BYTES_PER_READ: usize = 2048;
 const SEND_PING_TIMEOUT: Duration = Duration::from_secs(30);
 const DROP_CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+const SERVER_TICK_INTERVAL: Duration = Duration::from_secs(10);
 const PING_PROBES_COUNT: u8 = 2;
 
 #[derive(Hash, Eq, PartialEq, Copy, Clone)]
@@ -49,7 +60,6 @@ pub enum NetworkClientState {
     NeedsWrite,
     NeedsRead,
     Closed,
-    #[cfg(feature = "tls-connections")]
     Connected,
 }
 
@@ -57,22 +67,29 @@ type NetworkResult<T> = io::Result<(T, NetworkClientState)>;
 
 pub enum ClientSocket {
     Plain(TcpStream),
+    WebSocket(WebSocket<TcpStream>),
+    /// The flag is set when the WebSocket handshake follows the TLS one
     #[cfg(feature = "tls-connections")]
-    SslHandshake(Option<MidHandshakeSslStream<TcpStream>>),
+    SslHandshake(Option<MidHandshakeSslStream<TcpStream>>, bool),
     #[cfg(feature = "tls-connections")]
     SslStream(SslStream<TcpStream>),
+    #[cfg(feature = "tls-connections")]
+    SecureWebSocket(WebSocket<SslStream<TcpStream>>),
 }
 
 impl ClientSocket {
     fn inner(&self) -> &TcpStream {
         match self {
             ClientSocket::Plain(stream) => stream,
+            ClientSocket::WebSocket(socket) => socket.get_ref(),
             #[cfg(feature = "tls-connections")]
-            ClientSocket::SslHandshake(Some(builder)) => builder.get_ref(),
+            ClientSocket::SslHandshake(Some(builder), _) => builder.get_ref(),
             #[cfg(feature = "tls-connections")]
-            ClientSocket::SslHandshake(None) => unreachable!(),
+            ClientSocket::SslHandshake(None, _) => unreachable!(),
             #[cfg(feature = "tls-connections")]
             ClientSocket::SslStream(ssl_stream) => ssl_stream.get_ref(),
+            #[cfg(feature = "tls-connections")]
+            ClientSocket::SecureWebSocket(socket) => socket.get_ref().get_ref(),
         }
     }
 }
@@ -107,22 +124,28 @@ impl NetworkClient {
     fn handshake_impl(
         &mut self,
         handshake: MidHandshakeSslStream<TcpStream>,
+        is_websocket: bool,
     ) -> io::Result<NetworkClientState> {
         match handshake.handshake() {
             Ok(stream) => {
-                self.socket = ClientSocket::SslStream(stream);
                 debug!(
                     "TLS handshake with {} ({}) completed",
                     self.id, self.peer_addr
                 );
-                Ok(NetworkClientState::Connected)
+                if is_websocket {
+                    self.socket = ClientSocket::SecureWebSocket(WebSocket::new(stream));
+                    Ok(NetworkClientState::NeedsRead)
+                } else {
+                    self.socket = ClientSocket::SslStream(stream);
+                    Ok(NetworkClientState::Connected)
+                }
             }
             Err(HandshakeError::WouldBlock(new_handshake)) => {
-                self.socket = ClientSocket::SslHandshake(Some(new_handshake));
+                self.socket = ClientSocket::SslHandshake(Some(new_handshake), is_websocket);
                 Ok(NetworkClientState::Idle)
             }
             Err(HandshakeError::Failure(new_handshake)) => {
-                self.socket = ClientSocket::SslHandshake(Some(new_handshake));
+                self.socket = ClientSocket::SslHandshake(Some(new_handshake), is_websocket);
                 debug!("TLS handshake with {} ({}) failed", self.id, self.peer_addr);
                 Err(Error::new(ErrorKind::Other, "Connection failure"))
             }
@@ -130,6 +153,19 @@ impl NetworkClient {
         }
     }
 
+    fn websocket_handshake_impl<S: Read + Write>(
+        socket: &mut WebSocket<S>,
+        id: ClientId,
+        addr: &SocketAddr,
+    ) -> io::Result<NetworkClientState> {
+        if socket.handshake()? {
+            debug!("WebSocket handshake with {} ({}) completed", id, addr);
+            Ok(NetworkClientState::Connected)
+        } else {
+            Ok(NetworkClientState::Idle)
+        }
+    }
+
     fn read_impl<R: Read>(
         decoder: &mut ProtocolDecoder,
         source: &mut R,
@@ -173,15 +209,33 @@ impl NetworkClient {
             ClientSocket::Plain(ref mut stream) => {
                 NetworkClient::read_impl(&mut self.decoder, stream, self.id, &self.peer_addr)
             }
+            ClientSocket::WebSocket(ref mut socket) if !socket.is_handshake_complete() => {
+                let state =
+                    NetworkClient::websocket_handshake_impl(socket, self.id, &self.peer_addr)?;
+                Ok((Vec::new(), state))
+            }
+            ClientSocket::WebSocket(ref mut socket) => {
+                NetworkClient::read_impl(&mut self.decoder, socket, self.id, &self.peer_addr)
+            }
             #[cfg(feature = "tls-connections")]
-            ClientSocket::SslHandshake(ref mut handshake_opt) => {
+            ClientSocket::SslHandshake(ref mut handshake_opt, is_websocket) => {
                 let handshake = std::mem::replace(handshake_opt, None).unwrap();
-                Ok((Vec::new(), self.handshake_impl(handshake)?))
+                Ok((Vec::new(), self.handshake_impl(handshake, is_websocket)?))
             }
             #[cfg(feature = "tls-connections")]
             ClientSocket::SslStream(ref mut stream) => {
                 NetworkClient::read_impl(&mut self.decoder, stream, self.id, &self.peer_addr)
             }
+            #[cfg(feature = "tls-connections")]
+            ClientSocket::SecureWebSocket(ref mut socket) if !socket.is_handshake_complete() => {
+                let state =
+                    NetworkClient::websocket_handshake_impl(socket, self.id, &self.peer_addr)?;
+                Ok((Vec::new(), state))
+            }
+            #[cfg(feature = "tls-connections")]
+            ClientSocket::SecureWebSocket(ref mut socket) => {
+                NetworkClient::read_impl(&mut self.decoder, socket, self.id, &self.peer_addr)
+            }
         }
     }
 
@@ -209,15 +263,30 @@ impl NetworkClient {
             ClientSocket::Plain(ref mut stream) => {
                 NetworkClient::write_impl(&mut self.buf_out, stream)
             }
+            // the messages are held back until the connection is upgraded
+            ClientSocket::WebSocket(ref socket) if !socket.is_handshake_complete() => {
+                Ok(((), NetworkClientState::Idle))
+            }
+            ClientSocket::WebSocket(ref mut socket) => {
+                NetworkClient::write_impl(&mut self.buf_out, socket)
+            }
             #[cfg(feature = "tls-connections")]
-            ClientSocket::SslHandshake(ref mut handshake_opt) => {
+            ClientSocket::SslHandshake(ref mut handshake_opt, is_websocket) => {
                 let handshake = std::mem::replace(handshake_opt, None).unwrap();
-                Ok(((), self.handshake_impl(handshake)?))
+                Ok(((), self.handshake_impl(handshake, is_websocket)?))
             }
             #[cfg(feature = "tls-connections")]
             ClientSocket::SslStream(ref mut stream) => {
                 NetworkClient::write_impl(&mut self.buf_out, stream)
             }
+            #[cfg(feature = "tls-connections")]
+            ClientSocket::SecureWebSocket(ref socket) if !socket.is_handshake_complete() => {
+                Ok(((), NetworkClientState::Idle))
+            }
+            #[cfg(feature = "tls-connections")]
+            ClientSocket::SecureWebSocket(ref mut socket) => {
+                NetworkClient::write_impl(&mut self.buf_out, socket)
+            }
         };
 
         self.socket.inner().flush()?;
@@ -240,56 +309,117 @@ impl NetworkClient {
 #[cfg(feature = "tls-connections")]
 struct ServerSsl {
     listener: TcpListener,
+    websocket_listener: Option<TcpListener>,
     context: SslContext,
 }
 
+#[cfg(feature = "official-server")]
+struct IoRequest {
+    id: RequestId,
+    client_id: ClientId,
+    deadline: Instant,
+    /// Taken when the request is failed before the worker responds
+    failure: Option<IoResult>,
+}
+
 #[cfg(feature = "official-server")]
 pub struct IoLayer {
     next_request_id: RequestId,
-    request_queue: Vec<(RequestId, ClientId)>,
-    io_thread: IOThread,
+    request_queue: Vec<IoRequest>,
+    io_pool: IoPool,
+    task_timeout: Duration,
+    deadline_timeout: Option<timer::Timeout>,
 }
 
 #[cfg(feature = "official-server")]
 impl IoLayer {
-    fn new() -> Self {
+    fn new(library: RoomLibrary, config: &IoConfig) -> Self {
         Self {
             next_request_id: 0,
             request_queue: vec![],
-            io_thread: IOThread::new(),
+            io_pool: IoPool::new(library, config),
+            task_timeout: config.task_timeout(),
+            deadline_timeout: None,
         }
     }
 
     fn send(&mut self, client_id: ClientId, task: IoTask) {
         let request_id = self.next_request_id;
-        self.next_request_id += 1;
-        self.request_queue.push((request_id, client_id));
-        self.io_thread.send(request_id, task);
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        let mut request = IoRequest {
+            id: request_id,
+            client_id,
+            deadline: Instant::now() + self.task_timeout,
+            failure: Some(task.failure_result()),
+        };
+
+        if self.io_pool.send(request_id, task).is_err() {
+            warn!(
+                "IO queue is full, the request of client {} is rejected",
+                client_id
+            );
+            if let Some(failure) = request.failure.take() {
+                self.io_pool.send_result(request_id, failure);
+            }
+        }
+        self.request_queue.push(request);
     }
 
     fn try_recv(&mut self) -> Option<(ClientId, IoResult)> {
-        let (request_id, result) = self.io_thread.try_recv()?;
-        if let Some(index) = self
+        while let Some((request_id, result)) = self.io_pool.try_recv() {
+            if let Some(index) = self
+                .request_queue
+                .iter()
+                .position(|request| request.id == request_id)
+            {
+                let request = self.request_queue.swap_remove(index);
+                return Some((request.client_id, result));
+            }
+            self.io_pool.forget_cancelled(request_id);
+        }
+        None
+    }
+
+    /// Fails the timed out requests and cancels their tasks if the workers have not started them yet
+    fn expire(&mut self, now: Instant) {
+        for request in &mut self.request_queue {
+            if request.deadline <= now {
+                if let Some(failure) = request.failure.take() {
+                    warn!("IO request {} timed out", request.id);
+                    self.io_pool.cancel(request.id);
+                    self.io_pool.send_result(request.id, failure);
+                }
+            }
+        }
+    }
+
+    /// Sets the timer to the nearest deadline of the requests that are not failed yet
+    fn set_deadline_timeout(&mut self, timer: &mut timer::Timer<TimerData>) {
+        if self.deadline_timeout.is_some() {
+            return;
+        }
+        let deadline = self
             .request_queue
             .iter()
-            .position(|(id, _)| *id == request_id)
-        {
-            let (_, client_id) = self.request_queue.swap_remove(index);
-            Some((client_id, result))
-        } else {
-            None
+            .filter(|request| request.failure.is_some())
+            .map(|request| request.deadline)
+            .min();
+        if let Some(deadline) = deadline {
+            let delay = deadline.saturating_duration_since(Instant::now());
+            self.deadline_timeout = Some(timer.set_timeout(delay, TimerData::IoDeadline));
         }
     }
 
     fn cancel(&mut self, client_id: ClientId) {
-        let mut index = 0;
-        while index < self.request_queue.len() {
-            if self.request_queue[index].1 == client_id {
-                self.request_queue.swap_remove(index);
+        let io_pool = &self.io_pool;
+        self.request_queue.retain(|request| {
+            if request.client_id == client_id {
+                io_pool.cancel(request.id);
+                false
             } else {
-                index += 1;
+                true
             }
-        }
+        });
     }
 }
 
@@ -298,10 +428,16 @@ enum TimeoutEvent {
     DropClient,
 }
 
-struct TimerData(TimeoutEvent, ClientId);
+enum TimerData {
+    Client(TimeoutEvent, ClientId),
+    ServerTick,
+    #[cfg(feature = "official-server")]
+    IoDeadline,
+}
 
 pub struct NetworkLayer {
     listener: TcpListener,
+    websocket_listener: Option<TcpListener>,
     server: HWServer,
     clients: Slab<NetworkClient>,
     pending: HashSet<(ClientId, NetworkClientState)>,
@@ -310,6 +446,8 @@ pub struct NetworkLayer {
     ssl: ServerSsl,
     #[cfg(feature = "official-server")]
     io: IoLayer,
+    #[cfg(feature = "federation")]
+    peers: Option<PeerLinks>,
     timer: timer::Timer<TimerData>,
 }
 
@@ -324,14 +462,14 @@ fn create_ping_timeout(
 ) -> timer::Timeout {
     timer.set_timeout(
         SEND_PING_TIMEOUT,
-        TimerData(TimeoutEvent::SendPing { probes_count }, client_id),
+        TimerData::Client(TimeoutEvent::SendPing { probes_count }, client_id),
     )
 }
 
 fn create_drop_timeout(timer: &mut timer::Timer<TimerData>, client_id: ClientId) -> timer::Timeout {
     timer.set_timeout(
         DROP_CLIENT_TIMEOUT,
-        TimerData(TimeoutEvent::DropClient, client_id),
+        TimerData::Client(TimeoutEvent::DropClient, client_id),
     )
 }
 
@@ -340,10 +478,26 @@ impl NetworkLayer {
         register_read(poll, &self.listener, utils::SERVER_TOKEN)?;
         #[cfg(feature = "tls-connections")]
         register_read(poll, &self.ssl.listener, utils::SECURE_SERVER_TOKEN)?;
+        if let Some(ref listener) = self.websocket_listener {
+            register_read(poll, listener, utils::WEBSOCKET_SERVER_TOKEN)?;
+        }
+        #[cfg(feature = "tls-connections")]
+        {
+            if let Some(ref listener) = self.ssl.websocket_listener {
+                register_read(poll, listener, utils::SECURE_WEBSOCKET_SERVER_TOKEN)?;
+            }
+        }
         register_read(poll, &self.timer, utils::TIMER_TOKEN)?;
 
         #[cfg(feature = "official-server")]
-        self.io.io_thread.register_rx(poll, utils::IO_TOKEN)?;
+        self.io.io_pool.register_rx(poll, utils::IO_TOKEN)?;
+
+        #[cfg(feature = "federation")]
+        {
+            if let Some(ref peers) = self.peers {
+                peers.register_rx(poll, utils::FEDERATION_TOKEN)?;
+            }
+        }
 
         Ok(())
     }
@@ -397,6 +551,7 @@ impl NetworkLayer {
         }
 
         debug!("{} pending server messages", response.len());
+        handlers::record_chat_history(&mut self.server, &response);
         let output = response.extract_messages(&mut self.server);
         for (clients, message) in output {
             debug!("Message {:?} to {:?}", message, clients);
@@ -416,17 +571,26 @@ impl NetworkLayer {
 
         #[cfg(feature = "official-server")]
         {
-            let client_id = response.client_id();
-            for task in response.extract_io_tasks() {
+            for (client_id, task) in response.extract_io_tasks() {
                 self.io.send(client_id, task);
             }
+            self.io.set_deadline_timeout(&mut self.timer);
+        }
+
+        #[cfg(feature = "federation")]
+        {
+            for message in response.extract_peer_messages() {
+                if let Some(ref peers) = self.peers {
+                    peers.broadcast(&message);
+                }
+            }
         }
     }
 
     pub fn handle_timeout(&mut self, poll: &Poll) -> io::Result<()> {
-        while let Some(TimerData(event, client_id)) = self.timer.poll() {
-            match event {
-                TimeoutEvent::SendPing { probes_count } => {
+        while let Some(data) = self.timer.poll() {
+            match data {
+                TimerData::Client(TimeoutEvent::SendPing { probes_count }, client_id) => {
                     if let Some(ref mut client) = self.clients.get_mut(client_id) {
                         client.send_string(&HWServerMessage::Ping.to_raw_protocol());
                         client.write()?;
@@ -438,7 +602,7 @@ impl NetworkLayer {
                         client.replace_timeout(timeout);
                     }
                 }
-                TimeoutEvent::DropClient => {
+                TimerData::Client(TimeoutEvent::DropClient, client_id) => {
                     self.operation_failed(
                         poll,
                         client_id,
@@ -446,16 +610,45 @@ impl NetworkLayer {
                         "No ping response",
                     )?;
                 }
+                TimerData::ServerTick => {
+                    for response in handlers::handle_server_tick(&mut self.server) {
+                        self.handle_response(response, poll);
+                    }
+                    self.timer
+                        .set_timeout(SERVER_TICK_INTERVAL, TimerData::ServerTick);
+                }
+                #[cfg(feature = "official-server")]
+                TimerData::IoDeadline => {
+                    self.io.deadline_timeout = None;
+                    self.io.expire(Instant::now());
+                    self.io.set_deadline_timeout(&mut self.timer);
+                }
             }
         }
         Ok(())
     }
 
     #[cfg(feature = "official-server")]
-    pub fn handle_io_result(&mut self) -> io::Result<()> {
-        if let Some((client_id, result)) = self.io.try_recv() {
+    pub fn handle_io_result(&mut self, poll: &Poll) -> io::Result<()> {
+        while let Some((client_id, result)) = self.io.try_recv() {
             let mut response = handlers::Response::new(client_id);
             handlers::handle_io_result(&mut self.server, client_id, &mut response, result);
+            self.handle_response(response, poll);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "federation")]
+    pub fn handle_peer_events(&mut self, poll: &Poll) -> io::Result<()> {
+        let events: Vec<_> = match self.peers {
+            Some(ref peers) => std::iter::from_fn(|| peers.try_recv()).collect(),
+            None => vec![],
+        };
+        for event in events {
+            // link events are not caused by any local client
+            let mut response = handlers::Response::new(ClientId::max_value());
+            handlers::handle_peer_event(&mut self.server, &mut response, event);
+            self.handle_response(response, poll);
         }
         Ok(())
     }
@@ -465,13 +658,20 @@ impl NetworkLayer {
     }
 
     #[cfg(feature = "tls-connections")]
-    fn create_client_secure_socket(&self, socket: TcpStream) -> io::Result<ClientSocket> {
+    fn create_client_secure_socket(
+        &self,
+        socket: TcpStream,
+        is_websocket: bool,
+    ) -> io::Result<ClientSocket> {
         let ssl = Ssl::new(&self.ssl.context).unwrap();
         let mut builder = SslStreamBuilder::new(ssl, socket);
         builder.set_accept_state();
         match builder.handshake() {
+            Ok(stream) if is_websocket => Ok(ClientSocket::SecureWebSocket(WebSocket::new(stream))),
             Ok(stream) => Ok(ClientSocket::SslStream(stream)),
-            Err(HandshakeError::WouldBlock(stream)) => Ok(ClientSocket::SslHandshake(Some(stream))),
+            Err(HandshakeError::WouldBlock(stream)) => {
+                Ok(ClientSocket::SslHandshake(Some(stream), is_websocket))
+            }
             Err(e) => {
                 debug!("OpenSSL handshake failed: {}", e);
                 Err(Error::new(ErrorKind::Other, "Connection failure"))
@@ -484,7 +684,7 @@ impl NetworkLayer {
 
         if let ClientSocket::Plain(_) = self.clients[client_id].socket {
             #[cfg(feature = "tls-connections")]
-            response.add(Redirect(self.ssl.listener.local_addr().unwrap().port()).send_self())
+            response.add(Redirect(self.ssl.listener.local_addr().unwrap().port(), None).send_self())
         }
 
         handlers::handle_client_accept(&mut self.server, client_id, &mut response);
@@ -504,7 +704,29 @@ impl NetworkLayer {
             utils::SECURE_SERVER_TOKEN => {
                 let (client_socket, addr) = self.ssl.listener.accept()?;
                 info!("Connected(TLS): {}", addr);
-                self.register_client(poll, self.create_client_secure_socket(client_socket)?, addr)?;
+                self.register_client(
+                    poll,
+                    self.create_client_secure_socket(client_socket, false)?,
+                    addr,
+                )?;
+            }
+            utils::WEBSOCKET_SERVER_TOKEN => {
+                let listener = self.websocket_listener.as_ref().unwrap();
+                let (client_socket, addr) = listener.accept()?;
+                info!("Connected(WebSocket): {}", addr);
+                let socket = ClientSocket::WebSocket(WebSocket::new(client_socket));
+                self.register_client(poll, socket, addr)?;
+            }
+            #[cfg(feature = "tls-connections")]
+            utils::SECURE_WEBSOCKET_SERVER_TOKEN => {
+                let listener = self.ssl.websocket_listener.as_ref().unwrap();
+                let (client_socket, addr) = listener.accept()?;
+                info!("Connected(secure WebSocket): {}", addr);
+                self.register_client(
+                    poll,
+                    self.create_client_secure_socket(client_socket, true)?,
+                    addr,
+                )?;
             }
             _ => unreachable!(),
         }
@@ -555,7 +777,6 @@ impl NetworkLayer {
                         self.pending.insert((client_id, state));
                     }
                     NetworkClientState::Closed => self.client_error(&poll, client_id)?,
-                    #[cfg(feature = "tls-connections")]
                     NetworkClientState::Connected => self.init_client(poll, client_id),
                     _ => {}
                 };
@@ -627,8 +848,13 @@ impl NetworkLayer {
 pub struct NetworkLayerBuilder {
     listener: Option<TcpListener>,
     secure_listener: Option<TcpListener>,
+    websocket_listener: Option<TcpListener>,
+    secure_websocket_listener: Option<TcpListener>,
     clients_capacity: usize,
     rooms_capacity: usize,
+    config: ServerConfig,
+    assets: Option<AssetIndex>,
+    hooks: Vec<Box<dyn ServerHooks>>,
 }
 
 impl Default for NetworkLayerBuilder {
@@ -638,6 +864,11 @@ impl Default for NetworkLayerBuilder {
             rooms_capacity: 512,
             listener: None,
             secure_listener: None,
+            websocket_listener: None,
+            secure_websocket_listener: None,
+            config: ServerConfig::default(),
+            assets: None,
+            hooks: Vec::new(),
         }
     }
 }
@@ -657,8 +888,41 @@ impl NetworkLayerBuilder {
         }
     }
 
+    pub fn with_websocket_listener(self, listener: TcpListener) -> Self {
+        Self {
+            websocket_listener: Some(listener),
+            ..self
+        }
+    }
+
+    pub fn with_secure_websocket_listener(self, listener: TcpListener) -> Self {
+        Self {
+            secure_websocket_listener: Some(listener),
+            ..self
+        }
+    }
+
+    pub fn with_config(self, config: ServerConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    pub fn with_assets(self, assets: AssetIndex) -> Self {
+        Self {
+            assets: Some(assets),
+            ..self
+        }
+    }
+
+    pub fn with_hooks(mut self, hooks: Box<dyn ServerHooks>) -> Self {
+        self.hooks.push(hooks);
+        self
+    }
+
     #[cfg(feature = "tls-connections")]
-    fn create_ssl_context(listener: TcpListener) -> ServerSsl {
+    fn create_ssl_context(
+        listener: TcpListener,
+        websocket_listener: Option<TcpListener>,
+    ) -> ServerSsl {
         let mut builder = SslContextBuilder::new(SslMethod::tls()).unwrap();
         builder.set_verify(SslVerifyMode::NONE);
         builder.set_read_ahead(true);
@@ -672,19 +936,37 @@ impl NetworkLayerBuilder {
         builder.set_cipher_list("DEFAULT:!LOW:!RC4:!EXP").unwrap();
         ServerSsl {
             listener,
+            websocket_listener,
             context: builder.build(),
         }
     }
 
     pub fn build(self) -> NetworkLayer {
-        let server = HWServer::new(self.clients_capacity, self.rooms_capacity);
+        #[cfg(feature = "official-server")]
+        let library = RoomLibrary::new(self.config.room_library_dir());
+        #[cfg(feature = "official-server")]
+        let io_config = self.config.io.clone();
+        #[cfg(feature = "federation")]
+        let peers = if self.config.federation.is_enabled() {
+            Some(PeerLinks::new(&self.config.federation))
+        } else {
+            None
+        };
+
+        let mut server = HWServer::new(self.clients_capacity, self.rooms_capacity, self.config);
+        server.assets = self.assets;
+        for hooks in self.hooks {
+            server.register_hooks(hooks);
+        }
         let clients = Slab::with_capacity(self.clients_capacity);
         let pending = HashSet::with_capacity(2 * self.clients_capacity);
         let pending_cache = Vec::with_capacity(2 * self.clients_capacity);
-        let timer = timer::Builder::default().build();
+        let mut timer = timer::Builder::default().build();
+        timer.set_timeout(SERVER_TICK_INTERVAL, TimerData::ServerTick);
 
         NetworkLayer {
             listener: self.listener.expect("No listener provided"),
+            websocket_listener: self.websocket_listener,
             server,
             clients,
             pending,
@@ -692,9 +974,12 @@ impl NetworkLayerBuilder {
             #[cfg(feature = "tls-connections")]
             ssl: Self::create_ssl_context(
                 self.secure_listener.expect("No secure listener provided"),
+                self.secure_websocket_listener,
             ),
             #[cfg(feature = "official-server")]
-            io: IoLayer::new(),
+            io: IoLayer::new(library, &io_config),
+            #[cfg(feature = "federation")]
+            peers,
             timer,
         }
     }
@@ -0,0 +1,490 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Error, ErrorKind, Read, Result, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::server::{
+    config::FederationConfig,
+    federation::{PeerEvent, PeerId, PeerMessage},
+};
+use base64::encode;
+use log::*;
+use mio::{Evented, Poll, PollOpt};
+use mio_extras::channel;
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, sign::Signer};
+use rand::{thread_rng, RngCore};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+const MAX_MESSAGE_LINES: usize = 4096;
+const MAX_LINE_LENGTH: usize = 4096;
+
+struct PeerLink {
+    name: String,
+    sender: mpsc::Sender<PeerMessage>,
+}
+
+type Links = Arc<Mutex<HashMap<PeerId, PeerLink>>>;
+
+#[derive(Clone)]
+struct LinkContext {
+    name: String,
+    host: String,
+    port: u16,
+    secret: String,
+    next_peer_id: Arc<AtomicUsize>,
+    links: Links,
+    events: channel::Sender<PeerEvent>,
+}
+
+impl LinkContext {
+    fn hello(&self, challenge: String) -> PeerMessage {
+        PeerMessage::Hello {
+            name: self.name.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            challenge,
+        }
+    }
+
+    /// HMAC of the shared secret over the side of the link, both challenges and the name
+    /// of the server giving the proof, so that a proof cannot be reflected back at its author
+    fn proof(&self, role: Role, challenges: &Challenges, name: &str) -> Result<String> {
+        let key = PKey::hmac(self.secret.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        for part in &[role.label(), &challenges.client, &challenges.server, name] {
+            signer.update(part.as_bytes())?;
+            signer.update(b"\n")?;
+        }
+        let digest = signer.sign_to_vec()?;
+        Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    fn send_event(&self, event: PeerEvent) {
+        if self.events.send(event).is_err() {
+            warn!("Unable to deliver a federation event");
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Role {
+    /// The side that initiated the connection
+    Client,
+    /// The side that accepted the connection
+    Server,
+}
+
+impl Role {
+    fn label(self) -> &'static str {
+        match self {
+            Role::Client => "client",
+            Role::Server => "server",
+        }
+    }
+
+    fn other(self) -> Role {
+        match self {
+            Role::Client => Role::Server,
+            Role::Server => Role::Client,
+        }
+    }
+}
+
+struct Challenges {
+    client: String,
+    server: String,
+}
+
+struct MessageReader {
+    reader: BufReader<TcpStream>,
+}
+
+impl MessageReader {
+    /// Returns `None` for the messages that cannot be parsed
+    fn read(&mut self) -> Result<Option<PeerMessage>> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            let mut limited = (&mut self.reader).take(MAX_LINE_LENGTH as u64 + 1);
+            if limited.read_line(&mut line)? == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            } else if !line.ends_with('\n') && line.len() > MAX_LINE_LENGTH {
+                return Err(Error::new(ErrorKind::InvalidData, "Line is too long"));
+            }
+            let line = line.trim_end_matches('\n');
+            if line.is_empty() {
+                break;
+            } else if lines.len() == MAX_MESSAGE_LINES {
+                return Err(Error::new(ErrorKind::InvalidData, "Message is too long"));
+            }
+            lines.push(line.to_string());
+        }
+        Ok(PeerMessage::parse(&lines))
+    }
+}
+
+fn new_challenge() -> String {
+    let mut challenge = [0u8; 18];
+    thread_rng().fill_bytes(&mut challenge);
+    encode(&challenge)
+}
+
+fn write_message(stream: &mut TcpStream, msg: &PeerMessage) -> Result<()> {
+    stream.write_all(msg.to_raw_protocol().as_bytes())
+}
+
+/// Exchanges the greetings with the other server and checks that it knows the shared secret
+fn handshake(
+    stream: &mut TcpStream,
+    reader: &mut MessageReader,
+    context: &LinkContext,
+    role: Role,
+) -> Result<(String, String, u16)> {
+    let challenge = new_challenge();
+    write_message(stream, &context.hello(challenge.clone()))?;
+
+    let (name, host, port, challenges) = match reader.read()? {
+        Some(PeerMessage::Hello {
+            name,
+            host,
+            port,
+            challenge: peer_challenge,
+        }) => {
+            if peer_challenge == challenge {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "Challenge reflected",
+                ));
+            }
+            let challenges = match role {
+                Role::Client => Challenges {
+                    client: challenge,
+                    server: peer_challenge,
+                },
+                Role::Server => Challenges {
+                    client: peer_challenge,
+                    server: challenge,
+                },
+            };
+            let proof = context.proof(role, &challenges, &context.name)?;
+            write_message(stream, &PeerMessage::Auth(proof))?;
+            (name, host, port, challenges)
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidData, "Expected a greeting")),
+    };
+
+    let expected_proof = context.proof(role.other(), &challenges, &name)?;
+    match reader.read()? {
+        Some(PeerMessage::Auth(ref proof))
+            if proof.len() == expected_proof.len()
+                && memcmp::eq(proof.as_bytes(), expected_proof.as_bytes()) =>
+        {
+            Ok((name, host, port))
+        }
+        _ => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Authentication failed",
+        )),
+    }
+}
+
+fn run_link(mut stream: TcpStream, context: &LinkContext, role: Role) -> Result<()> {
+    let addr = stream.peer_addr()?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = MessageReader {
+        reader: BufReader::new(stream.try_clone()?),
+    };
+    let (name, host, port) = handshake(&mut stream, &mut reader, context, role)?;
+    stream.set_read_timeout(None)?;
+
+    let peer_id = context.next_peer_id.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = mpsc::channel::<PeerMessage>();
+    {
+        let mut links = context.links.lock().unwrap();
+        if name == context.name || links.values().any(|link| link.name == name) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Server {} is already linked", name),
+            ));
+        }
+        links.insert(
+            peer_id,
+            PeerLink {
+                name: name.clone(),
+                sender: tx,
+            },
+        );
+    }
+
+    let writer = thread::Builder::new()
+        .name(format!("federation-writer-{}", peer_id))
+        .spawn(move || {
+            for msg in rx {
+                if let Err(e) = write_message(&mut stream, &msg) {
+                    debug!(
+                        "Error while writing to the federation link {}: {}",
+                        peer_id, e
+                    );
+                    break;
+                }
+            }
+            let _ = stream.shutdown(Shutdown::Both);
+        })?;
+
+    info!("Linked to server {} ({})", name, addr);
+    context.send_event(PeerEvent::Connected {
+        peer_id,
+        name: name.clone(),
+        host,
+        port,
+    });
+
+    let result = loop {
+        match reader.read() {
+            Ok(Some(msg)) => context.send_event(PeerEvent::Message(peer_id, msg)),
+            Ok(None) => warn!("Invalid message from server {}", name),
+            Err(e) => break e,
+        }
+    };
+
+    context.links.lock().unwrap().remove(&peer_id);
+    context.send_event(PeerEvent::Disconnected(peer_id));
+    if writer.join().is_err() {
+        warn!("The writer of the federation link {} panicked", peer_id);
+    }
+    info!("Link to server {} is closed: {}", name, result);
+    Ok(())
+}
+
+fn accept_links(listener: TcpListener, context: LinkContext) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let context = context.clone();
+                let result = thread::Builder::new()
+                    .name("federation-link".to_string())
+                    .spawn(move || {
+                        if let Err(e) = run_link(stream, &context, Role::Server) {
+                            warn!("Federation link refused: {}", e);
+                        }
+                    });
+                if let Err(e) = result {
+                    warn!("Unable to start a federation link: {}", e);
+                }
+            }
+            Err(e) => warn!("Error accepting a federation link: {}", e),
+        }
+    }
+}
+
+fn connect_link(address: String, context: LinkContext) {
+    loop {
+        match TcpStream::connect(&address) {
+            Ok(stream) => {
+                if let Err(e) = run_link(stream, &context, Role::Client) {
+                    warn!("Federation link to {} failed: {}", address, e);
+                }
+            }
+            Err(e) => debug!("Unable to connect to {}: {}", address, e),
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+/// Links to the other servers of the federation, each link runs in its own threads
+pub struct PeerLinks {
+    events_rx: channel::Receiver<PeerEvent>,
+    links: Links,
+}
+
+impl PeerLinks {
+    pub fn new(config: &FederationConfig) -> Self {
+        let (events_tx, events_rx) = channel::channel();
+        let links = Arc::new(Mutex::new(HashMap::new()));
+        let context = LinkContext {
+            name: config.name.clone(),
+            host: config.public_host.clone(),
+            port: config.public_port,
+            secret: config.secret.clone(),
+            next_peer_id: Arc::new(AtomicUsize::new(0)),
+            links: links.clone(),
+            events: events_tx,
+        };
+
+        if let Some(ref address) = config.listen {
+            match TcpListener::bind(address) {
+                Ok(listener) => {
+                    let context = context.clone();
+                    thread::Builder::new()
+                        .name("federation-listener".to_string())
+                        .spawn(move || accept_links(listener, context))
+                        .expect("Unable to start the federation listener");
+                }
+                Err(e) => error!(
+                    "Unable to listen for the federation links on {}: {}",
+                    address, e
+                ),
+            }
+        }
+
+        for address in &config.peers {
+            let address = address.clone();
+            let context = context.clone();
+            thread::Builder::new()
+                .name(format!("federation-connector-{}", address))
+                .spawn(move || connect_link(address, context))
+                .expect("Unable to start a federation connector");
+        }
+
+        Self { events_rx, links }
+    }
+
+    pub fn broadcast(&self, msg: &PeerMessage) {
+        for link in self.links.lock().unwrap().values() {
+            if link.sender.send(msg.clone()).is_err() {
+                debug!("Unable to send a message to server {}", link.name);
+            }
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<PeerEvent> {
+        self.events_rx.try_recv().ok()
+    }
+
+    pub fn register_rx(&self, poll: &Poll, token: mio::Token) -> Result<()> {
+        self.events_rx
+            .register(poll, token, mio::Ready::readable(), PollOpt::edge())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context(name: &str, secret: &str) -> LinkContext {
+        let (events, _) = channel::channel();
+        LinkContext {
+            name: name.to_string(),
+            host: "localhost".to_string(),
+            port: 46631,
+            secret: secret.to_string(),
+            next_peer_id: Arc::new(AtomicUsize::new(0)),
+            links: Arc::new(Mutex::new(HashMap::new())),
+            events,
+        }
+    }
+
+    fn reader(stream: &TcpStream) -> MessageReader {
+        MessageReader {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+        }
+    }
+
+    /// Runs the handshake of `server` on an accepted connection while `client` drives the other end
+    fn run_server<F>(server: LinkContext, client: F) -> Result<(String, String, u16)>
+    where
+        F: FnOnce(TcpStream),
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).unwrap();
+            let mut reader = reader(&stream);
+            let result = handshake(&mut stream, &mut reader, &server, Role::Server);
+            let _ = stream.shutdown(Shutdown::Both);
+            result
+        });
+        client(TcpStream::connect(address).unwrap());
+        server.join().unwrap()
+    }
+
+    fn connect(client: LinkContext) -> impl FnOnce(TcpStream) {
+        move |mut stream| {
+            let mut reader = reader(&stream);
+            let result = handshake(&mut stream, &mut reader, &client, Role::Client);
+            assert!(result.is_ok());
+        }
+    }
+
+    #[test]
+    fn handshake_with_shared_secret() {
+        let result = run_server(context("a", "secret"), connect(context("b", "secret")));
+        assert_eq!(result.unwrap().0, "b");
+    }
+
+    #[test]
+    fn handshake_with_wrong_secret() {
+        let result = run_server(context("a", "secret"), |mut stream| {
+            let mut reader = reader(&stream);
+            let result = handshake(
+                &mut stream,
+                &mut reader,
+                &context("b", "guess"),
+                Role::Client,
+            );
+            assert!(result.is_err());
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn handshake_with_reflected_challenge() {
+        let result = run_server(context("a", "secret"), |mut stream| {
+            let mut reader = reader(&stream);
+            match reader.read().unwrap() {
+                Some(hello @ PeerMessage::Hello { .. }) => {
+                    write_message(&mut stream, &hello).unwrap()
+                }
+                _ => panic!("Expected a greeting"),
+            }
+            if let Ok(Some(auth @ PeerMessage::Auth(_))) = reader.read() {
+                let _ = write_message(&mut stream, &auth);
+            }
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn handshake_with_reflected_proof() {
+        let result = run_server(context("a", "secret"), |mut stream| {
+            let mut reader = reader(&stream);
+            match reader.read().unwrap() {
+                Some(PeerMessage::Hello {
+                    name, host, port, ..
+                }) => {
+                    let hello = PeerMessage::Hello {
+                        name,
+                        host,
+                        port,
+                        challenge: new_challenge(),
+                    };
+                    write_message(&mut stream, &hello).unwrap()
+                }
+                _ => panic!("Expected a greeting"),
+            }
+            match reader.read().unwrap() {
+                Some(auth @ PeerMessage::Auth(_)) => write_message(&mut stream, &auth).unwrap(),
+                _ => panic!("Expected a proof"),
+            }
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn long_lines() {
+        let result = run_server(context("a", "secret"), |mut stream| {
+            let line = "x".repeat(MAX_LINE_LENGTH + 1);
+            let _ = stream.write_all(line.as_bytes());
+        });
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
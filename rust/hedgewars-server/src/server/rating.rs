@@ -0,0 +1,64 @@
+/// Rating of a registered player on the ranked ladder
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerRating {
+    pub nick: String,
+    pub rating: i32,
+    pub games: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RatingChange {
+    pub nick: String,
+    pub old_rating: i32,
+    pub new_rating: i32,
+}
+
+/// Elo expectation of the player to win against the opponent
+pub fn expected_score(rating: i32, opponent_rating: i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(f64::from(opponent_rating - rating) / 400.0))
+}
+
+fn average(ratings: &[i32]) -> i32 {
+    if ratings.is_empty() {
+        0
+    } else {
+        ratings.iter().sum::<i32>() / ratings.len() as i32
+    }
+}
+
+/// Computes the rating deltas of the winners and the losers of a game.
+/// Every player is rated against the average rating of the opposing side
+pub fn elo_changes(winners: &[i32], losers: &[i32], k_factor: u32) -> (Vec<i32>, Vec<i32>) {
+    let k_factor = f64::from(k_factor);
+    let delta = |rating: i32, opponents: &[i32], score: f64| {
+        (k_factor * (score - expected_score(rating, average(opponents)))).round() as i32
+    };
+
+    (
+        winners.iter().map(|r| delta(*r, losers, 1.0)).collect(),
+        losers.iter().map(|r| delta(*r, winners, 0.0)).collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expected_scores() {
+        assert_eq!(expected_score(1500, 1500), 0.5);
+        assert!((expected_score(1900, 1500) - 0.909).abs() < 0.001);
+        assert!((expected_score(1500, 1900) - 0.091).abs() < 0.001);
+    }
+
+    #[test]
+    fn rating_changes() {
+        assert_eq!(elo_changes(&[1500], &[1500], 32), (vec![16], vec![-16]));
+        assert_eq!(elo_changes(&[1900], &[1500], 32), (vec![3], vec![-3]));
+        assert_eq!(elo_changes(&[1500], &[1900], 32), (vec![29], vec![-29]));
+        assert_eq!(
+            elo_changes(&[1400, 1600], &[1500], 32),
+            (vec![20, 12], vec![-16])
+        );
+    }
+}
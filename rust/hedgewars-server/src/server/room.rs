@@ -1,14 +1,20 @@
 use crate::server::{
+    chat_history::ChatHistory,
     client::HWClient,
     coretypes::{
         ClientId, GameCfg, GameCfg::*, RoomConfig, RoomId, TeamInfo, Voting, MAX_HEDGEHOGS_PER_TEAM,
     },
+    demo::game_demo,
 };
 use bitflags::*;
 use serde::{Deserialize, Serialize};
 use serde_derive::{Deserialize, Serialize};
 use serde_yaml;
-use std::{collections::HashMap, iter};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    iter,
+};
 
 pub const MAX_TEAMS_IN_ROOM: u8 = 8;
 pub const MAX_HEDGEHOGS_IN_ROOM: u8 = MAX_HEDGEHOGS_PER_TEAM * MAX_HEDGEHOGS_PER_TEAM;
@@ -30,6 +36,12 @@ pub struct GameInfo {
     pub msg_log: Vec<String>,
     pub sync_msg: Option<String>,
     pub is_paused: bool,
+    /// Game setup checksums reported by the clients, keyed by the engine ticks
+    pub checksums: BTreeMap<u32, Vec<(ClientId, String)>>,
+    /// Engine ticks of the last timed message of each client
+    pub engine_ticks: HashMap<ClientId, u32>,
+    /// Engine ticks of the desyncs already written to the log
+    pub reported_desyncs: HashSet<u32>,
     config: RoomConfig,
 }
 
@@ -40,6 +52,9 @@ impl GameInfo {
             msg_log: Vec::new(),
             sync_msg: None,
             is_paused: false,
+            checksums: BTreeMap::new(),
+            engine_ticks: HashMap::new(),
+            reported_desyncs: HashSet::new(),
             teams_in_game: teams.len() as u8,
             teams_at_start: teams,
             config,
@@ -49,6 +64,54 @@ impl GameInfo {
     pub fn client_teams(&self, client_id: ClientId) -> impl Iterator<Item = &TeamInfo> + Clone {
         client_teams_impl(&self.teams_at_start, client_id)
     }
+
+    /// Records the checksum the client reported at the engine ticks,
+    /// returns `true` if it differs from a checksum reported by another client
+    pub fn add_checksum(&mut self, client_id: ClientId, ticks: u32, checksum: String) -> bool {
+        let entries = self.checksums.entry(ticks).or_insert_with(Vec::new);
+        entries.retain(|(id, _)| *id != client_id);
+        let is_desync = entries.iter().any(|(_, c)| *c != checksum);
+        entries.push((client_id, checksum));
+        is_desync
+    }
+
+    /// Groups the clients by the checksums reported at the engine ticks, the largest group first
+    pub fn checksum_groups(&self, ticks: u32) -> Vec<Vec<ClientId>> {
+        let mut groups: Vec<(&str, Vec<ClientId>)> = vec![];
+        for (client_id, checksum) in self.checksums.get(&ticks).into_iter().flatten() {
+            match groups.iter_mut().find(|(c, _)| c == checksum) {
+                Some((_, ids)) => ids.push(*client_id),
+                None => groups.push((checksum, vec![*client_id])),
+            }
+        }
+        groups.sort_by_key(|(_, ids)| Reverse(ids.len()));
+        groups.into_iter().map(|(_, ids)| ids).collect()
+    }
+
+    /// The engine demo of the game the checker replays to find out the winners
+    pub fn demo(&self) -> Option<Vec<String>> {
+        let teams: Vec<_> = self.teams_at_start.iter().map(|(_, t)| t.clone()).collect();
+        game_demo(&self.config, &teams, &self.msg_log)
+    }
+
+    /// Returns the teams of the only clan left in the game after the other teams left it
+    pub fn forfeit_winners(&self) -> Option<Vec<String>> {
+        if self.left_teams.is_empty() {
+            return None;
+        }
+        let remaining: Vec<_> = self
+            .teams_at_start
+            .iter()
+            .map(|(_, t)| t)
+            .filter(|t| !self.left_teams.contains(&t.name))
+            .collect();
+        let clan = remaining.first()?.color;
+        if remaining.iter().all(|t| t.color == clan) {
+            Some(remaining.iter().map(|t| t.name.clone()).collect())
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -63,6 +126,9 @@ bitflags! {
         const RESTRICTED_JOIN = 0b0000_0010;
         const RESTRICTED_TEAM_ADD = 0b0000_0100;
         const RESTRICTED_UNREGISTERED_PLAYERS = 0b0000_1000;
+        const AUTO_BALANCE = 0b0001_0000;
+        const PERMANENT = 0b0010_0000;
+        const RANKED = 0b0100_0000;
     }
 }
 
@@ -84,6 +150,7 @@ pub struct HWRoom {
     pub voting: Option<Voting>,
     pub saves: HashMap<String, RoomSave>,
     pub game_info: Option<GameInfo>,
+    pub chat_history: ChatHistory,
 }
 
 impl HWRoom {
@@ -105,6 +172,7 @@ impl HWRoom {
             voting: None,
             saves: HashMap::new(),
             game_info: None,
+            chat_history: ChatHistory::new(0),
         }
     }
 
@@ -215,6 +283,70 @@ impl HWRoom {
         self.client_teams(owner_id).nth(0).map(|t| t.color)
     }
 
+    pub fn clans_number(&self) -> u8 {
+        let mut colors: Vec<_> = self.teams.iter().map(|(_, t)| t.color).collect();
+        colors.sort_unstable();
+        colors.dedup();
+        colors.len() as u8
+    }
+
+    /// Spreads the teams across `clans_number` clans evenly by hedgehog and player count.
+    /// The teams of a single client are kept together unless there are fewer clients
+    /// than clans. Returns the indices of the teams whose color has changed.
+    pub fn balance_clans(&mut self, clans_number: u8) -> Vec<usize> {
+        let clans_number = (clans_number.min(MAX_TEAMS_IN_ROOM) as usize).min(self.teams.len());
+        if clans_number < 2 {
+            return vec![];
+        }
+
+        let mut owners: Vec<ClientId> = vec![];
+        let mut groups: Vec<(u32, Vec<usize>)> = vec![];
+        for (index, (owner_id, team)) in self.teams.iter().enumerate() {
+            let hedgehogs = u32::from(team.hedgehogs_number);
+            match owners.iter().position(|id| id == owner_id) {
+                Some(i) => {
+                    groups[i].0 += hedgehogs;
+                    groups[i].1.push(index);
+                }
+                None => {
+                    owners.push(*owner_id);
+                    groups.push((hedgehogs, vec![index]));
+                }
+            }
+        }
+
+        if groups.len() < clans_number {
+            groups = self
+                .teams
+                .iter()
+                .enumerate()
+                .map(|(index, (_, t))| (u32::from(t.hedgehogs_number), vec![index]))
+                .collect();
+        }
+        groups.sort_by_key(|(hedgehogs, indices)| Reverse((*hedgehogs, indices.len())));
+
+        let mut clans = vec![(0u32, 0usize); clans_number];
+        let mut changed = vec![];
+        for (hedgehogs, indices) in groups {
+            let (color, clan) = clans
+                .iter_mut()
+                .enumerate()
+                .min_by_key(|(_, clan)| **clan)
+                .unwrap();
+            clan.0 += hedgehogs;
+            clan.1 += 1;
+
+            for index in indices {
+                let team = &mut self.teams[index].1;
+                if team.color != color as u8 {
+                    team.color = color as u8;
+                    changed.push(index);
+                }
+            }
+        }
+        changed
+    }
+
     pub fn has_multiple_clans(&self) -> bool {
         self.teams.iter().min_by_key(|(_, t)| t.color)
             != self.teams.iter().max_by_key(|(_, t)| t.color)
@@ -233,6 +365,15 @@ impl HWRoom {
     pub fn is_fixed(&self) -> bool {
         self.flags.contains(RoomFlags::FIXED)
     }
+    pub fn is_permanent(&self) -> bool {
+        self.flags.contains(RoomFlags::PERMANENT)
+    }
+    pub fn is_ranked(&self) -> bool {
+        self.flags.contains(RoomFlags::RANKED)
+    }
+    pub fn is_auto_balanced(&self) -> bool {
+        self.flags.contains(RoomFlags::AUTO_BALANCE)
+    }
     pub fn is_join_restricted(&self) -> bool {
         self.flags.contains(RoomFlags::RESTRICTED_JOIN)
     }
@@ -247,6 +388,15 @@ impl HWRoom {
     pub fn set_is_fixed(&mut self, value: bool) {
         self.flags.set(RoomFlags::FIXED, value)
     }
+    pub fn set_is_permanent(&mut self, value: bool) {
+        self.flags.set(RoomFlags::PERMANENT, value)
+    }
+    pub fn set_is_ranked(&mut self, value: bool) {
+        self.flags.set(RoomFlags::RANKED, value)
+    }
+    pub fn set_is_auto_balanced(&mut self, value: bool) {
+        self.flags.set(RoomFlags::AUTO_BALANCE, value)
+    }
     pub fn set_join_restriction(&mut self, value: bool) {
         self.flags.set(RoomFlags::RESTRICTED_JOIN, value)
     }
@@ -334,6 +484,14 @@ impl HWRoom {
         self.saves.remove(name).is_some()
     }
 
+    pub fn get_config_preset(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(&self.config)
+    }
+
+    pub fn set_config_preset(&mut self, text: &str) -> Result<(), serde_yaml::Error> {
+        serde_yaml::from_str::<RoomConfig>(text).map(|config| self.config = config)
+    }
+
     pub fn get_saves(&self) -> Result<String, serde_yaml::Error> {
         serde_yaml::to_string(&(&self.greeting, &self.saves))
     }
@@ -347,3 +505,125 @@ impl HWRoom {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::coretypes::HedgehogInfo;
+
+    fn team(name: &str, color: u8) -> TeamInfo {
+        let hedgehog = || HedgehogInfo {
+            name: String::new(),
+            hat: String::new(),
+        };
+        TeamInfo {
+            owner: String::new(),
+            name: name.to_string(),
+            color,
+            grave: String::new(),
+            fort: String::new(),
+            voice_pack: String::new(),
+            flag: String::new(),
+            difficulty: 0,
+            hedgehogs_number: 4,
+            hedgehogs: [
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+                hedgehog(),
+            ],
+        }
+    }
+
+    fn game_info(teams: &[(ClientId, &str, u8)]) -> GameInfo {
+        let teams = teams
+            .iter()
+            .map(|(id, name, color)| (*id, team(name, *color)))
+            .collect();
+        GameInfo::new(teams, RoomConfig::new())
+    }
+
+    fn names(teams: &[&str]) -> Option<Vec<String>> {
+        Some(teams.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn forfeit_winners() {
+        let mut info = game_info(&[(0, "a", 0), (1, "b", 1), (2, "c", 1)]);
+        assert_eq!(info.forfeit_winners(), None);
+        info.left_teams.push("a".to_string());
+        assert_eq!(info.forfeit_winners(), names(&["b", "c"]));
+
+        let mut info = game_info(&[(0, "a", 0), (1, "b", 1), (2, "c", 2)]);
+        info.left_teams.push("a".to_string());
+        assert_eq!(info.forfeit_winners(), None);
+        info.left_teams.push("c".to_string());
+        assert_eq!(info.forfeit_winners(), names(&["b"]));
+    }
+
+    #[test]
+    fn checksums() {
+        let mut info = game_info(&[(0, "a", 0), (1, "b", 1), (2, "c", 2), (3, "d", 3)]);
+        assert!(!info.add_checksum(0, 0, "1|1".to_string()));
+        assert!(!info.add_checksum(1, 0, "1|1".to_string()));
+        // checksums reported at other ticks are not compared
+        assert!(!info.add_checksum(2, 100, "1|2".to_string()));
+        assert!(info.add_checksum(2, 0, "1|2".to_string()));
+        assert!(!info.add_checksum(3, 100, "1|2".to_string()));
+
+        assert_eq!(info.checksum_groups(0), vec![vec![0, 1], vec![2]]);
+        assert_eq!(info.checksum_groups(100), vec![vec![2, 3]]);
+        assert!(info.checksum_groups(50).is_empty());
+
+        // a new report replaces the previous one at the same ticks
+        assert!(!info.add_checksum(2, 0, "1|1".to_string()));
+        assert_eq!(info.checksum_groups(0), vec![vec![0, 1, 2]]);
+    }
+
+    fn room_with(teams: &[(ClientId, &str, u8)]) -> HWRoom {
+        let mut room = HWRoom::new(0);
+        room.teams = teams
+            .iter()
+            .map(|(id, name, hedgehogs)| {
+                let mut team = team(name, 0);
+                team.hedgehogs_number = *hedgehogs;
+                (*id, team)
+            })
+            .collect();
+        room
+    }
+
+    fn colors(room: &HWRoom) -> Vec<u8> {
+        room.teams.iter().map(|(_, t)| t.color).collect()
+    }
+
+    #[test]
+    fn balance_clans() {
+        let mut room = room_with(&[(0, "a", 4), (0, "b", 4), (1, "c", 4), (1, "d", 4)]);
+        assert_eq!(room.balance_clans(2), vec![2, 3]);
+        assert_eq!(colors(&room), vec![0, 0, 1, 1]);
+        assert!(room.balance_clans(2).is_empty());
+
+        // teams of a single client are split when there are fewer clients than clans
+        let mut room = room_with(&[(0, "a", 4), (0, "b", 4), (0, "c", 4)]);
+        assert_eq!(room.balance_clans(3), vec![1, 2]);
+        assert_eq!(colors(&room), vec![0, 1, 2]);
+
+        // the clans are evened out by hedgehogs first
+        let mut room = room_with(&[(0, "a", 6), (1, "b", 2), (2, "c", 2), (3, "d", 2)]);
+        assert_eq!(room.balance_clans(2), vec![1, 2, 3]);
+        assert_eq!(colors(&room), vec![0, 1, 1, 1]);
+
+        // there are never more clans than teams
+        let mut room = room_with(&[(0, "a", 4), (1, "b", 4)]);
+        assert_eq!(room.balance_clans(8), vec![1]);
+        assert_eq!(colors(&room), vec![0, 1]);
+
+        let mut room = room_with(&[(0, "a", 4)]);
+        assert!(room.balance_clans(2).is_empty());
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+const PUBLIC_DIR: &str = "public";
+const USERS_DIR: &str = "users";
+const ROOMS_DIR: &str = "rooms";
+const EXTENSION: &str = "yaml";
+const MAX_NAME_LENGTH: usize = 40;
+
+pub enum PresetScope {
+    Public,
+    Private(String),
+}
+
+/// Names are used as file names, so only a safe subset of characters is allowed
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && !name.starts_with(' ')
+        && !name.ends_with(' ')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_')
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if is_valid_name(name) {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::InvalidInput, "Invalid name"))
+    }
+}
+
+fn nick_dir(nick: &str) -> String {
+    nick.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Room config storage confined to a single directory:
+/// public presets are stored in `public`, private ones in `users/<hex encoded nick>`
+/// and the room saves in `rooms`
+#[derive(Clone)]
+pub struct RoomLibrary {
+    path: PathBuf,
+}
+
+impl RoomLibrary {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn scope_dir(&self, scope: &PresetScope) -> PathBuf {
+        match scope {
+            PresetScope::Public => self.path.join(PUBLIC_DIR),
+            PresetScope::Private(nick) => self.path.join(USERS_DIR).join(nick_dir(nick)),
+        }
+    }
+
+    fn file_path(dir: &Path, name: &str) -> Result<PathBuf> {
+        check_name(name)?;
+        Ok(dir.join(name).with_extension(EXTENSION))
+    }
+
+    fn save_file(dir: &Path, name: &str, contents: &str) -> Result<()> {
+        let path = Self::file_path(dir, name)?;
+        fs::create_dir_all(dir)?;
+        fs::write(path, contents)
+    }
+
+    pub fn list_presets(&self, scope: &PresetScope) -> Result<Vec<String>> {
+        let dir = self.scope_dir(scope);
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut names: Vec<_> = fs::read_dir(dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? == EXTENSION {
+                    Some(path.file_stem()?.to_str()?.to_string())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn load_preset(&self, scope: &PresetScope, name: &str) -> Result<String> {
+        fs::read_to_string(Self::file_path(&self.scope_dir(scope), name)?)
+    }
+
+    pub fn save_preset(&self, scope: &PresetScope, name: &str, contents: &str) -> Result<()> {
+        Self::save_file(&self.scope_dir(scope), name, contents)
+    }
+
+    pub fn delete_preset(&self, scope: &PresetScope, name: &str) -> Result<()> {
+        fs::remove_file(Self::file_path(&self.scope_dir(scope), name)?)
+    }
+
+    pub fn load_room(&self, name: &str) -> Result<String> {
+        fs::read_to_string(Self::file_path(&self.path.join(ROOMS_DIR), name)?)
+    }
+
+    pub fn save_room(&self, name: &str, contents: &str) -> Result<()> {
+        Self::save_file(&self.path.join(ROOMS_DIR), name, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(is_valid_name("Team battle 2"));
+        assert!(is_valid_name("fort_mode-v2"));
+
+        for name in &[
+            "",
+            " padded",
+            "padded ",
+            "..",
+            "../public/preset",
+            "users/preset",
+            "..\\preset",
+            "preset.yaml",
+            "préset",
+            &"x".repeat(MAX_NAME_LENGTH + 1),
+        ] {
+            assert!(!is_valid_name(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn library() {
+        let path = std::env::temp_dir().join(format!("hw-library-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let library = RoomLibrary::new(&path);
+        let public = PresetScope::Public;
+        let private = PresetScope::Private("../nick".to_string());
+
+        assert!(library.list_presets(&public).unwrap().is_empty());
+        library.save_preset(&public, "b", "public b").unwrap();
+        library.save_preset(&public, "a", "public a").unwrap();
+        library.save_preset(&private, "a", "private a").unwrap();
+        library.save_room("a", "room a").unwrap();
+
+        assert_eq!(library.list_presets(&public).unwrap(), vec!["a", "b"]);
+        assert_eq!(library.list_presets(&private).unwrap(), vec!["a"]);
+        assert_eq!(library.load_preset(&public, "a").unwrap(), "public a");
+        assert_eq!(library.load_preset(&private, "a").unwrap(), "private a");
+        assert_eq!(library.load_room("a").unwrap(), "room a");
+        assert!(path.join("users/2e2e2f6e69636b/a.yaml").is_file());
+
+        let error = library
+            .load_preset(&private, "../../../public/a")
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let error = library.save_room("../escape", "room").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(!path.join("escape.yaml").exists());
+
+        library.delete_preset(&public, "a").unwrap();
+        let presets = library.list_presets(&public);
+        fs::remove_dir_all(&path).unwrap();
+        assert_eq!(presets.unwrap(), vec!["b"]);
+    }
+}
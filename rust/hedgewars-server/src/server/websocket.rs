@@ -0,0 +1,387 @@
+use base64::encode;
+use sha1::Sha1;
+use std::{
+    cmp::min,
+    io::{self, Error, ErrorKind, Read, Write},
+};
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HANDSHAKE_SIZE: usize = 8192;
+const MAX_FRAME_SIZE: usize = 65536;
+const READ_CHUNK_SIZE: usize = 4096;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+#[derive(Debug, PartialEq)]
+struct Frame {
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Computes the `Sec-WebSocket-Accept` value for the key sent by the client
+fn accept_key(key: &str) -> String {
+    let digest = Sha1::from(format!("{}{}", key, HANDSHAKE_GUID)).digest();
+    encode(&digest.bytes())
+}
+
+/// Checks the HTTP upgrade request and returns the key of the client
+fn parse_upgrade_request(request: &str) -> Option<&str> {
+    let mut lines = request.split("\r\n");
+    let request_line = lines.next()?;
+    if !request_line.starts_with("GET ") || !request_line.ends_with(" HTTP/1.1") {
+        return None;
+    }
+
+    let mut key = None;
+    let mut is_upgrade = false;
+    let mut is_websocket = false;
+    let mut is_supported_version = false;
+
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next()?.trim().to_lowercase();
+        let value = parts.next()?.trim();
+        let has_token = |token: &str| {
+            value
+                .split(',')
+                .any(|v| v.trim().eq_ignore_ascii_case(token))
+        };
+        match &name[..] {
+            "connection" => is_upgrade = has_token("upgrade"),
+            "upgrade" => is_websocket = has_token("websocket"),
+            "sec-websocket-version" => is_supported_version = value == "13",
+            "sec-websocket-key" if !value.is_empty() => key = Some(value),
+            _ => (),
+        }
+    }
+
+    if is_upgrade && is_websocket && is_supported_version {
+        key
+    } else {
+        None
+    }
+}
+
+/// Parses a client frame from the start of the buffer.
+/// Returns the frame with the unmasked payload and the number of bytes it occupied
+fn parse_frame(buf: &[u8]) -> io::Result<Option<(Frame, usize)>> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    if buf[0] & 0x70 != 0 {
+        return Err(invalid_data("Reserved frame bits are set"));
+    }
+    if buf[1] & 0x80 == 0 {
+        return Err(invalid_data("Client frames must be masked"));
+    }
+
+    let opcode = buf[0] & 0x0F;
+    let (length, mut offset) = match buf[1] & 0x7F {
+        126 if buf.len() >= 4 => (usize::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+        127 if buf.len() >= 10 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&buf[2..10]);
+            (u64::from_be_bytes(bytes) as usize, 10)
+        }
+        126 | 127 => return Ok(None),
+        length => (usize::from(length), 2),
+    };
+
+    if length > MAX_FRAME_SIZE {
+        return Err(invalid_data("Frame is too large"));
+    }
+    if opcode & 0x8 != 0 && (length > 125 || buf[0] & 0x80 == 0) {
+        return Err(invalid_data("Invalid control frame"));
+    }
+    if buf.len() < offset + 4 + length {
+        return Ok(None);
+    }
+
+    let mut mask = [0u8; 4];
+    mask.copy_from_slice(&buf[offset..offset + 4]);
+    offset += 4;
+    let payload = buf[offset..offset + length]
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+
+    Ok(Some((Frame { opcode, payload }, offset + length)))
+}
+
+/// Encodes an unmasked server frame
+fn encode_frame(opcode: u8, payload: &[u8], out: &mut Vec<u8>) {
+    out.push(0x80 | opcode);
+    match payload.len() {
+        length @ 0..=125 => out.push(length as u8),
+        length @ 126..=0xFFFF => {
+            out.push(126);
+            out.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            out.push(127);
+            out.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(payload);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum WebSocketState {
+    Handshake,
+    Open,
+    Closed,
+}
+
+/// A server side WebSocket connection over the stream.
+/// The payload of the data frames is read as a continuous byte stream
+/// and every write is sent as a single text frame
+pub struct WebSocket<S> {
+    stream: S,
+    state: WebSocketState,
+    buf_in: Vec<u8>,
+    data: Vec<u8>,
+    buf_out: Vec<u8>,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            state: WebSocketState::Handshake,
+            buf_in: vec![],
+            data: vec![],
+            buf_out: vec![],
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn is_handshake_complete(&self) -> bool {
+        self.state != WebSocketState::Handshake
+    }
+
+    /// Reads more data from the stream into the input buffer, returns `false` on EOF
+    fn fill_buffer(&mut self) -> io::Result<bool> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        let bytes = self.stream.read(&mut chunk)?;
+        self.buf_in.extend_from_slice(&chunk[..bytes]);
+        Ok(bytes > 0)
+    }
+
+    /// Writes out the buffered frames, returns `false` if the stream is not ready for all of them
+    fn send_buffered(&mut self) -> io::Result<bool> {
+        while !self.buf_out.is_empty() {
+            match self.stream.write(&self.buf_out) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(bytes) => {
+                    self.buf_out.drain(..bytes);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Processes the HTTP upgrade request, returns `true` once the connection is open
+    pub fn handshake(&mut self) -> io::Result<bool> {
+        loop {
+            if let Some(end) = self.buf_in.windows(4).position(|w| w == b"\r\n\r\n") {
+                let request = String::from_utf8_lossy(&self.buf_in[..end]).into_owned();
+                self.buf_in.drain(..end + 4);
+
+                return match parse_upgrade_request(&request) {
+                    Some(key) => {
+                        let response = format!(
+                            "HTTP/1.1 101 Switching Protocols\r\n\
+                             Upgrade: websocket\r\n\
+                             Connection: Upgrade\r\n\
+                             Sec-WebSocket-Accept: {}\r\n\r\n",
+                            accept_key(key)
+                        );
+                        self.buf_out.extend_from_slice(response.as_bytes());
+                        self.state = WebSocketState::Open;
+                        self.send_buffered()?;
+                        Ok(true)
+                    }
+                    None => {
+                        self.buf_out
+                            .extend_from_slice(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+                        let _ = self.send_buffered();
+                        Err(invalid_data("Invalid WebSocket handshake"))
+                    }
+                };
+            } else if self.buf_in.len() > MAX_HANDSHAKE_SIZE {
+                return Err(invalid_data("WebSocket handshake is too long"));
+            }
+
+            match self.fill_buffer() {
+                Ok(true) => (),
+                Ok(false) => return Err(ErrorKind::UnexpectedEof.into()),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> io::Result<()> {
+        match frame.opcode {
+            OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+                self.data.extend_from_slice(&frame.payload)
+            }
+            OPCODE_PING => {
+                encode_frame(OPCODE_PONG, &frame.payload, &mut self.buf_out);
+                self.send_buffered()?;
+            }
+            OPCODE_PONG => (),
+            OPCODE_CLOSE => {
+                // echo the status code back
+                let status = &frame.payload[..min(frame.payload.len(), 2)];
+                encode_frame(OPCODE_CLOSE, status, &mut self.buf_out);
+                self.state = WebSocketState::Closed;
+                let _ = self.send_buffered();
+            }
+            _ => return Err(invalid_data("Unknown frame opcode")),
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for WebSocket<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.data.is_empty() {
+                let bytes = min(buf.len(), self.data.len());
+                buf[..bytes].copy_from_slice(&self.data[..bytes]);
+                self.data.drain(..bytes);
+                return Ok(bytes);
+            } else if self.state == WebSocketState::Closed {
+                return Ok(0);
+            }
+
+            match parse_frame(&self.buf_in)? {
+                Some((frame, size)) => {
+                    self.buf_in.drain(..size);
+                    self.handle_frame(frame)?;
+                }
+                None => {
+                    if !self.fill_buffer()? {
+                        self.state = WebSocketState::Closed;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for WebSocket<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.send_buffered()? {
+            return Err(ErrorKind::WouldBlock.into());
+        } else if buf.is_empty() || self.state == WebSocketState::Closed {
+            return Ok(buf.len());
+        }
+        encode_frame(OPCODE_TEXT, buf, &mut self.buf_out);
+        self.send_buffered()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.send_buffered()? {
+            self.stream.flush()
+        } else {
+            Err(ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn masked_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1u8, 2, 3, 4];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn handshake_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let request = "GET /chat HTTP/1.1\r\n\
+                       Host: server.example.com\r\n\
+                       Upgrade: websocket\r\n\
+                       Connection: keep-alive, Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13";
+        assert_eq!(
+            parse_upgrade_request(request),
+            Some("dGhlIHNhbXBsZSBub25jZQ==")
+        );
+        assert_eq!(parse_upgrade_request("GET / HTTP/1.1\r\nHost: a"), None);
+    }
+
+    #[test]
+    fn frames() {
+        let frame = masked_frame(OPCODE_TEXT, b"PING\n\n");
+        assert_eq!(parse_frame(&frame[..3]).unwrap(), None);
+        assert_eq!(
+            parse_frame(&frame).unwrap(),
+            Some((
+                Frame {
+                    opcode: OPCODE_TEXT,
+                    payload: b"PING\n\n".to_vec()
+                },
+                frame.len()
+            ))
+        );
+
+        let mut unmasked = frame.clone();
+        unmasked[1] &= 0x7F;
+        assert!(parse_frame(&unmasked).is_err());
+
+        let mut out = vec![];
+        encode_frame(OPCODE_TEXT, &[0; 300], &mut out);
+        assert_eq!(&out[..4], &[0x81, 126, 1, 44]);
+        assert_eq!(out.len(), 304);
+    }
+
+    #[test]
+    fn stream() {
+        let mut input = masked_frame(OPCODE_TEXT, b"NICK\n");
+        input.extend(masked_frame(OPCODE_PING, b"hi"));
+        input.extend(masked_frame(OPCODE_CONTINUATION, b"bob\n\n"));
+        input.extend(masked_frame(OPCODE_CLOSE, &[3, 232]));
+
+        let mut socket = WebSocket::new(Cursor::new(input));
+        socket.state = WebSocketState::Open;
+        let mut data = vec![];
+        socket.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"NICK\nbob\n\n");
+
+        let output = &socket.get_ref().get_ref()[..];
+        let mut pong = vec![];
+        encode_frame(OPCODE_PONG, b"hi", &mut pong);
+        encode_frame(OPCODE_CLOSE, &[3, 232], &mut pong);
+        assert!(output.ends_with(&pong));
+    }
+}
@@ -1,12 +1,15 @@
 use base64::encode;
 use mio;
-use std::iter::Iterator;
+use std::{iter::Iterator, time::Duration};
 
 pub const SERVER_VERSION: u32 = 3;
 pub const SERVER_TOKEN: mio::Token = mio::Token(1_000_000_000);
 pub const SECURE_SERVER_TOKEN: mio::Token = mio::Token(1_000_000_001);
 pub const TIMER_TOKEN: mio::Token = mio::Token(1_000_000_002);
 pub const IO_TOKEN: mio::Token = mio::Token(1_000_000_003);
+pub const FEDERATION_TOKEN: mio::Token = mio::Token(1_000_000_004);
+pub const WEBSOCKET_SERVER_TOKEN: mio::Token = mio::Token(1_000_000_005);
+pub const SECURE_WEBSOCKET_SERVER_TOKEN: mio::Token = mio::Token(1_000_000_006);
 
 pub fn is_name_illegal(name: &str) -> bool {
     name.len() > 40
@@ -72,3 +75,39 @@ pub fn protocol_version_string(protocol_number: u16) -> &'static str {
         _ => "Unknown",
     }
 }
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let units = [
+        (seconds / 86400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let parts: Vec<_> = units
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_format() {
+        let format = |seconds| format_duration(Duration::from_secs(seconds));
+        assert_eq!(format(0), "0s");
+        assert_eq!(format(59), "59s");
+        assert_eq!(format(60), "1m");
+        assert_eq!(format(3661), "1h 1m 1s");
+        assert_eq!(format(90000), "1d 1h");
+        assert_eq!(format_duration(Duration::from_millis(999)), "0s");
+    }
+}
@@ -3,7 +3,7 @@ use std::ops::RangeInclusive;
 use crate::{
     common::{GearData, GearDataProcessor, GearId},
     grid::Grid,
-    physics::PhysicsData,
+    physics::{PhysicsData, PositionUpdates},
 };
 
 use fpnum::*;
@@ -42,12 +42,23 @@ impl GearData for CollisionData {}
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct ContactData {
+    /// Fraction of the speed along the contact normal kept after a bounce
     pub elasticity: FPNum,
+    /// Fraction of the speed along the surface lost on a contact
     pub friction: FPNum,
 }
 
 impl GearData for ContactData {}
 
+impl Default for ContactData {
+    fn default() -> Self {
+        Self {
+            elasticity: fp!(0),
+            friction: fp!(0),
+        }
+    }
+}
+
 struct EnabledCollisionsCollection {
     gear_ids: Vec<GearId>,
     collisions: Vec<CollisionData>,
@@ -66,16 +77,183 @@ impl EnabledCollisionsCollection {
         self.collisions.push(collision);
     }
 
-    fn iter(&self) -> impl Iterator<Item = (GearId, &CollisionData)> {
-        self.gear_ids.iter().cloned().zip(self.collisions.iter())
+    fn find(&self, gear_id: GearId) -> Option<usize> {
+        self.gear_ids.iter().position(|id| *id == gear_id)
+    }
+}
+
+struct ContactsCollection {
+    gear_ids: Vec<GearId>,
+    contacts: Vec<ContactData>,
+}
+
+impl ContactsCollection {
+    fn new() -> Self {
+        Self {
+            gear_ids: Vec::new(),
+            contacts: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, gear_id: GearId, contact: ContactData) {
+        match self.gear_ids.iter().position(|id| *id == gear_id) {
+            Some(index) => self.contacts[index] = contact,
+            None => {
+                self.gear_ids.push(gear_id);
+                self.contacts.push(contact);
+            }
+        }
+    }
+
+    fn get(&self, gear_id: GearId) -> ContactData {
+        match self.gear_ids.iter().position(|id| *id == gear_id) {
+            Some(index) => self.contacts[index],
+            None => ContactData::default(),
+        }
     }
 }
 
 pub struct CollisionProcessor {
     grid: Grid,
     enabled_collisions: EnabledCollisionsCollection,
+    contacts: ContactsCollection,
 
     detected_collisions: DetectedCollisions,
+    land_contacts: LandContacts,
+}
+
+/// Gears pushed out of the land during the step with their corrected state
+pub struct LandContacts {
+    pub gear_ids: Vec<GearId>,
+    pub positions: Vec<FPPoint>,
+    pub velocities: Vec<FPPoint>,
+    /// Speed towards the land before the contact, zero for the gears resting or sliding on it
+    pub impact_speeds: Vec<FPNum>,
+}
+
+impl LandContacts {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            gear_ids: Vec::with_capacity(capacity),
+            positions: Vec::with_capacity(capacity),
+            velocities: Vec::with_capacity(capacity),
+            impact_speeds: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        gear_id: GearId,
+        position: FPPoint,
+        velocity: FPPoint,
+        impact_speed: FPNum,
+    ) {
+        self.gear_ids.push(gear_id);
+        self.positions.push(position);
+        self.velocities.push(velocity);
+        self.impact_speeds.push(impact_speed);
+    }
+
+    pub fn clear(&mut self) {
+        self.gear_ids.clear();
+        self.positions.clear();
+        self.velocities.clear();
+        self.impact_speeds.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GearId, &FPPoint, &FPPoint)> {
+        self.gear_ids
+            .iter()
+            .cloned()
+            .zip(self.positions.iter().zip(self.velocities.iter()))
+            .map(|(gear_id, (position, velocity))| (gear_id, position, velocity))
+    }
+}
+
+fn is_land(land: &Land2D<u32>, x: i32, y: i32) -> bool {
+    land.is_valid_coordinate(x, y) && land[y as usize][x as usize] != 0
+}
+
+/// Offsets of the land pixels inside the circle from its center
+fn land_offsets<'a>(
+    land: &'a Land2D<u32>,
+    bounds: &CircleBounds,
+) -> impl Iterator<Item = Point> + 'a {
+    let center = fppoint_round(&bounds.center);
+    let radius = bounds.radius.abs_round() as i32;
+    (-radius..=radius)
+        .flat_map(move |dy| (-radius..=radius).map(move |dx| Point::new(dx, dy)))
+        .filter(move |offset| {
+            offset.x * offset.x + offset.y * offset.y <= radius * radius
+                && is_land(land, center.x + offset.x, center.y + offset.y)
+        })
+}
+
+/// Unit vector pointing from the land pixels overlapped by the circle to its center
+fn land_normal(land: &Land2D<u32>, bounds: &CircleBounds, velocity: &FPPoint) -> Option<FPPoint> {
+    let mut count = 0;
+    let mut sum = Point::ZERO;
+    for offset in land_offsets(land, bounds) {
+        count += 1;
+        sum -= offset;
+    }
+
+    if count == 0 {
+        None
+    } else if sum == Point::ZERO {
+        // buried evenly, so get back the way the gear came in
+        if velocity.is_zero() {
+            Some(-FPPoint::unit_y())
+        } else {
+            Some(-*velocity / velocity.distance())
+        }
+    } else {
+        let normal = FPPoint::new(sum.x.into(), sum.y.into());
+        Some(normal / normal.distance())
+    }
+}
+
+/// Gears moving slower than this after landing on a floor come to rest
+fn rest_speed() -> FPNum {
+    fp!(1 / 50)
+}
+
+fn is_floor(normal: &FPPoint) -> bool {
+    normal.y().is_negative() && normal.y().abs() >= normal.x().abs()
+}
+
+/// Moves the gear out of the land along the normal
+/// and reflects the velocity according to its contact properties
+fn resolve_land_contact(
+    land: &Land2D<u32>,
+    bounds: &mut CircleBounds,
+    velocity: &mut FPPoint,
+    normal: FPPoint,
+    contact: &ContactData,
+) -> FPNum {
+    let max_steps = bounds.radius.abs_round() * 2 + 1;
+    for _ in 0..max_steps {
+        if land_offsets(land, bounds).next().is_none() {
+            break;
+        }
+        bounds.center += normal;
+    }
+
+    let normal_speed = velocity.dot(&normal);
+    if !normal_speed.is_negative() {
+        return fp!(0);
+    }
+
+    let normal_velocity = normal * normal_speed;
+    let tangent_velocity = *velocity - normal_velocity;
+    *velocity =
+        tangent_velocity * (fp!(1) - contact.friction) - normal_velocity * contact.elasticity;
+
+    if is_floor(&normal) && velocity.max_norm() < rest_speed() {
+        *velocity = FPPoint::zero();
+    }
+
+    -normal_speed
 }
 
 pub struct DetectedCollisions {
@@ -95,6 +273,11 @@ impl DetectedCollisions {
         self.pairs.push((contact_gear_id1, contact_gear_id2));
         self.positions.push(fppoint_round(&position));
     }
+
+    pub fn clear(&mut self) {
+        self.pairs.clear();
+        self.positions.clear();
+    }
 }
 
 impl CollisionProcessor {
@@ -102,28 +285,140 @@ impl CollisionProcessor {
         Self {
             grid: Grid::new(size),
             enabled_collisions: EnabledCollisionsCollection::new(),
+            contacts: ContactsCollection::new(),
             detected_collisions: DetectedCollisions::new(0),
+            land_contacts: LandContacts::new(0),
         }
     }
 
-    pub fn process(&mut self, land: &Land2D<u32>, updates: &crate::physics::PositionUpdates) {
-        self.grid.check_collisions(&mut self.detected_collisions);
+    pub fn process(&mut self, land: &Land2D<u32>, updates: &PositionUpdates) -> &LandContacts {
+        self.detected_collisions.clear();
+        self.land_contacts.clear();
+
+        for (gear_id, position, velocity) in updates.iter() {
+            if let Some(index) = self.enabled_collisions.find(gear_id) {
+                let bounds = &mut self.enabled_collisions.collisions[index].bounds;
+                bounds.center = *position;
 
-        for (gear_id, collision) in self.enabled_collisions.iter() {
-            if collision
-                .bounds
-                .rows()
-                .any(|(y, r)| (&land[y][r]).iter().any(|v| *v != 0))
-            {
-                self.detected_collisions
-                    .push(gear_id, 0, &collision.bounds.center)
+                let is_resting = match land_normal(land, bounds, velocity) {
+                    Some(normal) => {
+                        let mut velocity = *velocity;
+                        let impact_speed = resolve_land_contact(
+                            land,
+                            bounds,
+                            &mut velocity,
+                            normal,
+                            &self.contacts.get(gear_id),
+                        );
+                        self.land_contacts
+                            .push(gear_id, bounds.center, velocity, impact_speed);
+                        velocity.is_zero()
+                    }
+                    None => false,
+                };
+
+                self.grid.update_position(gear_id, &bounds.center);
+                if is_resting {
+                    self.grid.settle(gear_id);
+                }
             }
         }
+
+        self.grid.check_collisions(&mut self.detected_collisions);
+
+        &self.land_contacts
+    }
+
+    /// Pairs of the intersecting gears found during the last step
+    pub fn detected_collisions(&self) -> &DetectedCollisions {
+        &self.detected_collisions
     }
 }
 
 impl GearDataProcessor<CollisionData> for CollisionProcessor {
     fn add(&mut self, gear_id: GearId, gear_data: CollisionData) {
         self.grid.insert_static(gear_id, &gear_data.bounds);
+        self.enabled_collisions.push(gear_id, gear_data);
+    }
+}
+
+impl GearDataProcessor<ContactData> for CollisionProcessor {
+    fn add(&mut self, gear_id: GearId, gear_data: ContactData) {
+        self.contacts.insert(gear_id, gear_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor_land() -> Land2D<u32> {
+        let mut land = Land2D::new(Size::new(256, 256), 0);
+        for y in 100..256 {
+            for x in 0..256 {
+                land.map(y, x, |pixel| *pixel = 1);
+            }
+        }
+        land
+    }
+
+    fn process_single(
+        land: &Land2D<u32>,
+        center: FPPoint,
+        velocity: FPPoint,
+    ) -> (FPPoint, FPPoint, FPNum) {
+        let mut processor = CollisionProcessor::new(Size::new(256, 256));
+        processor.add(
+            1,
+            CollisionData {
+                bounds: CircleBounds {
+                    center,
+                    radius: fp!(10),
+                },
+            },
+        );
+        processor.add(
+            1,
+            ContactData {
+                elasticity: fp!(1 / 2),
+                friction: fp!(1 / 4),
+            },
+        );
+
+        let mut updates = PositionUpdates::new(1);
+        updates.push(1, &center, &velocity);
+        let contacts = processor.process(land, &updates);
+        assert_eq!(contacts.gear_ids, vec![1]);
+        (
+            contacts.positions[0],
+            contacts.velocities[0],
+            contacts.impact_speeds[0],
+        )
+    }
+
+    #[test]
+    fn land_bounce() {
+        let land = floor_land();
+        let (position, velocity, impact_speed) = process_single(
+            &land,
+            FPPoint::new(fp!(128), fp!(95)),
+            FPPoint::new(fp!(1), fp!(2)),
+        );
+        assert_eq!(position, FPPoint::new(fp!(128), fp!(89)));
+        assert_eq!(velocity, FPPoint::new(fp!(3 / 4), fp!(-1)));
+        assert_eq!(impact_speed, fp!(2));
+    }
+
+    #[test]
+    fn land_rest() {
+        let land = floor_land();
+        let (position, velocity, impact_speed) = process_single(
+            &land,
+            FPPoint::new(fp!(128), fp!(91)),
+            FPPoint::new(fp!(0), fp!(1 / 100)),
+        );
+        assert_eq!(position, FPPoint::new(fp!(128), fp!(89)));
+        assert!(velocity.is_zero());
+        assert_eq!(impact_speed, fp!(1 / 100));
     }
 }
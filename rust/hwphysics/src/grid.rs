@@ -5,30 +5,74 @@ use crate::{
 
 use fpnum::FPPoint;
 use integral_geometry::{GridIndex, Point, Size};
+use std::collections::HashMap;
 
-struct GridBin {
+struct GridEntries {
     refs: Vec<GearId>,
-    static_entries: Vec<CircleBounds>,
-    dynamic_entries: Vec<CircleBounds>,
+    bounds: Vec<CircleBounds>,
 }
 
-impl GridBin {
+impl GridEntries {
     fn new() -> Self {
         Self {
             refs: vec![],
-            static_entries: vec![],
-            dynamic_entries: vec![],
+            bounds: vec![],
+        }
+    }
+
+    fn push(&mut self, gear_id: GearId, bounds: CircleBounds) {
+        self.refs.push(gear_id);
+        self.bounds.push(bounds);
+    }
+
+    fn find(&self, gear_id: GearId) -> Option<usize> {
+        self.refs.iter().position(|id| *id == gear_id)
+    }
+
+    fn remove(&mut self, gear_id: GearId) -> Option<CircleBounds> {
+        let index = self.find(gear_id)?;
+        self.refs.swap_remove(index);
+        Some(self.bounds.swap_remove(index))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (GearId, &CircleBounds)> {
+        self.refs.iter().cloned().zip(self.bounds.iter())
+    }
+}
+
+struct GridBin {
+    static_entries: GridEntries,
+    dynamic_entries: GridEntries,
+}
+
+impl GridBin {
+    fn new() -> Self {
+        Self {
+            static_entries: GridEntries::new(),
+            dynamic_entries: GridEntries::new(),
         }
     }
 }
 
 const GRID_BIN_SIZE: usize = 128;
 
+/// Neighbours following a bin, so that each pair of neighbouring bins is visited once
+const FORWARD_NEIGHBOURS: [Point; 4] = [
+    Point::new(1, 0),
+    Point::new(-1, 1),
+    Point::new(0, 1),
+    Point::new(1, 1),
+];
+
+/// Broad phase of the collision detection. Gears can only collide within the same
+/// or neighbouring bins, so the sum of the radii must not exceed the bin size
 pub struct Grid {
     bins: Vec<GridBin>,
     space_size: Size,
     bins_count: Size,
     index: GridIndex,
+    /// Bin of every gear in the grid
+    locations: HashMap<GearId, usize>,
 }
 
 impl Grid {
@@ -41,43 +85,177 @@ impl Grid {
             space_size: size,
             bins_count,
             index: Size::square(GRID_BIN_SIZE).to_grid_index(),
+            locations: HashMap::new(),
         }
     }
 
+    /// Gears outside of the space are kept in the edge bins
     fn bin_index(&self, position: &FPPoint) -> Point {
-        self.index.map(fppoint_round(position))
+        let index = self.index.map(fppoint_round(position));
+        Point::new(
+            index.x.max(0).min(self.bins_count.width as i32 - 1),
+            index.y.max(0).min(self.bins_count.height as i32 - 1),
+        )
     }
 
-    fn lookup_bin(&mut self, position: &FPPoint) -> &mut GridBin {
+    fn linear_bin_index(&self, position: &FPPoint) -> usize {
         let index = self.bin_index(position);
-        &mut self.bins[index.x as usize * self.bins_count.width + index.y as usize]
+        self.bins_count
+            .linear_index(index.x as usize, index.y as usize)
+    }
+
+    fn neighbour(&self, bin_index: usize, offset: Point) -> Option<&GridBin> {
+        let x = (bin_index % self.bins_count.width) as i32 + offset.x;
+        let y = (bin_index / self.bins_count.width) as i32 + offset.y;
+        if x >= 0
+            && y >= 0
+            && (x as usize) < self.bins_count.width
+            && (y as usize) < self.bins_count.height
+        {
+            Some(&self.bins[self.bins_count.linear_index(x as usize, y as usize)])
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, gear_id: GearId) -> Option<CircleBounds> {
+        let bin = &mut self.bins[self.locations.remove(&gear_id)?];
+        bin.dynamic_entries
+            .remove(gear_id)
+            .or_else(|| bin.static_entries.remove(gear_id))
     }
 
     pub fn insert_static(&mut self, gear_id: GearId, bounds: &CircleBounds) {
-        self.lookup_bin(&bounds.center).static_entries.push(*bounds)
+        self.remove(gear_id);
+        let index = self.linear_bin_index(&bounds.center);
+        self.bins[index].static_entries.push(gear_id, *bounds);
+        self.locations.insert(gear_id, index);
     }
 
     pub fn insert_dynamic(&mut self, gear_id: GearId, bounds: &CircleBounds) {
-        self.lookup_bin(&bounds.center)
-            .dynamic_entries
-            .push(*bounds)
+        self.remove(gear_id);
+        let index = self.linear_bin_index(&bounds.center);
+        self.bins[index].dynamic_entries.push(gear_id, *bounds);
+        self.locations.insert(gear_id, index);
+    }
+
+    /// Moves the gear to its new position, making it dynamic if it was static
+    pub fn update_position(&mut self, gear_id: GearId, position: &FPPoint) {
+        let current_index = match self.locations.get(&gear_id) {
+            Some(index) => *index,
+            None => return,
+        };
+        let new_index = self.linear_bin_index(position);
+
+        let entries = &mut self.bins[current_index].dynamic_entries;
+        match entries.find(gear_id) {
+            Some(entry) if new_index == current_index => {
+                entries.bounds[entry].center = *position;
+            }
+            _ => {
+                if let Some(mut bounds) = self.remove(gear_id) {
+                    bounds.center = *position;
+                    self.insert_dynamic(gear_id, &bounds);
+                }
+            }
+        }
+    }
+
+    /// Makes the gear static once it stops moving
+    pub fn settle(&mut self, gear_id: GearId) {
+        if let Some(index) = self.locations.get(&gear_id) {
+            if let Some(bounds) = self.bins[*index].dynamic_entries.remove(gear_id) {
+                self.bins[*index].static_entries.push(gear_id, bounds);
+            }
+        }
     }
 
+    /// Reports each pair of intersecting gears once, at least one of the gears is dynamic
     pub fn check_collisions(&self, collisions: &mut DetectedCollisions) {
-        for bin in &self.bins {
-            for bounds in &bin.dynamic_entries {
-                for other in &bin.dynamic_entries {
-                    if bounds.intersects(other) && bounds != other {
-                        collisions.push(0, 0, &bounds.center)
+        for (bin_index, bin) in self.bins.iter().enumerate() {
+            let entries = &bin.dynamic_entries;
+            for (i, (gear_id, bounds)) in entries.iter().enumerate() {
+                for (other_id, other) in entries.iter().skip(i + 1) {
+                    if bounds.intersects(other) {
+                        collisions.push(gear_id, other_id, &bounds.center)
                     }
                 }
 
-                for other in &bin.static_entries {
-                    if bounds.intersects(other) {
-                        collisions.push(0, 0, &bounds.center)
+                for offset in &FORWARD_NEIGHBOURS {
+                    if let Some(neighbour) = self.neighbour(bin_index, *offset) {
+                        for (other_id, other) in neighbour.dynamic_entries.iter() {
+                            if bounds.intersects(other) {
+                                collisions.push(gear_id, other_id, &bounds.center)
+                            }
+                        }
+                    }
+                }
+
+                for y in -1..=1 {
+                    for x in -1..=1 {
+                        if let Some(neighbour) = self.neighbour(bin_index, Point::new(x, y)) {
+                            for (other_id, other) in neighbour.static_entries.iter() {
+                                if bounds.intersects(other) {
+                                    collisions.push(gear_id, other_id, &bounds.center)
+                                }
+                            }
+                        }
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fpnum::{fp, FPNum};
+
+    fn bounds(x: i32, y: i32) -> CircleBounds {
+        CircleBounds {
+            center: FPPoint::new(FPNum::from(x), FPNum::from(y)),
+            radius: fp!(10),
+        }
+    }
+
+    fn collisions(grid: &Grid) -> Vec<(GearId, GearId)> {
+        let mut collisions = DetectedCollisions::new(0);
+        grid.check_collisions(&mut collisions);
+        collisions.pairs
+    }
+
+    #[test]
+    fn bins() {
+        let grid = Grid::new(Size::new(1024, 512));
+        let position = FPPoint::new(fp!(300), fp!(10));
+        assert_eq!(grid.bin_index(&position), Point::new(2, 0));
+        assert_eq!(grid.linear_bin_index(&position), 2);
+        let position = FPPoint::new(fp!(10), fp!(300));
+        assert_eq!(grid.linear_bin_index(&position), 16);
+        let position = FPPoint::new(fp!(-10), fp!(5000));
+        assert_eq!(grid.bin_index(&position), Point::new(0, 3));
+    }
+
+    #[test]
+    fn pairs() {
+        let mut grid = Grid::new(Size::new(1024, 1024));
+        // both gears straddle the edge between the bins
+        grid.insert_dynamic(1, &bounds(125, 50));
+        grid.insert_dynamic(2, &bounds(132, 50));
+        grid.insert_static(3, &bounds(128, 60));
+        grid.insert_static(4, &bounds(500, 500));
+        assert_eq!(collisions(&grid), vec![(1, 2), (1, 3), (2, 3)]);
+
+        grid.update_position(2, &FPPoint::new(fp!(300), fp!(50)));
+        assert_eq!(collisions(&grid), vec![(1, 3)]);
+
+        // a static gear becomes dynamic once it moves
+        grid.update_position(4, &FPPoint::new(fp!(300), fp!(55)));
+        assert_eq!(collisions(&grid), vec![(1, 3), (2, 4)]);
+
+        grid.settle(1);
+        grid.settle(4);
+        assert_eq!(collisions(&grid), vec![(2, 4)]);
+    }
+}
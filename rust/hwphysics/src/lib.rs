@@ -3,12 +3,12 @@ pub mod common;
 mod grid;
 pub mod physics;
 
-use fpnum::FPNum;
+use fpnum::{FPNum, FPPoint};
 use integral_geometry::Size;
 use land2d::Land2D;
 
 use crate::{
-    collision::{CollisionData, CollisionProcessor, ContactData},
+    collision::{CollisionData, CollisionProcessor, ContactData, LandContacts},
     common::{GearData, GearDataAggregator, GearDataProcessor, GearId},
     physics::{PhysicsData, PhysicsProcessor},
 };
@@ -37,6 +37,7 @@ macro_rules! processor_map {
 
 processor_map!(PhysicsData => physics);
 processor_map!(CollisionData => collision);
+processor_map!(ContactData => collision);
 
 impl World {
     pub fn new(world_size: Size) -> Self {
@@ -46,9 +47,27 @@ impl World {
         }
     }
 
-    pub fn step(&mut self, time_step: FPNum, land: &Land2D<u32>) {
+    pub fn set_gravity(&mut self, gravity: FPNum) {
+        self.physics.set_gravity(gravity);
+    }
+
+    /// Sets the wind for the turn
+    pub fn set_wind(&mut self, wind: FPNum) {
+        self.physics.set_wind(wind);
+    }
+
+    pub fn apply_impulse(&mut self, gear_id: GearId, impulse: FPPoint) {
+        self.physics.apply_impulse(gear_id, impulse);
+    }
+
+    /// Moves the gears and returns the ones that hit the land
+    pub fn step(&mut self, time_step: FPNum, land: &Land2D<u32>) -> &LandContacts {
         let updates = self.physics.process(time_step);
-        self.collision.process(land, &updates);
+        let contacts = self.collision.process(land, updates);
+        for (gear_id, position, velocity) in contacts.iter() {
+            self.physics.correct(gear_id, *position, *velocity);
+        }
+        contacts
     }
 
     pub fn add_gear_data<T>(&mut self, gear_id: GearId, data: T)
@@ -63,7 +82,7 @@ impl World {
 #[cfg(test)]
 mod tests {
     use crate::{
-        collision::{CircleBounds, CollisionData},
+        collision::{CircleBounds, CollisionData, ContactData},
         physics::PhysicsData,
         World,
     };
@@ -80,10 +99,7 @@ mod tests {
 
         world.add_gear_data(
             gear_id,
-            PhysicsData {
-                position: FPPoint::zero(),
-                velocity: FPPoint::unit_y(),
-            },
+            PhysicsData::new(FPPoint::zero(), FPPoint::unit_y()),
         );
 
         world.add_gear_data(
@@ -100,4 +116,49 @@ mod tests {
 
         world.step(fp!(1), &land);
     }
+
+    #[test]
+    fn land_contact() {
+        let world_size = Size::new(256, 256);
+        let mut world = World::new(world_size);
+        world.set_gravity(fp!(1 / 128));
+        let gear_id = 1;
+        let position = FPPoint::new(fp!(128), fp!(50));
+
+        world.add_gear_data(gear_id, PhysicsData::new(position, FPPoint::unit_y()));
+        world.add_gear_data(
+            gear_id,
+            CollisionData {
+                bounds: CircleBounds {
+                    center: position,
+                    radius: fp!(10),
+                },
+            },
+        );
+        world.add_gear_data(
+            gear_id,
+            ContactData {
+                elasticity: fp!(1 / 2),
+                friction: fp!(0),
+            },
+        );
+
+        let mut land = Land2D::new(world_size, 0);
+        for y in 100..256 {
+            for x in 0..256 {
+                land.map(y, x, |pixel| *pixel = 1);
+            }
+        }
+
+        let mut impacts = vec![];
+        for _ in 0..1000 {
+            let contacts = world.step(fp!(1), &land);
+            impacts.extend(contacts.impact_speeds.iter().cloned());
+        }
+
+        // the gear bounces a few times and then stays on the ground
+        assert!(impacts.len() > 1);
+        assert!(impacts[0] > impacts[1]);
+        assert!(world.step(fp!(1), &land).gear_ids.is_empty());
+    }
 }
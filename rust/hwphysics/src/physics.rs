@@ -2,17 +2,45 @@ use crate::common::{GearData, GearDataProcessor, GearId};
 use fpnum::*;
 use integral_geometry::{GridIndex, Point, Size};
 
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct PhysicsProperties {
+    /// Impulses and the wind accelerate heavier gears less
+    pub mass: FPNum,
+    /// Fraction of the velocity lost per time unit
+    pub drag: FPNum,
+    pub is_wind_sensitive: bool,
+}
+
+impl Default for PhysicsProperties {
+    fn default() -> Self {
+        Self {
+            mass: fp!(1),
+            drag: fp!(0),
+            is_wind_sensitive: false,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct PhysicsData {
     pub position: FPPoint,
     pub velocity: FPPoint,
+    pub properties: PhysicsProperties,
 }
 
 impl GearData for PhysicsData {}
 
 impl PhysicsData {
     pub fn new(position: FPPoint, velocity: FPPoint) -> Self {
-        Self { position, velocity }
+        Self {
+            position,
+            velocity,
+            properties: PhysicsProperties::default(),
+        }
+    }
+
+    pub fn with_properties(self, properties: PhysicsProperties) -> Self {
+        Self { properties, ..self }
     }
 }
 
@@ -20,6 +48,7 @@ pub struct DynamicPhysicsCollection {
     gear_ids: Vec<GearId>,
     positions: Vec<FPPoint>,
     velocities: Vec<FPPoint>,
+    properties: Vec<PhysicsProperties>,
 }
 
 impl DynamicPhysicsCollection {
@@ -28,6 +57,7 @@ impl DynamicPhysicsCollection {
             gear_ids: Vec::new(),
             positions: Vec::new(),
             velocities: Vec::new(),
+            properties: Vec::new(),
         }
     }
 
@@ -39,19 +69,38 @@ impl DynamicPhysicsCollection {
         self.gear_ids.push(id);
         self.positions.push(physics.position);
         self.velocities.push(physics.velocity);
+        self.properties.push(physics.properties);
     }
 
-    fn iter_pos_update(&mut self) -> impl Iterator<Item = (GearId, (&mut FPPoint, &FPPoint))> {
-        self.gear_ids
-            .iter()
-            .cloned()
-            .zip(self.positions.iter_mut().zip(self.velocities.iter()))
+    fn find(&self, gear_id: GearId) -> Option<usize> {
+        self.gear_ids.iter().position(|id| *id == gear_id)
+    }
+
+    fn remove(&mut self, index: usize) -> PhysicsData {
+        self.gear_ids.swap_remove(index);
+        PhysicsData {
+            position: self.positions.swap_remove(index),
+            velocity: self.velocities.swap_remove(index),
+            properties: self.properties.swap_remove(index),
+        }
+    }
+
+    fn iter_pos_update(
+        &mut self,
+    ) -> impl Iterator<Item = (GearId, (&mut FPPoint, &mut FPPoint, &PhysicsProperties))> {
+        self.gear_ids.iter().cloned().zip(
+            self.positions
+                .iter_mut()
+                .zip(self.velocities.iter_mut().zip(self.properties.iter()))
+                .map(|(pos, (vel, properties))| (pos, vel, properties)),
+        )
     }
 }
 
 pub struct StaticPhysicsCollection {
     gear_ids: Vec<GearId>,
     positions: Vec<FPPoint>,
+    properties: Vec<PhysicsProperties>,
 }
 
 impl StaticPhysicsCollection {
@@ -59,12 +108,27 @@ impl StaticPhysicsCollection {
         Self {
             gear_ids: Vec::new(),
             positions: Vec::new(),
+            properties: Vec::new(),
         }
     }
 
     fn push(&mut self, gear_id: GearId, physics: PhysicsData) {
         self.gear_ids.push(gear_id);
         self.positions.push(physics.position);
+        self.properties.push(physics.properties);
+    }
+
+    fn find(&self, gear_id: GearId) -> Option<usize> {
+        self.gear_ids.iter().position(|id| *id == gear_id)
+    }
+
+    fn remove(&mut self, index: usize) -> PhysicsData {
+        self.gear_ids.swap_remove(index);
+        PhysicsData {
+            position: self.positions.swap_remove(index),
+            velocity: FPPoint::zero(),
+            properties: self.properties.swap_remove(index),
+        }
     }
 }
 
@@ -72,6 +136,11 @@ pub struct PhysicsProcessor {
     dynamic_physics: DynamicPhysicsCollection,
     static_physics: StaticPhysicsCollection,
 
+    /// Downward acceleration applied to every moving gear
+    gravity: FPNum,
+    /// Horizontal force applied to the wind sensitive gears, changes every turn
+    wind: FPNum,
+
     physics_cleanup: Vec<GearId>,
     position_updates: PositionUpdates,
 }
@@ -79,6 +148,7 @@ pub struct PhysicsProcessor {
 pub struct PositionUpdates {
     pub gear_ids: Vec<GearId>,
     pub positions: Vec<FPPoint>,
+    pub velocities: Vec<FPPoint>,
 }
 
 impl PositionUpdates {
@@ -86,12 +156,28 @@ impl PositionUpdates {
         Self {
             gear_ids: Vec::with_capacity(capacity),
             positions: Vec::with_capacity(capacity),
+            velocities: Vec::with_capacity(capacity),
         }
     }
 
-    pub fn push(&mut self, gear_id: GearId, position: &FPPoint) {
+    pub fn push(&mut self, gear_id: GearId, position: &FPPoint, velocity: &FPPoint) {
         self.gear_ids.push(gear_id);
         self.positions.push(*position);
+        self.velocities.push(*velocity);
+    }
+
+    pub fn clear(&mut self) {
+        self.gear_ids.clear();
+        self.positions.clear();
+        self.velocities.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GearId, &FPPoint, &FPPoint)> {
+        self.gear_ids
+            .iter()
+            .cloned()
+            .zip(self.positions.iter().zip(self.velocities.iter()))
+            .map(|(gear_id, (position, velocity))| (gear_id, position, velocity))
     }
 }
 
@@ -100,23 +186,85 @@ impl PhysicsProcessor {
         PhysicsProcessor {
             dynamic_physics: DynamicPhysicsCollection::new(),
             static_physics: StaticPhysicsCollection::new(),
+            gravity: fp!(1 / 5000),
+            wind: fp!(0),
             physics_cleanup: Vec::new(),
             position_updates: PositionUpdates::new(0),
         }
     }
 
+    pub fn set_gravity(&mut self, gravity: FPNum) {
+        self.gravity = gravity;
+    }
+
+    pub fn set_wind(&mut self, wind: FPNum) {
+        self.wind = wind;
+    }
+
     pub fn process(&mut self, time_step: FPNum) -> &PositionUpdates {
-        for (gear_id, (pos, vel)) in self.dynamic_physics.iter_pos_update() {
+        self.position_updates.clear();
+        self.physics_cleanup.clear();
+
+        let gravity = FPPoint::new(fp!(0), self.gravity);
+        let wind = self.wind;
+
+        for (gear_id, (pos, vel, properties)) in self.dynamic_physics.iter_pos_update() {
+            let mut acceleration = gravity;
+            if properties.is_wind_sensitive {
+                acceleration += FPPoint::new(wind / properties.mass, fp!(0));
+            }
+
+            *vel += acceleration * time_step;
+            if !properties.drag.is_zero() {
+                *vel -= *vel * properties.drag * time_step;
+            }
             *pos += *vel * time_step;
+
             if !vel.is_zero() {
-                self.position_updates.push(gear_id, pos)
-            } else {
+                self.position_updates.push(gear_id, pos, vel)
+            } else if acceleration.is_zero() {
                 self.physics_cleanup.push(gear_id)
             }
         }
+
+        for index in 0..self.physics_cleanup.len() {
+            self.put_to_rest(self.physics_cleanup[index]);
+        }
+
         &self.position_updates
     }
 
+    /// Stops the gear and moves it to the static gears, which are not processed until woken
+    pub fn put_to_rest(&mut self, gear_id: GearId) {
+        if let Some(index) = self.dynamic_physics.find(gear_id) {
+            let physics = self.dynamic_physics.remove(index);
+            self.static_physics.push(gear_id, physics);
+        }
+    }
+
+    /// Overrides the state of the moving gear after a collision, a stopped gear is put to rest
+    pub fn correct(&mut self, gear_id: GearId, position: FPPoint, velocity: FPPoint) {
+        if let Some(index) = self.dynamic_physics.find(gear_id) {
+            self.dynamic_physics.positions[index] = position;
+            self.dynamic_physics.velocities[index] = velocity;
+            if velocity.is_zero() {
+                self.put_to_rest(gear_id);
+            }
+        }
+    }
+
+    /// Changes the velocity of the gear according to its mass, waking it if it's at rest
+    pub fn apply_impulse(&mut self, gear_id: GearId, impulse: FPPoint) {
+        if let Some(index) = self.dynamic_physics.find(gear_id) {
+            let mass = self.dynamic_physics.properties[index].mass;
+            self.dynamic_physics.velocities[index] += impulse / mass;
+        } else if let Some(index) = self.static_physics.find(gear_id) {
+            let mut physics = self.static_physics.remove(index);
+            physics.velocity = impulse / physics.properties.mass;
+            self.dynamic_physics.push(gear_id, physics);
+        }
+    }
+
     pub fn push(&mut self, gear_id: GearId, physics_data: PhysicsData) {
         if physics_data.velocity.is_zero() {
             self.static_physics.push(gear_id, physics_data);
@@ -135,3 +283,63 @@ impl GearDataProcessor<PhysicsData> for PhysicsProcessor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn processor() -> PhysicsProcessor {
+        let mut processor = PhysicsProcessor::new();
+        processor.set_gravity(fp!(1 / 8));
+        processor.set_wind(fp!(1 / 2));
+        processor
+    }
+
+    #[test]
+    fn forces() {
+        let mut processor = processor();
+        processor.push(1, PhysicsData::new(FPPoint::zero(), FPPoint::unit_x()));
+        processor.push(
+            2,
+            PhysicsData::new(FPPoint::zero(), FPPoint::unit_x()).with_properties(
+                PhysicsProperties {
+                    mass: fp!(2),
+                    drag: fp!(0),
+                    is_wind_sensitive: true,
+                },
+            ),
+        );
+
+        let updates = processor.process(fp!(1));
+        assert_eq!(updates.gear_ids, vec![1, 2]);
+        assert_eq!(updates.positions[0], FPPoint::new(fp!(1), fp!(1 / 8)));
+        assert_eq!(updates.positions[1], FPPoint::new(fp!(5 / 4), fp!(1 / 8)));
+
+        let updates = processor.process(fp!(1));
+        assert_eq!(updates.gear_ids.len(), 2);
+        assert_eq!(updates.positions[0], FPPoint::new(fp!(2), fp!(3 / 8)));
+    }
+
+    #[test]
+    fn rest_and_wake() {
+        let mut processor = processor();
+        processor.push(1, PhysicsData::new(FPPoint::zero(), FPPoint::unit_y()));
+        processor.put_to_rest(1);
+        assert_eq!(processor.dynamic_physics.len(), 0);
+        assert!(processor.process(fp!(1)).gear_ids.is_empty());
+
+        processor.apply_impulse(1, FPPoint::unit_x() * fp!(2));
+        assert_eq!(processor.dynamic_physics.len(), 1);
+        assert_eq!(
+            processor.dynamic_physics.velocities[0],
+            FPPoint::unit_x() * fp!(2)
+        );
+
+        // without any forces a stopped gear comes to rest on its own
+        processor.set_gravity(fp!(0));
+        processor.apply_impulse(1, FPPoint::unit_x() * fp!(-2));
+        assert!(processor.process(fp!(1)).gear_ids.is_empty());
+        assert_eq!(processor.dynamic_physics.len(), 0);
+        assert_eq!(processor.static_physics.positions[0], FPPoint::zero());
+    }
+}